@@ -0,0 +1,395 @@
+//! Expression tree produced by the parser and its pretty-printer
+//!
+//! `Display` prints the fewest parentheses needed to keep the tree's shape, and the
+//! printed form parses back to an equal tree for every tree `parse_expression` builds.
+
+use crate::Op;
+use crate::lexer::Span;
+use std::fmt;
+
+/// Parsed expression tree node with the span of input it was parsed from
+///
+/// Equality compares only the tree's shape and values, never the spans, so a
+/// reprinted and reparsed tree compares equal to the original.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+/// Number literal: its value and the text it was written as
+///
+/// Exact number modes read the text, so `0.1` stays one tenth rather than the
+/// nearest binary fraction. An `i` suffix (`2i`) makes the literal imaginary,
+/// with `value` as its imaginary part. Literals compare by value.
+#[derive(Debug, Clone)]
+pub struct Literal {
+    pub value: f64,
+    pub text: String,
+    pub imaginary: bool,
+}
+
+impl From<f64> for Literal {
+    fn from(value: f64) -> Literal {
+        Literal {
+            value,
+            text: value.to_string(),
+            imaginary: false,
+        }
+    }
+}
+
+impl Literal {
+    /// Radix of a literal written as `0x...`, `0o...` or `0b...`
+    pub fn radix_of(text: &str) -> Option<u32> {
+        match text.get(..2)? {
+            "0x" => Some(16),
+            "0o" => Some(8),
+            "0b" => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Radix the literal was written in, if not decimal
+    pub fn radix(&self) -> Option<u32> {
+        Literal::radix_of(&self.text)
+    }
+
+    /// Exact value of a literal written in another radix, which the lexer
+    /// only accepts when it fits a `u128`
+    pub fn radix_value(&self) -> Option<u128> {
+        let digits: String = self.text.get(2..)?.chars().filter(|&c| c != '_').collect();
+        u128::from_str_radix(&digits, self.radix()?).ok()
+    }
+}
+
+impl PartialEq for Literal {
+    fn eq(&self, other: &Literal) -> bool {
+        self.value == other.value && self.imaginary == other.imaginary
+    }
+}
+
+/// Shape of an expression node
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(Literal),
+    /// Variable looked up in the `Environment`
+    Var(String),
+    /// Sign or `~` applied to an operand; only `Op::Add`, `Op::Sub` and
+    /// `Op::BitNot` are meaningful here
+    Unary(Op, Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    /// Function call `name(args...)`
+    Call(String, Vec<Expr>),
+    /// Custom operator from the `Registry`, by symbol, with one operand for a
+    /// prefix operator and two for an infix one
+    Operator(String, Vec<Expr>),
+    /// Postfix factorial `n!`, binding tighter than any operator
+    Factorial(Box<Expr>),
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Expr) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Expr {
+    /// Node with an empty span, for trees built in code rather than parsed
+    pub fn new(kind: ExprKind) -> Expr {
+        Expr {
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    pub fn number(n: f64) -> Expr {
+        Expr::new(ExprKind::Number(Literal::from(n)))
+    }
+
+    pub fn var(name: impl Into<String>) -> Expr {
+        Expr::new(ExprKind::Var(name.into()))
+    }
+
+    pub fn call(name: impl Into<String>, args: Vec<Expr>) -> Expr {
+        Expr::new(ExprKind::Call(name.into(), args))
+    }
+
+    pub fn unary(op: Op, operand: Expr) -> Expr {
+        Expr::new(ExprKind::Unary(op, Box::new(operand)))
+    }
+
+    pub fn binary(op: Op, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::new(ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)))
+    }
+
+    pub fn factorial(operand: Expr) -> Expr {
+        Expr::new(ExprKind::Factorial(Box::new(operand)))
+    }
+
+    /// The same node pointing at `span`
+    pub fn with_span(mut self, span: Span) -> Expr {
+        self.span = span;
+        self
+    }
+}
+
+impl Op {
+    /// Symbol used when printing the operator
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::IntDiv => "//",
+            Op::Rem => "%",
+            Op::Pow => "^",
+            Op::BitAnd => "&",
+            Op::BitOr => "|",
+            Op::BitXor => "^^",
+            Op::Shl => "<<",
+            Op::Shr => ">>",
+            Op::BitNot => "~",
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// Which side of its parent a sub-expression sits on
+#[derive(Clone, Copy, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// Whether `child` needs parentheses as the `side` operand of binary `parent`
+fn needs_parens(parent: Op, child: &Expr, side: Side) -> bool {
+    match &child.kind {
+        ExprKind::Number(_) | ExprKind::Var(_) | ExprKind::Call(..) | ExprKind::Factorial(_) => {
+            false
+        }
+        // A sign swallows a following `^`, so `(-2) ^ 2` keeps its parentheses
+        ExprKind::Unary(..) => parent == Op::Pow && side == Side::Left,
+        // Its precedence lives in the registry, so always group a custom operator
+        ExprKind::Operator(..) => true,
+        ExprKind::Binary(op, ..) => {
+            let (child_prec, parent_prec) = (op.precedence(), parent.precedence());
+            child_prec < parent_prec
+                || (child_prec == parent_prec && (side == Side::Left) == parent.is_right_assoc())
+        }
+    }
+}
+
+/// Write `expr`, wrapped in parentheses when `wrap` is set
+fn write_operand(f: &mut fmt::Formatter<'_>, expr: &Expr, wrap: bool) -> fmt::Result {
+    if wrap {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            // The lexer never produces a negative literal, so keep one grouped
+            ExprKind::Number(n) if n.value.is_sign_negative() => write!(f, "({})", n.text),
+            ExprKind::Number(n) => f.write_str(&n.text),
+            ExprKind::Var(name) => f.write_str(name),
+            ExprKind::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                f.write_str(")")
+            }
+            ExprKind::Unary(op, operand) => {
+                write!(f, "{}", op)?;
+                // Only a power binds tighter than the sign
+                let wrap = match operand.kind {
+                    ExprKind::Binary(inner, ..) => inner != Op::Pow,
+                    ExprKind::Operator(..) => true,
+                    _ => false,
+                };
+                write_operand(f, operand, wrap)
+            }
+            ExprKind::Operator(symbol, operands) => {
+                let atomic = |e: &Expr| {
+                    matches!(
+                        e.kind,
+                        ExprKind::Number(_)
+                            | ExprKind::Var(_)
+                            | ExprKind::Call(..)
+                            | ExprKind::Factorial(_)
+                    )
+                };
+                match operands.as_slice() {
+                    [operand] => {
+                        f.write_str(symbol)?;
+                        // A word operator needs a space before its operand
+                        if symbol.ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+                            f.write_str(" ")?;
+                        }
+                        write_operand(f, operand, !atomic(operand))
+                    }
+                    [lhs, rhs] => {
+                        write_operand(f, lhs, !atomic(lhs))?;
+                        write!(f, " {} ", symbol)?;
+                        write_operand(f, rhs, !atomic(rhs))
+                    }
+                    _ => unreachable!("operator {} with {} operands", symbol, operands.len()),
+                }
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                write_operand(f, lhs, needs_parens(*op, lhs, Side::Left))?;
+                write!(f, " {} ", op)?;
+                write_operand(f, rhs, needs_parens(*op, rhs, Side::Right))
+            }
+            ExprKind::Factorial(operand) => {
+                let wrap = matches!(
+                    operand.kind,
+                    ExprKind::Unary(..) | ExprKind::Binary(..) | ExprKind::Operator(..)
+                );
+                write_operand(f, operand, wrap)?;
+                f.write_str("!")
+            }
+        }
+    }
+}
+
+/// One line of calculator input
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// Evaluate and print an expression
+    Expr(Expr),
+    /// `name = expr` binds the value of `expr` to `name`
+    Assign(String, Expr),
+    /// `name(params) = body` defines a function
+    Define(String, Vec<String>, Expr),
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Expr(expr) => write!(f, "{}", expr),
+            Statement::Assign(name, expr) => write!(f, "{} = {}", name, expr),
+            Statement::Define(name, params, body) => {
+                write!(f, "{}({}) = {}", name, params.join(", "), body)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn reparse(expr: &Expr) -> Expr {
+        parse(&expr.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_minimal_parentheses() {
+        let n = Expr::number;
+        let sum = Expr::binary(Op::Add, n(2.0), n(3.0));
+        assert_eq!(
+            Expr::binary(Op::Mul, sum.clone(), n(4.0)).to_string(),
+            "(2 + 3) * 4"
+        );
+        assert_eq!(
+            Expr::binary(Op::Add, n(1.0), sum.clone()).to_string(),
+            "1 + (2 + 3)"
+        );
+        assert_eq!(
+            Expr::binary(Op::Add, sum.clone(), n(1.0)).to_string(),
+            "2 + 3 + 1"
+        );
+        let pow = Expr::binary(Op::Pow, n(3.0), n(2.0));
+        assert_eq!(
+            Expr::binary(Op::Pow, n(2.0), pow.clone()).to_string(),
+            "2 ^ 3 ^ 2"
+        );
+        assert_eq!(
+            Expr::binary(Op::Pow, pow, n(2.0)).to_string(),
+            "(3 ^ 2) ^ 2"
+        );
+        let neg = Expr::unary(Op::Sub, n(2.0));
+        assert_eq!(
+            Expr::binary(Op::Pow, neg.clone(), n(2.0)).to_string(),
+            "(-2) ^ 2"
+        );
+        assert_eq!(Expr::unary(Op::Sub, sum).to_string(), "-(2 + 3)");
+    }
+
+    #[test]
+    fn test_round_trip_examples() {
+        for input in [
+            "(2 + 3) * 4 ^ 2 / -7",
+            "2 - (3 - 4)",
+            "2^-1",
+            "-2 ^ 2 * 3",
+            "(-2) ^ 2",
+            "--5",
+            "+1 - (2 / (3 * 4))",
+            "add 0.1 1e21",
+            "x * (y - ans) ^ -z",
+            "max(1, 2 + 3, -x) * sqrt(log(2, 8)) ^ 2",
+            "pi()",
+            "-3! ^ 2 % 7 // (n - 1)!",
+            "(2 ^ 3)! mod 10",
+            "2i * x + 3.5e-1i - i",
+        ] {
+            let expr = parse(input).unwrap();
+            assert_eq!(reparse(&expr), expr, "printed as {}", expr);
+        }
+    }
+
+    /// Small deterministic generator so the round trip is checked on many shapes
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % bound
+        }
+
+        fn expr(&mut self, depth: u32) -> Expr {
+            let ops = [Op::Add, Op::Sub, Op::Mul, Op::Div, Op::Pow, Op::Rem];
+            match if depth == 0 { 0 } else { self.next(5) } {
+                0 if self.next(4) == 0 => Expr::var(["x", "y", "ans"][self.next(3) as usize]),
+                0 => Expr::number(self.next(1000) as f64 / 8.0),
+                1 => {
+                    let sign = if self.next(2) == 0 { Op::Add } else { Op::Sub };
+                    Expr::unary(sign, self.expr(depth - 1))
+                }
+                2 => Expr::factorial(self.expr(depth - 1)),
+                _ => {
+                    let op = ops[self.next(6) as usize];
+                    Expr::binary(op, self.expr(depth - 1), self.expr(depth - 1))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_generated() {
+        let mut rng = Lcg(42);
+        for _ in 0..2000 {
+            let expr = rng.expr(5);
+            assert_eq!(reparse(&expr), expr, "printed as {}", expr);
+        }
+    }
+}
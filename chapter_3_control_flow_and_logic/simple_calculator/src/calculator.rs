@@ -0,0 +1,1831 @@
+//! A calculator session, the library's entry point for running input line by
+//! line the way the command-line REPL does
+//!
+//! A `Calculator` holds the variables, functions and RPN stack, the history,
+//! the number mode, the angle unit, the output format and the limits, and is
+//! configured through `Calculator::builder()`. `eval_line` takes whatever the
+//! REPL takes: statements in the current notation, settings commands like
+//! `mode rational` or `angle degrees`, and the commands `:simplify`, `d/dx`,
+//! `solve` and `plot`, and the meta-commands `:help`, `:vars`, `:history`,
+//! `:mode`, `:reset`, `:save` and `:load`. `$3` in a line stands for the
+//! value of history entry 3. What to do with the resulting `Line`, printing it
+//! or sending it on as JSON, is up to the front end.
+
+use crate::error::closest_match;
+use crate::formatter::{Formatter, Notation, fixed_places};
+use crate::plot::{self, Canvas};
+use crate::registry::Registry;
+use crate::{
+    ANS, AngleUnit, BigInt, CalcError, Complex, DEFAULT_MAX_DEPTH, Environment, EvalPolicy, Format,
+    IntType, Json, Number, Overflow, Rational, Rpn, RpnView, Span, Statement, UserFunction, Word,
+    derive_in, evaluate, execute, integral, parse_equation_with, parse_rpn_with,
+    parse_statement_with, parse_with, simplify, solve, solve_all,
+};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// History entries kept by default
+pub const DEFAULT_HISTORY: usize = 1000;
+
+/// What the calculator takes, one line per feature, as `:help` shows it
+pub const HELP: &str = "\
+Enter expressions like 2 + 3 * 4, (2 + 3)^2 or add 2 3
+Call functions like sqrt(2), log(2, 8) or max(1, 5, 3); constants pi, e and tau
+Assign variables with x = 4.2; 'ans' holds the last result
+Define functions with hyp(a, b) = sqrt(a^2 + b^2)
+Enter 7 // 2 or 7 % 2 for Euclidean division and 5! for factorials
+Switch with 'mode rational|integer|complex|float', or 'mode rpn|infix' for notation
+Work in fixed-width integers with 'mode u8' (i8..i128, u8..u128): 0xF0 & 0b1100, ~x, 1 << 4
+Choose what overflow does with 'overflow wrapping|saturating|error'
+In RPN, 3 4 + pushes 7; dup, swap, drop, clear, roll, neg and stack manage the stack
+Print with 'display fraction|decimal' (rational) or 'display rect|polar' (complex)
+Tidy a formula without evaluating it with ':simplify 2*x + x*1'
+Differentiate with 'd/dx x^3 + sin(x)'; other variables count as constants
+Find roots with 'solve x^3 - 2*x = 5 for x in [0, 3]', or all of them with solve_all
+Integrate with 'integrate(x^2, x, 0, 3)', shown with its error estimate
+Add or multiply terms with 'sum(1/n^2, n, 1, 100)' or 'prod(k, k, 1, 10)'
+Draw functions of x with 'plot sin(x) * x; cos(x) from -10 to 10'
+Undefined results like sqrt(-1) are errors; 'policy ieee' gives NaN and inf instead
+Work in degrees with 'angle degrees', and back with 'angle radians'
+Switch to machine-readable output with 'output json'
+Round results with 'format fixed 2', 'sci 3', 'eng 2' or 'sig 15', and back with 'format auto'
+Group digits in threes with 'grouping on', or with another character as in 'grouping _'
+List values and functions with ':vars', and earlier lines with ':history'; $3 is line 3's value
+Show the settings with ':mode', or change one with ':mode rational'; ':reset' forgets all values
+Save the session with ':save calc.txt' and replay it later with ':load calc.txt'
+Type ':help' to see this again, and 'quit' or 'exit' to leave";
+
+/// Commands that start with `:`, for suggestions when one is misspelled
+const COMMANDS: [&str; 8] = [
+    ":help",
+    ":vars",
+    ":history",
+    ":mode",
+    ":reset",
+    ":save",
+    ":load",
+    ":simplify",
+];
+
+/// Number type results are computed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberMode {
+    #[default]
+    Float,
+    /// Exact fractions
+    Rational,
+    /// Exact integers of any size
+    Integer,
+    Complex,
+    /// Fixed-width integers of one type, with bitwise operators
+    Programmer(IntType),
+}
+
+impl NumberMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            NumberMode::Float => "float",
+            NumberMode::Rational => "rational",
+            NumberMode::Integer => "integer",
+            NumberMode::Complex => "complex",
+            NumberMode::Programmer(ty) => ty.name(),
+        }
+    }
+
+    /// Mode called `name`, as in the `mode` command: an integer type name
+    /// like `u8` is programmer mode
+    pub fn from_name(name: &str) -> Option<NumberMode> {
+        match name {
+            "float" => Some(NumberMode::Float),
+            "rational" => Some(NumberMode::Rational),
+            "integer" => Some(NumberMode::Integer),
+            "complex" => Some(NumberMode::Complex),
+            _ => IntType::from_name(name).map(NumberMode::Programmer),
+        }
+    }
+}
+
+impl fmt::Display for NumberMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How results are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputFormat {
+    /// Rationals as decimals rather than fractions
+    pub decimal: bool,
+    /// Digits after the point for rationals shown as decimals
+    pub decimal_digits: usize,
+    /// Complex numbers in polar rather than rectangular form
+    pub polar: bool,
+    /// Lines as JSON objects rather than text; the front end does the writing
+    pub json: bool,
+    /// Notation and digit grouping for results; other notations than auto
+    /// show rationals as decimals and, beyond `fixed`, exact values by their
+    /// nearest `f64`
+    pub numbers: Formatter,
+}
+
+impl Default for OutputFormat {
+    fn default() -> OutputFormat {
+        OutputFormat {
+            decimal: false,
+            decimal_digits: 20,
+            polar: false,
+            json: false,
+            numbers: Formatter::default(),
+        }
+    }
+}
+
+/// Bounds on what a session keeps and how deep evaluation goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Nested user function calls before a `RecursionLimit` error
+    pub max_depth: usize,
+    /// History entries kept; the oldest go first
+    pub history: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_depth: DEFAULT_MAX_DEPTH,
+            history: DEFAULT_HISTORY,
+        }
+    }
+}
+
+/// One line run by a `Calculator`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// Position in the session, counting from 1
+    pub number: usize,
+    pub input: String,
+    /// The value shown, if the line produced one
+    pub result: Option<String>,
+    /// The value as input that reads it back, which `$n` stands for
+    pub value: Option<String>,
+}
+
+/// What running one line produced
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    /// A result value as shown, and as an `f64` (NaN if it has none)
+    Value(String, f64),
+    /// A function definition
+    Defined(String),
+    /// Confirmation of a settings command
+    Note(String),
+    /// The RPN stack from the bottom up, after `stack`
+    Stack(Vec<String>),
+    /// A drawing, one line per row
+    Plot(String),
+    /// Lines of information, like the variables after `:vars`
+    Listing(Vec<String>),
+    /// An RPN line that left the stack empty
+    Empty,
+}
+
+/// Why a line failed
+#[derive(Debug)]
+pub enum LineError {
+    Parse(CalcError),
+    Eval(CalcError),
+    /// A malformed settings command
+    Usage(&'static str),
+    /// A `:` command that does not exist, and the closest one that does
+    UnknownCommand(String, Option<String>),
+    /// A session file that could not be written, read or replayed
+    File(String),
+}
+
+impl LineError {
+    /// Message for the user, with the offending part of `line` underlined
+    pub fn render(&self, line: &str) -> String {
+        match self {
+            LineError::Parse(e) => format!("Parse error: {}", e.render(line)),
+            LineError::Eval(e) => format!("Error evaluating expression: {}", e.render(line)),
+            LineError::Usage(usage) => format!("Usage: {}", usage),
+            LineError::UnknownCommand(name, suggestion) => {
+                format!("Unknown command: {}{}", name, did_you_mean(suggestion))
+            }
+            LineError::File(message) => format!("File error: {}", message),
+        }
+    }
+
+    /// The message alone, without the line it refers to
+    pub fn message(&self) -> String {
+        match self {
+            LineError::Parse(e) | LineError::Eval(e) => e.to_string(),
+            LineError::Usage(usage) => format!("usage: {}", usage),
+            LineError::UnknownCommand(name, suggestion) => {
+                format!("unknown command: {}{}", name, did_you_mean(suggestion))
+            }
+            LineError::File(message) => message.clone(),
+        }
+    }
+
+    /// `{"kind", "message", "span"}`; an unknown command spans its name, other
+    /// errors outside a statement all of `line`
+    pub fn to_json(&self, line: &str) -> Json {
+        let (kind, span) = match self {
+            LineError::Parse(e) | LineError::Eval(e) => return Json::error(e),
+            LineError::UnknownCommand(name, _) => {
+                let start = line.find(name.as_str()).unwrap_or_default();
+                ("unknown_command", Span::new(start, start + name.len()))
+            }
+            LineError::Usage(_) => ("usage", Span::new(0, line.len())),
+            LineError::File(_) => ("file", Span::new(0, line.len())),
+        };
+        Json::object([
+            ("kind", Json::from(kind)),
+            ("message", Json::from(self.message())),
+            ("span", Json::span(span)),
+        ])
+    }
+}
+
+/// ` (did you mean 'x'?)` for a suggestion `x`, as `CalcError` writes it
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(s) => format!(" (did you mean '{}'?)", s),
+        None => String::new(),
+    }
+}
+
+/// Everything known about one line after running it
+#[derive(Debug)]
+pub struct Line {
+    /// The statement as the parser understood it
+    pub normalized: Option<String>,
+    /// Time spent evaluating, if the line got that far
+    pub elapsed: Option<Duration>,
+    pub outcome: Result<Output, LineError>,
+}
+
+impl Line {
+    /// A line that was not parsed or evaluated, like a settings command
+    fn new(outcome: Result<Output, LineError>) -> Line {
+        Line {
+            normalized: None,
+            elapsed: None,
+            outcome,
+        }
+    }
+
+    /// One JSON object describing `input` and how it went, with its line
+    /// number when reading a file
+    pub fn to_json(&self, input: &str, number: Option<usize>) -> Json {
+        let mut fields = vec![("input", Json::from(input))];
+        if let Some(n) = number {
+            fields.insert(0, ("line", Json::from(n)));
+        }
+        fields.push(("normalized", Json::from(self.normalized.clone())));
+        match &self.outcome {
+            Ok(Output::Value(text, value)) => {
+                fields.push(("result", Json::from(text.as_str())));
+                fields.push(("value", Json::from(*value)));
+            }
+            Ok(Output::Defined(_) | Output::Empty) => fields.push(("result", Json::Null)),
+            Ok(Output::Note(text)) => fields.push(("setting", Json::from(text.as_str()))),
+            Ok(Output::Plot(text)) => fields.push(("plot", Json::from(text.as_str()))),
+            Ok(Output::Listing(lines)) => fields.push((
+                "listing",
+                Json::Array(lines.iter().map(|l| Json::from(l.as_str())).collect()),
+            )),
+            Ok(Output::Stack(values)) => fields.push((
+                "stack",
+                Json::Array(values.iter().map(|v| Json::from(v.as_str())).collect()),
+            )),
+            Err(e) => fields.push(("error", e.to_json(input))),
+        }
+        // Whole nanoseconds, so the microseconds print without float noise
+        let micros = self.elapsed.map(|d| d.as_nanos() as f64 / 1000.0);
+        fields.push(("eval_time_us", Json::from(micros)));
+        Json::object(fields)
+    }
+}
+
+/// Variables, functions and the RPN stack in number type `N`
+#[derive(Debug)]
+struct State<N: Number> {
+    env: Environment<N>,
+    rpn: Rpn<N>,
+}
+
+impl<N: Number> State<N> {
+    fn new() -> State<N> {
+        State {
+            env: Environment::new(),
+            rpn: Rpn::new(),
+        }
+    }
+
+    fn convert<M: Number>(&self) -> State<M> {
+        State {
+            env: self.env.convert(),
+            rpn: self.rpn.convert(),
+        }
+    }
+
+    /// Run one line in infix or RPN notation
+    fn run(&mut self, line: &str, rpn: bool, show: impl Fn(&N) -> String) -> Line {
+        if !rpn {
+            let stmt = match parse_statement_with(line, self.env.registry()) {
+                Ok(stmt) => stmt,
+                Err(e) => return Line::new(Err(LineError::Parse(e))),
+            };
+            let start = Instant::now();
+            let result = execute(&stmt, &mut self.env);
+            let elapsed = start.elapsed();
+            let outcome = match result {
+                Ok(Some(value)) => Ok(Output::Value(show(&value), value.to_f64())),
+                Ok(None) => Ok(Output::Defined(stmt.to_string())),
+                Err(e) => Err(LineError::Eval(e)),
+            };
+            return Line {
+                normalized: Some(stmt.to_string()),
+                elapsed: Some(elapsed),
+                outcome,
+            };
+        }
+        let tokens = match parse_rpn_with(line, self.env.registry()) {
+            Ok(tokens) => tokens,
+            Err(e) => return Line::new(Err(LineError::Parse(e))),
+        };
+        let start = Instant::now();
+        let result = self.rpn.run_tokens(&tokens, &mut self.env);
+        let elapsed = start.elapsed();
+        let outcome = match (result, self.rpn.top()) {
+            (Ok(RpnView::Stack), _) => {
+                Ok(Output::Stack(self.rpn.values().iter().map(show).collect()))
+            }
+            (Ok(RpnView::Top), Some(top)) => Ok(Output::Value(show(top), top.to_f64())),
+            (Ok(RpnView::Top), None) => Ok(Output::Empty),
+            (Err(e), _) => Err(LineError::Eval(e)),
+        };
+        let words: Vec<String> = tokens.iter().map(|t| t.kind.to_string()).collect();
+        Line {
+            normalized: Some(words.join(" ")),
+            elapsed: Some(elapsed),
+            outcome,
+        }
+    }
+
+    /// Forget the variables, functions and stack
+    fn reset(&mut self) {
+        self.env.clear();
+        self.rpn = Rpn::new();
+    }
+
+    /// `name = value` for each variable, then each function definition
+    fn listing(&self, show: impl Fn(&N) -> String) -> Vec<String> {
+        let values = self
+            .env
+            .variables()
+            .map(|(name, value)| format!("{} = {}", name, show(value)));
+        let functions = self.env.functions().map(|(name, f)| definition(name, f));
+        values.chain(functions).collect()
+    }
+
+    /// Infix statements that rebuild the variables and functions, with `ans`
+    /// last since every assignment sets it; values that are not finite have
+    /// no literal and are left as comments
+    fn script(&self, literal: impl Fn(&N) -> String) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut ans = None;
+        for (name, value) in self.env.variables() {
+            let line = format!("{} = {}", name, literal(value));
+            if !value.is_finite() {
+                lines.push(format!("# {} cannot be read back", line));
+            } else if name == ANS {
+                ans = Some(line);
+            } else {
+                lines.push(line);
+            }
+        }
+        lines.extend(self.env.functions().map(|(name, f)| definition(name, f)));
+        lines.extend(ans);
+        lines
+    }
+}
+
+/// `name(params) = body`, as the function was defined
+fn definition(name: &str, f: &UserFunction) -> String {
+    Statement::Define(name.to_string(), f.params.clone(), f.body.clone()).to_string()
+}
+
+impl State<f64> {
+    /// A line that is just `integrate(...)`, shown with its error estimate
+    /// unless that is 0, or `None` for anything else
+    fn integral_line(&mut self, line: &str, numbers: Formatter) -> Option<Line> {
+        let Ok(Statement::Expr(expr)) = parse_statement_with(line, self.env.registry()) else {
+            return None;
+        };
+        let start = Instant::now();
+        let result = integral(&expr, &self.env)?;
+        let elapsed = start.elapsed();
+        let outcome = match result {
+            Ok(integral) => {
+                self.env.set(ANS, integral.value);
+                let mut shown = numbers.format(integral.value);
+                if integral.error != 0.0 {
+                    // An estimate; two significant figures are all it has
+                    let error = format!("{:.1e}", integral.error)
+                        .parse()
+                        .unwrap_or(f64::NAN);
+                    shown = format!("{} ± {}", shown, numbers.format(error));
+                }
+                Ok(Output::Value(shown, integral.value))
+            }
+            Err(e) => Err(LineError::Eval(e)),
+        };
+        Some(Line {
+            normalized: Some(expr.to_string()),
+            elapsed: Some(elapsed),
+            outcome,
+        })
+    }
+}
+
+impl State<Word> {
+    /// Read literals as `format`, and recast the variables and stack to it;
+    /// a value it cannot hold keeps its old type and fails when used
+    fn retype(&mut self, format: Format) {
+        self.env.set_context(format);
+        let recast: Vec<(String, Word)> = self
+            .env
+            .variables()
+            .filter_map(|(name, value)| Some((name.to_string(), value.cast(format).ok()?)))
+            .collect();
+        for (name, value) in recast {
+            self.env.set(name, value);
+        }
+        let stack: Vec<Word> = self
+            .rpn
+            .values()
+            .iter()
+            .map(|v| v.cast(format).unwrap_or_else(|_| v.clone()))
+            .collect();
+        self.rpn = Rpn::new();
+        for value in stack {
+            self.rpn.push(value);
+        }
+    }
+}
+
+/// How results of a number type are shown, and written back as literals for
+/// `:save` and history references
+trait Shown: Number {
+    fn show(&self, output: OutputFormat) -> String;
+
+    fn literal(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Shown for f64 {
+    fn show(&self, output: OutputFormat) -> String {
+        output.numbers.format(*self)
+    }
+}
+
+impl Shown for Rational {
+    fn show(&self, output: OutputFormat) -> String {
+        let numbers = output.numbers;
+        match numbers.notation {
+            Notation::Auto if output.decimal => {
+                numbers.group(&self.to_decimal(output.decimal_digits))
+            }
+            Notation::Auto => {
+                let parts = self.to_string();
+                let parts: Vec<String> = parts.split('/').map(|p| numbers.group(p)).collect();
+                parts.join("/")
+            }
+            Notation::Fixed(places) => {
+                numbers.group(&fixed_places(&self.to_decimal(places), places))
+            }
+            _ if self.to_f64().is_finite() => numbers.format(self.to_f64()),
+            _ => numbers.group(&self.to_decimal(output.decimal_digits)),
+        }
+    }
+}
+
+impl Shown for BigInt {
+    fn show(&self, output: OutputFormat) -> String {
+        let numbers = output.numbers;
+        match numbers.notation {
+            Notation::Auto => numbers.group(&self.to_string()),
+            Notation::Fixed(places) => numbers.group(&fixed_places(&self.to_string(), places)),
+            _ if self.to_f64().is_finite() => numbers.format(self.to_f64()),
+            _ => numbers.group(&self.to_string()),
+        }
+    }
+}
+
+impl Shown for Complex {
+    fn show(&self, output: OutputFormat) -> String {
+        let part = |x: f64| output.numbers.format(x);
+        if output.polar {
+            self.to_polar_string_with(part)
+        } else {
+            self.to_string_with(part)
+        }
+    }
+}
+
+impl Shown for Word {
+    /// In decimal, hexadecimal, octal and binary
+    fn show(&self, _output: OutputFormat) -> String {
+        let shown: Vec<String> = [16, 8, 2]
+            .iter()
+            .map(|&r| self.to_radix_string(r))
+            .collect();
+        format!("{} = {}", self, shown.join(" = "))
+    }
+
+    /// Hexadecimal, which reads back as the same bits, including the sign
+    /// bit, where a negative decimal like `-128` in `i8` would not
+    fn literal(&self) -> String {
+        self.to_radix_string(16)
+    }
+}
+
+/// `$body` with `$state` bound to the session's `State`, whatever its number
+/// type; each arm is compiled for its own type
+macro_rules! with_state {
+    ($session:expr, $state:ident => $body:expr) => {
+        match $session {
+            Session::Float($state) => $body,
+            Session::Rational($state) => $body,
+            Session::Integer($state) => $body,
+            Session::Complex($state) => $body,
+            Session::Programmer($state) => $body,
+        }
+    };
+}
+
+/// Session state in the current number mode
+#[derive(Debug)]
+enum Session {
+    Float(State<f64>),
+    Rational(State<Rational>),
+    Integer(State<BigInt>),
+    Complex(State<Complex>),
+    Programmer(State<Word>),
+}
+
+impl Session {
+    /// The session's variables, functions and stack in number type `N`
+    fn convert<N: Number>(&self) -> State<N> {
+        with_state!(self, state => state.convert())
+    }
+
+    fn policy(&self) -> EvalPolicy {
+        with_state!(self, state => state.env.policy())
+    }
+
+    fn set_policy(&mut self, policy: EvalPolicy) {
+        with_state!(self, state => state.env.set_policy(policy))
+    }
+
+    fn angle(&self) -> AngleUnit {
+        with_state!(self, state => state.env.angle())
+    }
+
+    fn set_angle(&mut self, angle: AngleUnit) {
+        with_state!(self, state => state.env.set_angle(angle))
+    }
+
+    fn set_max_depth(&mut self, depth: usize) {
+        with_state!(self, state => state.env.set_max_depth(depth))
+    }
+
+    fn registry(&self) -> &Registry {
+        with_state!(self, state => state.env.registry())
+    }
+
+    fn registry_mut(&mut self) -> &mut Registry {
+        with_state!(self, state => state.env.registry_mut())
+    }
+
+    fn reset(&mut self) {
+        with_state!(self, state => state.reset())
+    }
+
+    /// Variables and functions as `:vars` shows them
+    fn listing(&self, output: OutputFormat) -> Vec<String> {
+        with_state!(self, state => state.listing(|v| v.show(output)))
+    }
+
+    fn script(&self) -> Vec<String> {
+        with_state!(self, state => state.script(Shown::literal))
+    }
+
+    /// `ans` as a literal, if it has a value
+    fn ans(&self) -> Option<String> {
+        with_state!(self, state => state.env.get(ANS).map(Shown::literal))
+    }
+}
+
+/// A calculator session: values in the current number mode, the notation,
+/// the history and how results are shown
+#[derive(Debug)]
+pub struct Calculator {
+    session: Session,
+    rpn: bool,
+    output: OutputFormat,
+    limits: Limits,
+    /// What programmer mode does with results out of range
+    overflow: Overflow,
+    /// Size and characters of plots
+    canvas: Canvas,
+    history: Vec<HistoryEntry>,
+    /// Lines run so far
+    lines: usize,
+    /// `ans` as a literal after the line being run, if it computed a value
+    value: Option<String>,
+}
+
+/// Settings for a new `Calculator`; every one has the same default as the
+/// REPL
+#[derive(Debug, Clone)]
+pub struct CalculatorBuilder {
+    mode: NumberMode,
+    rpn: bool,
+    angle: AngleUnit,
+    output: OutputFormat,
+    limits: Limits,
+    policy: EvalPolicy,
+    overflow: Overflow,
+    canvas: Canvas,
+    registry: Registry,
+}
+
+impl Default for CalculatorBuilder {
+    fn default() -> CalculatorBuilder {
+        CalculatorBuilder {
+            mode: NumberMode::default(),
+            rpn: false,
+            angle: AngleUnit::default(),
+            output: OutputFormat::default(),
+            limits: Limits::default(),
+            policy: EvalPolicy::default(),
+            overflow: Format::default().overflow,
+            canvas: Canvas::default(),
+            registry: Registry::default(),
+        }
+    }
+}
+
+impl CalculatorBuilder {
+    pub fn mode(mut self, mode: NumberMode) -> CalculatorBuilder {
+        self.mode = mode;
+        self
+    }
+
+    /// Read input in reverse Polish notation rather than infix
+    pub fn rpn(mut self, rpn: bool) -> CalculatorBuilder {
+        self.rpn = rpn;
+        self
+    }
+
+    pub fn angle(mut self, angle: AngleUnit) -> CalculatorBuilder {
+        self.angle = angle;
+        self
+    }
+
+    pub fn output(mut self, output: OutputFormat) -> CalculatorBuilder {
+        self.output = output;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> CalculatorBuilder {
+        self.limits = limits;
+        self
+    }
+
+    pub fn policy(mut self, policy: EvalPolicy) -> CalculatorBuilder {
+        self.policy = policy;
+        self
+    }
+
+    /// What programmer mode does with results out of range
+    pub fn overflow(mut self, overflow: Overflow) -> CalculatorBuilder {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Size and characters of plots
+    pub fn canvas(mut self, canvas: Canvas) -> CalculatorBuilder {
+        self.canvas = canvas;
+        self
+    }
+
+    /// Operators and functions beyond the built-ins
+    pub fn registry(mut self, registry: Registry) -> CalculatorBuilder {
+        self.registry = registry;
+        self
+    }
+
+    pub fn build(self) -> Calculator {
+        let mut state = State::<f64>::new();
+        *state.env.registry_mut() = self.registry;
+        let mut calculator = Calculator {
+            session: Session::Float(state),
+            rpn: self.rpn,
+            output: self.output,
+            limits: self.limits,
+            overflow: self.overflow,
+            canvas: self.canvas,
+            history: Vec::new(),
+            lines: 0,
+            value: None,
+        };
+        calculator.set_policy(self.policy);
+        calculator.set_limits(self.limits);
+        calculator.set_angle(self.angle);
+        calculator.set_mode(self.mode);
+        calculator
+    }
+}
+
+impl Default for Calculator {
+    fn default() -> Calculator {
+        Calculator::builder().build()
+    }
+}
+
+impl Calculator {
+    /// A session with the REPL's defaults: float mode, infix, radians
+    pub fn new() -> Calculator {
+        Calculator::default()
+    }
+
+    pub fn builder() -> CalculatorBuilder {
+        CalculatorBuilder::default()
+    }
+
+    pub fn mode(&self) -> NumberMode {
+        match &self.session {
+            Session::Float(_) => NumberMode::Float,
+            Session::Rational(_) => NumberMode::Rational,
+            Session::Integer(_) => NumberMode::Integer,
+            Session::Complex(_) => NumberMode::Complex,
+            Session::Programmer(state) => NumberMode::Programmer(state.env.context().ty),
+        }
+    }
+
+    /// Switch number mode, converting variables and the stack; values the
+    /// new mode cannot hold are dropped. Switching to the current mode
+    /// changes nothing
+    pub fn set_mode(&mut self, mode: NumberMode) {
+        if mode == self.mode() {
+            return;
+        }
+        self.session = match mode {
+            NumberMode::Float => Session::Float(self.session.convert()),
+            NumberMode::Rational => Session::Rational(self.session.convert()),
+            NumberMode::Integer => Session::Integer(self.session.convert()),
+            NumberMode::Complex => Session::Complex(self.session.convert()),
+            NumberMode::Programmer(ty) => {
+                let mut state = match &mut self.session {
+                    Session::Programmer(state) => std::mem::replace(state, State::new()),
+                    session => session.convert(),
+                };
+                state.retype(Format {
+                    ty,
+                    overflow: self.overflow,
+                });
+                Session::Programmer(state)
+            }
+        };
+    }
+
+    /// Whether input is read in reverse Polish notation
+    pub fn rpn(&self) -> bool {
+        self.rpn
+    }
+
+    pub fn set_rpn(&mut self, rpn: bool) {
+        self.rpn = rpn;
+    }
+
+    pub fn angle(&self) -> AngleUnit {
+        self.session.angle()
+    }
+
+    pub fn set_angle(&mut self, angle: AngleUnit) {
+        self.session.set_angle(angle);
+    }
+
+    pub fn output(&self) -> OutputFormat {
+        self.output
+    }
+
+    pub fn set_output(&mut self, output: OutputFormat) {
+        self.output = output;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Change the limits, dropping the oldest history beyond the new length
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.session.set_max_depth(limits.max_depth);
+        let excess = self.history.len().saturating_sub(limits.history);
+        self.history.drain(..excess);
+    }
+
+    pub fn policy(&self) -> EvalPolicy {
+        self.session.policy()
+    }
+
+    pub fn set_policy(&mut self, policy: EvalPolicy) {
+        self.session.set_policy(policy);
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// Change what programmer mode does with results out of range, recasting
+    /// the current values if in programmer mode
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+        if let Session::Programmer(state) = &mut self.session {
+            let ty = state.env.context().ty;
+            state.retype(Format { ty, overflow });
+        }
+    }
+
+    pub fn canvas(&self) -> Canvas {
+        self.canvas
+    }
+
+    pub fn set_canvas(&mut self, canvas: Canvas) {
+        self.canvas = canvas;
+    }
+
+    /// Operators and functions beyond the built-ins, to add more to; the
+    /// registry carries over when the mode changes
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        self.session.registry_mut()
+    }
+
+    /// Lines run so far, oldest first, as many as the limits keep
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    /// Run one line of input, without its comment, and record it in the
+    /// history; `$n` in the line stands for the value of history entry `n`
+    pub fn eval_line(&mut self, line: &str) -> Line {
+        let result = match self.expand(line) {
+            Ok((expanded, replaced)) => {
+                let mut result = self.run(&expanded);
+                result.outcome = result.outcome.map_err(|e| match e {
+                    LineError::Parse(e) => LineError::Parse(unexpanded(e, &replaced)),
+                    LineError::Eval(e) => LineError::Eval(unexpanded(e, &replaced)),
+                    other => other,
+                });
+                result
+            }
+            Err(e) => Line::new(Err(LineError::Eval(e))),
+        };
+        self.lines += 1;
+        // A value computed outside the session, like a root from `solve`,
+        // reads back from its `f64`; a formula from `d/dx` as it is shown
+        let ans = self.value.take();
+        let (shown, value) = match &result.outcome {
+            Ok(Output::Value(text, x)) => {
+                let value = match ans {
+                    Some(ans) => ans,
+                    None if x.is_finite() => x.to_string(),
+                    None => text.clone(),
+                };
+                (Some(text.clone()), Some(value))
+            }
+            _ => (None, None),
+        };
+        self.history.push(HistoryEntry {
+            number: self.lines,
+            input: line.to_string(),
+            result: shown,
+            value,
+        });
+        let excess = self.history.len().saturating_sub(self.limits.history);
+        self.history.drain(..excess);
+        result
+    }
+
+    /// The settings as the commands that choose them, number mode last
+    fn settings(&self) -> Vec<String> {
+        let decimal = if self.output.decimal {
+            "decimal"
+        } else {
+            "fraction"
+        };
+        let polar = if self.output.polar { "polar" } else { "rect" };
+        vec![
+            format!("overflow {}", self.overflow.name()),
+            format!("policy {}", self.policy().name()),
+            format!("angle {}", self.angle().name()),
+            format!("display {}", decimal),
+            format!("display {}", polar),
+            format!("format {}", self.output.numbers.notation),
+            self.grouping(),
+            format!("mode {}", self.mode()),
+        ]
+    }
+
+    /// The `grouping` command that chooses the current separator
+    fn grouping(&self) -> String {
+        match self.output.numbers.separator {
+            Some(separator) => format!("grouping {}", separator),
+            None => "grouping off".to_string(),
+        }
+    }
+
+    fn notation(&self) -> &'static str {
+        if self.rpn { "mode rpn" } else { "mode infix" }
+    }
+
+    /// The session as lines that rebuild it when run: the settings, then the
+    /// variables and functions
+    ///
+    /// The RPN stack, the history and operators or functions registered in
+    /// code are not included, nor is the output format, which belongs to
+    /// whoever runs the lines.
+    pub fn script(&self) -> String {
+        let mut lines = vec![format!(
+            "# simple_calculator session in {} mode",
+            self.mode()
+        )];
+        lines.extend(self.settings());
+        lines.push("mode infix".to_string());
+        lines.extend(self.session.script());
+        if self.rpn {
+            lines.push(self.notation().to_string());
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Run one line: a settings command, or input in the current mode
+    fn run(&mut self, line: &str) -> Line {
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        if is_statement(&words) {
+            words.clear();
+        }
+        let note = |text: &str| Line::new(Ok(Output::Note(text.to_string())));
+        let listing = |lines: Vec<String>| Line::new(Ok(Output::Listing(lines)));
+        if let [word, _, ..] = words.as_slice()
+            && let Some(var) = derivative_variable(word)
+        {
+            return derive_line(line, var, self.session.registry(), self.angle());
+        }
+        if let ["solve" | "solve_all", _, ..] = words.as_slice()
+            && line.contains(" for ")
+        {
+            return self.solve_line(line, words[0] == "solve_all");
+        }
+        if let ["plot", _, ..] = words.as_slice()
+            && line.contains(" from ")
+        {
+            return self.plot_line(line);
+        }
+        match words.as_slice() {
+            ["mode", name] if let Some(mode) = NumberMode::from_name(name) => {
+                self.set_mode(mode);
+                note(&format!("mode {}", mode))
+            }
+            ["overflow", name] if let Some(overflow) = Overflow::from_name(name) => {
+                self.set_overflow(overflow);
+                note(&format!("overflow {}", overflow.name()))
+            }
+            ["policy", name] if let Some(policy) = EvalPolicy::from_name(name) => {
+                self.set_policy(policy);
+                note(&format!("policy {}", policy.name()))
+            }
+            ["angle", name] if let Some(angle) = AngleUnit::from_name(name) => {
+                self.set_angle(angle);
+                note(&format!("angle {}", angle.name()))
+            }
+            ["mode", "rpn"] => {
+                self.rpn = true;
+                note("mode rpn")
+            }
+            ["mode", "infix"] => {
+                self.rpn = false;
+                note("mode infix")
+            }
+            ["display", "fraction"] => {
+                self.output.decimal = false;
+                note("display fraction")
+            }
+            ["display", "decimal"] => {
+                self.output.decimal = true;
+                note("display decimal")
+            }
+            ["display", "rect"] => {
+                self.output.polar = false;
+                note("display rect")
+            }
+            ["display", "polar"] => {
+                self.output.polar = true;
+                note("display polar")
+            }
+            ["format", notation @ ..] if let Some(notation) = Notation::from_words(notation) => {
+                self.output.numbers.notation = notation;
+                note(&format!("format {}", notation))
+            }
+            ["grouping", "on"] => {
+                self.output.numbers.separator = Some(',');
+                note(&self.grouping())
+            }
+            ["grouping", "off"] => {
+                self.output.numbers.separator = None;
+                note(&self.grouping())
+            }
+            ["grouping", separator]
+                if let [c] = separator.chars().collect::<Vec<_>>()[..]
+                    && !c.is_alphanumeric() =>
+            {
+                self.output.numbers.separator = Some(c);
+                note(&self.grouping())
+            }
+            ["output", "text"] => {
+                self.output.json = false;
+                note("output text")
+            }
+            ["output", "json"] => {
+                self.output.json = true;
+                note("output json")
+            }
+            [":help"] => listing(HELP.lines().map(str::to_string).collect()),
+            [":vars"] => match self.session.listing(self.output) {
+                lines if lines.is_empty() => note("(no variables or functions)"),
+                lines => listing(lines),
+            },
+            [":history"] if self.history.is_empty() => note("(no history)"),
+            [":history"] => listing(self.history.iter().map(history_line).collect()),
+            [":mode"] => {
+                let mut lines = self.settings();
+                lines.push(self.notation().to_string());
+                listing(lines)
+            }
+            [":mode", ..] => self.run(&line.trim_start()[1..]),
+            [":reset"] => {
+                self.session.reset();
+                note("reset")
+            }
+            [":save", _, ..] => self.save_line(command_argument(line)),
+            [":load", _, ..] => self.load_line(command_argument(line)),
+            [":save"] => Line::new(Err(LineError::Usage(":save <file>"))),
+            [":load"] => Line::new(Err(LineError::Usage(":load <file>"))),
+            [":help" | ":vars" | ":history" | ":reset", _, ..] => Line::new(Err(LineError::Usage(
+                ":help, :vars, :history and :reset take no arguments",
+            ))),
+            [":simplify", _, ..] => simplify_line(line, self.policy(), self.session.registry()),
+            [":simplify"] => Line::new(Err(LineError::Usage(":simplify <formula>"))),
+            [command, ..] if command.starts_with(':') => {
+                let suggestion = closest_match(command, COMMANDS);
+                Line::new(Err(LineError::UnknownCommand(
+                    command.to_string(),
+                    suggestion.map(str::to_string),
+                )))
+            }
+            [
+                "mode" | "display" | "output" | "overflow" | "policy" | "angle" | "format"
+                | "grouping",
+                ..,
+            ] => Line::new(Err(LineError::Usage(
+                "mode float|rational|integer|complex|i8..i128|u8..u128|rpn|infix, display fraction|decimal|rect|polar, format auto|fixed N|sci N|eng N|sig N, grouping on|off|<character>, overflow wrapping|saturating|error, policy strict|ieee, angle radians|degrees, output text|json",
+            ))),
+            _ => {
+                let (rpn, output) = (self.rpn, self.output);
+                let integral = match &mut self.session {
+                    Session::Float(state) if !rpn => state.integral_line(line, output.numbers),
+                    _ => None,
+                };
+                let result = integral.unwrap_or_else(|| {
+                    with_state!(&mut self.session, state => state.run(line, rpn, |v| v.show(output)))
+                });
+                if let Ok(Output::Value(..)) = result.outcome {
+                    self.value = self.session.ans();
+                }
+                result
+            }
+        }
+    }
+}
+
+impl Calculator {
+    /// `:save file`: write `script()` to the file
+    fn save_line(&self, path: &str) -> Line {
+        let outcome = match std::fs::write(path, self.script()) {
+            Ok(()) => Ok(Output::Note(format!("saved to {}", path))),
+            Err(e) => Err(LineError::File(format!("cannot write {}: {}", path, e))),
+        };
+        Line::new(outcome)
+    }
+
+    /// `:load file`: run every line of the file, without output, stopping at
+    /// the first that fails; lines before it keep their effect
+    fn load_line(&mut self, path: &str) -> Line {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                return Line::new(Err(LineError::File(format!("cannot read {}: {}", path, e))));
+            }
+        };
+        let mut count = 0;
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line);
+            if line.is_empty() {
+                continue;
+            }
+            let outcome = if line.split_whitespace().next() == Some(":load") {
+                Err(LineError::File(
+                    ":load cannot be used in a loaded file".to_string(),
+                ))
+            } else {
+                self.run(line).outcome
+            };
+            if let Err(e) = outcome {
+                let message = format!("{} line {}: {}", path, number + 1, e.message());
+                return Line::new(Err(LineError::File(message)));
+            }
+            count += 1;
+        }
+        let plural = if count == 1 { "" } else { "s" };
+        Line::new(Ok(Output::Note(format!(
+            "loaded {} line{} from {}",
+            count, plural, path
+        ))))
+    }
+
+    /// `line` with each `$n` replaced by the value of history entry `n`, in
+    /// parentheses unless in RPN, and the span of each replacement before and
+    /// after; `:save` and `:load` take file names, which are left alone
+    fn expand(&self, line: &str) -> Result<(String, Vec<(Span, Span)>), CalcError> {
+        let mut expanded = String::new();
+        let mut replaced = Vec::new();
+        if let Some(":save" | ":load") = line.split_whitespace().next() {
+            return Ok((line.to_string(), replaced));
+        }
+        let mut copied = 0;
+        let mut from = 0;
+        while let Some(found) = line[from..].find('$') {
+            let start = from + found;
+            let digits = line[start + 1..]
+                .bytes()
+                .take_while(u8::is_ascii_digit)
+                .count();
+            from = start + 1 + digits;
+            if digits == 0 {
+                continue;
+            }
+            let span = Span::new(start, from);
+            let n = line[start + 1..from].parse().unwrap_or_default();
+            let value = self
+                .history
+                .iter()
+                .find(|entry| entry.number == n)
+                .and_then(|entry| entry.value.as_deref())
+                .ok_or(CalcError::NoHistory(n, span))?;
+            expanded.push_str(&line[copied..start]);
+            let at = expanded.len();
+            if self.rpn {
+                expanded.push_str(value);
+            } else {
+                expanded.push_str(&format!("({})", value));
+            }
+            replaced.push((span, Span::new(at, expanded.len())));
+            copied = from;
+        }
+        expanded.push_str(&line[copied..]);
+        Ok((expanded, replaced))
+    }
+}
+
+/// `e` pointing into the line as typed rather than as `expand` rewrote it;
+/// a span edge inside a replacement moves to that edge of the `$n`
+fn unexpanded(e: CalcError, replaced: &[(Span, Span)]) -> CalcError {
+    let original = |position: usize, end: bool| {
+        let mut shift = 0;
+        for (typed, expanded) in replaced {
+            if position >= expanded.end {
+                shift = typed.end as isize - expanded.end as isize;
+            } else if position > expanded.start {
+                return if end { typed.end } else { typed.start };
+            } else {
+                break;
+            }
+        }
+        position.saturating_add_signed(shift)
+    };
+    let span = e.span();
+    e.at(Span::new(
+        original(span.start, false),
+        original(span.end, true),
+    ))
+}
+
+/// One `:history` line: `$n: input = result`
+fn history_line(entry: &HistoryEntry) -> String {
+    match &entry.result {
+        Some(result) => format!("${}: {} = {}", entry.number, entry.input, result),
+        None => format!("${}: {}", entry.number, entry.input),
+    }
+}
+
+/// Everything after a command word, trimmed
+fn command_argument(line: &str) -> &str {
+    let line = line.trim_start();
+    line[line.find(char::is_whitespace).unwrap_or(line.len())..].trim()
+}
+
+/// `line` without its `#` comment and surrounding whitespace
+pub fn strip_comment(line: &str) -> &str {
+    line.split('#').next().unwrap_or_default().trim()
+}
+
+/// `:simplify formula`: the formula, or the body of a definition, tidied up
+/// without evaluating it, keeping what `policy` needs kept
+fn simplify_line(line: &str, policy: EvalPolicy, registry: &Registry) -> Line {
+    let offset = ":simplify".len();
+    let stmt = match parse_statement_with(&line[offset..], registry) {
+        Ok(stmt) => stmt,
+        Err(e) => return Line::new(Err(LineError::Parse(shifted(e, offset)))),
+    };
+    let start = Instant::now();
+    let simplified = match &stmt {
+        Statement::Expr(expr) => Statement::Expr(simplify(expr, policy)),
+        Statement::Assign(name, expr) => Statement::Assign(name.clone(), simplify(expr, policy)),
+        Statement::Define(name, params, body) => {
+            Statement::Define(name.clone(), params.clone(), simplify(body, policy))
+        }
+    };
+    Line {
+        normalized: Some(stmt.to_string()),
+        elapsed: Some(start.elapsed()),
+        outcome: Ok(Output::Value(simplified.to_string(), f64::NAN)),
+    }
+}
+
+impl Calculator {
+    /// `solve equation for x in [low, high]`, or with `solve_all` every root
+    /// there, in floating point whatever the number mode
+    fn solve_line(&self, line: &str, all: bool) -> Line {
+        const USAGE: &str = "solve|solve_all <equation> for <x> in [<low>, <high>]";
+        let usage = || Line::new(Err(LineError::Usage(USAGE)));
+        let env = self.session.convert::<f64>().env;
+        let numbers = self.output.numbers;
+        let command = if all { "solve_all" } else { "solve" };
+        let Some(for_at) = line.rfind(" for ") else {
+            return usage();
+        };
+        let Some((var, bounds)) = line[for_at + " for ".len()..].split_once(" in ") else {
+            return usage();
+        };
+        let (var, bounds) = (var.trim(), bounds.trim());
+        let Some((low, high)) = bounds
+            .strip_prefix('[')
+            .and_then(|b| b.strip_suffix(']'))
+            .and_then(|b| b.split_once(','))
+        else {
+            return usage();
+        };
+        if !is_name(var) {
+            return usage();
+        }
+
+        let equation_text = &line[command.len()..for_at];
+        let equation = match parse_equation_with(equation_text, env.registry()) {
+            Ok(equation) => equation,
+            Err(e) => return Line::new(Err(LineError::Parse(shifted(e, command.len())))),
+        };
+        let mut range = [0.0; 2];
+        for (bound, text) in range.iter_mut().zip([low, high]) {
+            *bound = match evaluate_part(line, text, &env) {
+                Ok(value) => value,
+                Err(e) => return Line::new(Err(e)),
+            };
+        }
+
+        let start = Instant::now();
+        let outcome = if all {
+            solve_all(&equation, var, range[0], range[1], &env).map(|roots| {
+                match roots.as_slice() {
+                    [] => Output::Note("no roots found".to_string()),
+                    _ => {
+                        let shown: Vec<String> =
+                            roots.iter().map(|&root| numbers.format(root)).collect();
+                        Output::Value(shown.join(", "), f64::NAN)
+                    }
+                }
+            })
+        } else {
+            solve(&equation, var, range[0], range[1], &env)
+                .map(|root| Output::Value(numbers.format(root), root))
+        };
+        Line {
+            normalized: Some(format!("{} = 0", equation)),
+            elapsed: Some(start.elapsed()),
+            outcome: outcome.map_err(|e| LineError::Eval(shifted(e, command.len()))),
+        }
+    }
+}
+
+impl Calculator {
+    /// `plot f; g from low to high`: each formula drawn over `x` on one grid,
+    /// in floating point whatever the number mode
+    fn plot_line(&self, line: &str) -> Line {
+        const USAGE: &str = "plot <formula>[; <formula>...] from <low> to <high>";
+        let env = self.session.convert::<f64>().env;
+        let Some(from_at) = line.rfind(" from ") else {
+            return Line::new(Err(LineError::Usage(USAGE)));
+        };
+        let Some((low, high)) = line[from_at + " from ".len()..].split_once(" to ") else {
+            return Line::new(Err(LineError::Usage(USAGE)));
+        };
+        let mut formulas = Vec::new();
+        for text in line["plot".len()..from_at].split(';') {
+            let offset = text.as_ptr() as usize - line.as_ptr() as usize;
+            match parse_with(text, env.registry()) {
+                Ok(expr) => formulas.push((expr, offset)),
+                Err(e) => return Line::new(Err(LineError::Parse(shifted(e, offset)))),
+            }
+        }
+        let mut range = [0.0; 2];
+        for (bound, text) in range.iter_mut().zip([low, high]) {
+            *bound = match evaluate_part(line, text, &env) {
+                Ok(value) => value,
+                Err(e) => return Line::new(Err(e)),
+            };
+        }
+
+        let start = Instant::now();
+        let columns = self.canvas.columns();
+        let curves = formulas
+            .iter()
+            .map(|(expr, offset)| {
+                plot::sample(expr, "x", range[0], range[1], columns, &env)
+                    .map_err(|e| shifted(e, *offset))
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let shown: Vec<String> = formulas.iter().map(|(e, _)| e.to_string()).collect();
+        Line {
+            normalized: Some(shown.join("; ")),
+            elapsed: Some(start.elapsed()),
+            outcome: curves
+                .map(|curves| Output::Plot(plot::render(&curves, range[0], range[1], &self.canvas)))
+                .map_err(LineError::Eval),
+        }
+    }
+}
+
+/// Value of `part`, a slice of `line`, with errors pointing into `line`
+fn evaluate_part(line: &str, part: &str, env: &Environment) -> Result<f64, LineError> {
+    let offset = part.as_ptr() as usize - line.as_ptr() as usize;
+    match parse_with(part, env.registry()).map(|expr| evaluate(&expr, env)) {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(LineError::Eval(shifted(e, offset))),
+        Err(e) => Err(LineError::Parse(shifted(e, offset))),
+    }
+}
+
+/// Whether `words` assign to or call a name rather than give a command, as
+/// in `format = 3` or `angle (x) = x * 2`, where the name is a command word
+fn is_statement(words: &[&str]) -> bool {
+    matches!(words, [first, second, ..]
+        if !first.starts_with(':') && second.starts_with(['=', '(']))
+}
+
+/// Whether `word` could name a variable
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_alphabetic() || first == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// `x` for a command word `d/dx`
+fn derivative_variable(word: &str) -> Option<&str> {
+    word.strip_prefix("d/d").filter(|var| is_name(var))
+}
+
+/// `d/dx formula`: the derivative of the formula with respect to `var`,
+/// simplified, with trigonometric functions in `angle`
+fn derive_line(line: &str, var: &str, registry: &Registry, angle: AngleUnit) -> Line {
+    let offset = "d/d".len() + var.len();
+    let expr = match parse_with(&line[offset..], registry) {
+        Ok(expr) => expr,
+        Err(e) => return Line::new(Err(LineError::Parse(shifted(e, offset)))),
+    };
+    let start = Instant::now();
+    let derivative = derive_in(&expr, var, angle);
+    let elapsed = start.elapsed();
+    Line {
+        normalized: Some(expr.to_string()),
+        elapsed: Some(elapsed),
+        outcome: derivative
+            .map(|d| Output::Value(d.to_string(), f64::NAN))
+            .map_err(|e| LineError::Eval(shifted(e, offset))),
+    }
+}
+
+/// `e` pointing `offset` bytes further right, for text parsed after a
+/// command word
+fn shifted(e: CalcError, offset: usize) -> CalcError {
+    let span = e.span();
+    e.at(Span::new(span.start + offset, span.end + offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Arity;
+
+    /// Text of the value `line` produced
+    fn value(line: Line) -> String {
+        match line.outcome {
+            Ok(Output::Value(text, _)) => text,
+            other => panic!("expected a value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut registry = Registry::default();
+        registry.register_function("clamp", Arity::Exact(3), |a| a[0].clamp(a[1], a[2]));
+        let mut calculator = Calculator::builder()
+            .mode(NumberMode::Rational)
+            .output(OutputFormat {
+                decimal: true,
+                decimal_digits: 3,
+                ..OutputFormat::default()
+            })
+            .limits(Limits {
+                max_depth: 5,
+                history: 2,
+            })
+            .registry(registry)
+            .build();
+        assert_eq!(calculator.mode(), NumberMode::Rational);
+        assert_eq!(value(calculator.eval_line("2 / 3")), "0.667");
+        calculator.eval_line("f(n) = f(n + 1)");
+        assert!(matches!(
+            calculator.eval_line("f(1)").outcome,
+            Err(LineError::Eval(CalcError::RecursionLimit(_, 5, _)))
+        ));
+        calculator.eval_line("mode float");
+        assert_eq!(value(calculator.eval_line("clamp(7, 0, 5)")), "5");
+        let history: Vec<(usize, &str)> = calculator
+            .history()
+            .iter()
+            .map(|entry| (entry.number, entry.input.as_str()))
+            .collect();
+        assert_eq!(history, [(4, "mode float"), (5, "clamp(7, 0, 5)")]);
+        assert_eq!(calculator.history()[1].result.as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn test_modes_and_settings() {
+        let mut calculator = Calculator::builder()
+            .mode(NumberMode::Programmer(IntType::U8))
+            .overflow(Overflow::Wrapping)
+            .rpn(true)
+            .build();
+        assert_eq!(
+            value(calculator.eval_line("200 100 +")),
+            "44 = 0x2C = 0o54 = 0b0010_1100"
+        );
+        calculator.set_rpn(false);
+        assert!(matches!(
+            calculator.eval_line("mode hex").outcome,
+            Err(LineError::Usage(_))
+        ));
+        assert!(matches!(
+            calculator.eval_line("mode i16").outcome,
+            Ok(Output::Note(text)) if text == "mode i16"
+        ));
+        assert_eq!(calculator.mode(), NumberMode::Programmer(IntType::I16));
+        assert_eq!(NumberMode::from_name("complex"), Some(NumberMode::Complex));
+        calculator.set_policy(EvalPolicy::Ieee);
+        calculator.set_mode(NumberMode::Float);
+        assert_eq!(calculator.policy(), EvalPolicy::Ieee);
+        assert_eq!(value(calculator.eval_line("1 / 0")), "inf");
+        assert_eq!(value(calculator.eval_line(":simplify 0 * x")), "0 * x");
+    }
+
+    #[test]
+    fn test_mode_switch_keeps_exact_values() {
+        let mut calculator = Calculator::builder().mode(NumberMode::Integer).build();
+        calculator.eval_line("x = 2^200");
+        let exact = value(calculator.eval_line("x"));
+        calculator.eval_line("mode integer");
+        assert_eq!(value(calculator.eval_line("x")), exact);
+        calculator.eval_line("mode rational");
+        assert_eq!(value(calculator.eval_line("x")), exact);
+        calculator.eval_line("y = 1/3");
+        calculator.eval_line("mode rational");
+        assert_eq!(value(calculator.eval_line("y")), "1/3");
+        calculator.eval_line("mode integer");
+        assert_eq!(value(calculator.eval_line("x - 2^200")), "0");
+        assert!(matches!(
+            calculator.eval_line("y").outcome,
+            Err(LineError::Eval(CalcError::UndefinedVariable(..)))
+        ));
+        calculator.eval_line("z = -2^127");
+        calculator.eval_line("w = 2^128 - 1");
+        calculator.eval_line("mode u128");
+        assert!(value(calculator.eval_line("w")).starts_with(&u128::MAX.to_string()));
+        calculator.eval_line("mode integer");
+        assert_eq!(value(calculator.eval_line("w - 2^128")), "-1");
+        assert_eq!(value(calculator.eval_line("z + 2^127")), "0");
+    }
+
+    #[test]
+    fn test_integral_error() {
+        let mut calculator = Calculator::new();
+        let line = "integrate(sqrt(x), x, 0, 1)";
+        assert_eq!(
+            value(calculator.eval_line(line)),
+            "0.6666666666666388 ± 3.6e-11"
+        );
+        calculator.eval_line("format sci 1");
+        assert_eq!(value(calculator.eval_line(line)), "6.7e-1 ± 3.6e-11");
+        assert_eq!(
+            value(calculator.eval_line("integrate(2, x, 0, 3)")),
+            "6.0e0"
+        );
+    }
+
+    #[test]
+    fn test_commands_use_session_operators() {
+        use crate::registry::{Assoc, Operator};
+        let mut calculator = Calculator::new();
+        calculator.registry_mut().register_operator(Operator::infix(
+            "<>",
+            4,
+            Assoc::Left,
+            |a, b| (a - b).abs(),
+        ));
+        let mut eval = |line: &str| calculator.eval_line(line).outcome;
+        assert!(matches!(eval(":simplify x <> x"), Ok(Output::Value(text, _)) if text == "x <> x"));
+        assert!(
+            matches!(eval("solve x <> 2 = 3 for x in [0, 10]"), Ok(Output::Value(text, _)) if text == "5")
+        );
+        assert!(
+            matches!(eval("d/dx x^2 + (1 <> 3)"), Ok(Output::Value(text, _)) if text == "2 * x")
+        );
+        // The operator parses, but its derivative is unknown
+        assert!(matches!(
+            eval("d/dx x <> 2"),
+            Err(LineError::Eval(CalcError::NotDifferentiable(..)))
+        ));
+        assert!(matches!(
+            eval("plot x <> 2 from 0 <> 1 to 3"),
+            Ok(Output::Plot(_))
+        ));
+    }
+
+    #[test]
+    fn test_command_words_as_names() {
+        let mut calculator = Calculator::new();
+        let mut eval = |line: &str| value(calculator.eval_line(line));
+        assert_eq!(eval("format = 3"), "3");
+        assert_eq!(eval("angle = 2"), "2");
+        assert!(matches!(
+            calculator.eval_line("mode (x) = x + format").outcome,
+            Ok(Output::Defined(_))
+        ));
+        assert_eq!(value(calculator.eval_line("mode (angle) * 2")), "10");
+        assert!(matches!(
+            calculator.eval_line("angle degrees").outcome,
+            Ok(Output::Note(text)) if text == "angle degrees"
+        ));
+        assert!(matches!(
+            calculator.eval_line("format 3").outcome,
+            Err(LineError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn test_degrees() {
+        let mut calculator = Calculator::builder().angle(AngleUnit::Degrees).build();
+        let mut eval = |line: &str| value(calculator.eval_line(line));
+        assert_eq!(eval("sin(30) + cos(60)"), "1");
+        assert_eq!(eval("sin(-90) + cos(180) + tan(225)"), "-1");
+        assert_eq!(eval("asin(0.5) + atan(1)"), "75");
+        assert_eq!(eval("acos(-1)"), "180");
+        assert!(matches!(
+            calculator.eval_line("tan(90)").outcome,
+            Err(LineError::Eval(CalcError::Domain(what, _))) if what == "tan(90)"
+        ));
+        // The unit survives a change of mode, and exact modes stay exact
+        calculator.eval_line("mode rational");
+        assert!(matches!(
+            calculator.eval_line("sin(30)").outcome,
+            Err(LineError::Eval(CalcError::Inexact(..)))
+        ));
+        calculator.eval_line("mode complex");
+        assert_eq!(value(calculator.eval_line("sin(30) + acos(-1)")), "180.5");
+        let z = Complex::new(1.0, 1.0)
+            .scale(std::f64::consts::PI / 180.0)
+            .sin();
+        assert_eq!(value(calculator.eval_line("sin(1+i)")), z.to_string());
+        assert_eq!(value(calculator.eval_line("re(asin(2))")), "90");
+        calculator.eval_line("mode float");
+        assert_eq!(value(calculator.eval_line("cos(90)")), "0");
+        // Derivatives and Newton steps in degrees carry the pi / 180
+        assert_eq!(
+            value(calculator.eval_line("d/dx sin(x)")),
+            "pi / 180 * cos(x)"
+        );
+        let root = calculator
+            .eval_line("solve cos(x) = 1 for x in [-10, 20]")
+            .outcome;
+        assert!(matches!(root, Ok(Output::Value(_, x)) if x.abs() < 1e-5));
+        calculator.eval_line("angle radians");
+        assert_eq!(calculator.angle(), AngleUnit::Radians);
+        assert_eq!(value(calculator.eval_line("cos(0) + sin(0)")), "1");
+        assert_eq!(
+            value(calculator.eval_line("acos(-1)")),
+            std::f64::consts::PI.to_string()
+        );
+    }
+
+    #[test]
+    fn test_number_format() {
+        let mut calculator = Calculator::builder()
+            .output(OutputFormat {
+                numbers: Formatter {
+                    notation: Notation::Fixed(2),
+                    separator: Some(','),
+                },
+                ..OutputFormat::default()
+            })
+            .build();
+        assert_eq!(value(calculator.eval_line("1234567.891")), "1,234,567.89");
+        assert_eq!(
+            value(calculator.eval_line("solve x^2 = 2 for x in [0, 2]")),
+            "1.41"
+        );
+        // The history keeps every digit for `$n`
+        assert_eq!(
+            calculator.history()[1].value.as_deref(),
+            Some("1.414213562373095")
+        );
+        calculator.eval_line("format sig 15");
+        calculator.eval_line("grouping off");
+        assert_eq!(value(calculator.eval_line("0.1 + 0.2")), "0.3");
+        assert_eq!(value(calculator.eval_line("ans * 10^22")), "3e21");
+        calculator.eval_line("format eng 1");
+        assert_eq!(value(calculator.eval_line("4.7 / 100000")), "47.0µ");
+
+        calculator.eval_line("mode rational");
+        assert_eq!(value(calculator.eval_line("-22/7")), "-3.1");
+        calculator.eval_line("format fixed 3");
+        assert_eq!(value(calculator.eval_line("1/8 + 1")), "1.125");
+        calculator.eval_line("format auto");
+        calculator.eval_line("grouping _");
+        assert_eq!(value(calculator.eval_line("10^6 / 3")), "1_000_000/3");
+        calculator.eval_line("mode complex");
+        calculator.eval_line("format fixed 1");
+        assert_eq!(value(calculator.eval_line("2000 - 3i / 4")), "2_000.0-0.8i");
+        assert!(matches!(
+            calculator.eval_line("format sig 0").outcome,
+            Err(LineError::Usage(_))
+        ));
+    }
+
+    /// Lines of the listing `line` produced
+    fn listing(line: Line) -> Vec<String> {
+        match line.outcome {
+            Ok(Output::Listing(lines)) => lines,
+            other => panic!("expected a listing, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        let mut calculator = Calculator::new();
+        calculator.eval_line("x = 3");
+        calculator.eval_line("sq(a) = a * a");
+        assert_eq!(value(calculator.eval_line("sq($1) - $1")), "6");
+        assert_eq!(
+            listing(calculator.eval_line(":vars")),
+            ["ans = 6", "x = 3", "sq(a) = a * a"]
+        );
+        assert_eq!(
+            listing(calculator.eval_line(":history")),
+            [
+                "$1: x = 3 = 3",
+                "$2: sq(a) = a * a",
+                "$3: sq($1) - $1 = 6",
+                "$4: :vars",
+            ]
+        );
+        // Errors point at the line as typed
+        let line = calculator.eval_line("$3 / 0 + $2");
+        assert!(matches!(
+            line.outcome,
+            Err(LineError::Eval(CalcError::NoHistory(2, span))) if span == Span::new(9, 11)
+        ));
+        let line = calculator.eval_line("$3 / (x - $1)");
+        assert!(matches!(
+            line.outcome,
+            Err(LineError::Eval(CalcError::DivisionByZero(span))) if span == Span::new(5, 13)
+        ));
+        let line = calculator.eval_line(":histroy");
+        assert_eq!(
+            line.outcome.unwrap_err().render(":histroy"),
+            "Unknown command: :histroy (did you mean ':history'?)"
+        );
+        assert_eq!(
+            listing(calculator.eval_line(":mode"))[7..],
+            ["mode float", "mode infix"]
+        );
+        calculator.eval_line(":mode rpn");
+        assert_eq!(value(calculator.eval_line("$3 2 *")), "12");
+        calculator.eval_line(":reset");
+        assert!(calculator.rpn());
+        assert!(matches!(
+            calculator.eval_line(":vars").outcome,
+            Ok(Output::Note(text)) if text == "(no variables or functions)"
+        ));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("calc-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut calculator = Calculator::builder()
+            .mode(NumberMode::Programmer(IntType::I8))
+            .build();
+        calculator.eval_line("x = 0x80");
+        calculator.eval_line("f(n) = n ^ 3");
+        calculator.eval_line("mode rpn");
+        calculator.eval_line(&format!(":save {}", path));
+        let script = std::fs::read_to_string(path).unwrap();
+        assert_eq!(
+            script.lines().skip(1).collect::<Vec<_>>(),
+            [
+                "overflow error",
+                "policy strict",
+                "angle radians",
+                "display fraction",
+                "display rect",
+                "format auto",
+                "grouping off",
+                "mode i8",
+                "mode infix",
+                "x = 0x80",
+                "f(n) = n ^ 3",
+                "ans = 0x80",
+                "mode rpn",
+            ]
+        );
+
+        let mut loaded = Calculator::builder().mode(NumberMode::Complex).build();
+        loaded.eval_line("z = 1 + 2i");
+        assert!(matches!(
+            loaded.eval_line(&format!(":load {}", path)).outcome,
+            Ok(Output::Note(text)) if text.starts_with("loaded 13 lines")
+        ));
+        assert_eq!(loaded.mode(), NumberMode::Programmer(IntType::I8));
+        assert_eq!(loaded.script(), script);
+
+        std::fs::write(path, "mode complex\nmode infix\nz = 1+2i\n:load other\n").unwrap();
+        let line = loaded.eval_line(&format!(":load {}", path));
+        assert!(matches!(
+            line.outcome,
+            Err(LineError::File(message)) if message.ends_with("line 4: :load cannot be used in a loaded file")
+        ));
+        assert_eq!(value(loaded.eval_line("z * i")), "-2+i");
+        std::fs::remove_file(path).unwrap();
+        assert!(matches!(
+            loaded.eval_line(&format!(":load {}", path)).outcome,
+            Err(LineError::File(_))
+        ));
+    }
+
+    #[test]
+    fn test_line_json() {
+        let mut line = Calculator::new().eval_line("1 + 1");
+        line.elapsed = Some(Duration::from_nanos(2648));
+        assert_eq!(
+            line.to_json("1 + 1", Some(3)).to_string(),
+            r#"{"line":3,"input":"1 + 1","normalized":"1 + 1","result":"2","value":2,"eval_time_us":2.648}"#
+        );
+    }
+}
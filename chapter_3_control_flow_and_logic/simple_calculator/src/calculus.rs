@@ -0,0 +1,225 @@
+//! Definite integrals, sums and products over a bound variable
+//!
+//! In expressions these are `integrate(f, x, a, b)`, `sum(f, i, from, to)`
+//! and `prod(f, i, from, to)`: the second argument names the variable that
+//! runs over the range, and `f` is evaluated once per value rather than up
+//! front. The functions here take the body as a closure, so they also work
+//! on formulas that never went through the parser.
+
+use crate::number::Number;
+use crate::{CalcError, Op, Span};
+
+/// Names of the functions that bind a variable, in place of built-ins
+pub const BOUND_FORMS: &[&str] = &["integrate", "sum", "prod"];
+
+/// Error `integrate` aims for over the whole interval
+pub const TOLERANCE: f64 = 1e-10;
+
+/// Integrand evaluations `integrate` may spend before giving up
+pub const MAX_EVALUATIONS: usize = 100_000;
+
+/// Terms a `sum` or `prod` may have; in an expression, the limit on all of
+/// them together, so nested forms cannot multiply past it
+pub const MAX_TERMS: usize = 1_000_000;
+
+/// Halvings of one piece of the interval before `integrate` stops refining it
+const MAX_DEPTH: u32 = 50;
+
+/// Result of a numeric integration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Integral {
+    pub value: f64,
+    /// Estimated absolute error, at most `TOLERANCE` unless the integrand
+    /// has a singularity or a jump the refinement could not resolve
+    pub error: f64,
+    /// Number of times the integrand was evaluated
+    pub evaluations: usize,
+}
+
+/// One piece of the interval with its Simpson estimate
+struct Piece {
+    a: f64,
+    b: f64,
+    fa: f64,
+    fm: f64,
+    fb: f64,
+    estimate: f64,
+    tolerance: f64,
+    depth: u32,
+}
+
+fn simpson(a: f64, b: f64, fa: f64, fm: f64, fb: f64) -> f64 {
+    (b - a) / 6.0 * (fa + 4.0 * fm + fb)
+}
+
+/// Integral of `f` from `a` to `b` by adaptive Simpson's rule
+///
+/// Each piece is halved until the two halves agree with the whole to within
+/// its share of `TOLERANCE`. Fails with `NoConvergence` after
+/// `MAX_EVALUATIONS` evaluations of `f`; errors from `f` are passed on.
+pub fn integrate(
+    mut f: impl FnMut(f64) -> Result<f64, CalcError>,
+    a: f64,
+    b: f64,
+) -> Result<Integral, CalcError> {
+    let m = a + (b - a) / 2.0;
+    let (fa, fm, fb) = (f(a)?, f(m)?, f(b)?);
+    let mut evaluations = 3;
+    let mut pieces = vec![Piece {
+        a,
+        b,
+        fa,
+        fm,
+        fb,
+        estimate: simpson(a, b, fa, fm, fb),
+        tolerance: TOLERANCE,
+        depth: 0,
+    }];
+    let (mut value, mut error) = (0.0, 0.0);
+    while let Some(p) = pieces.pop() {
+        if evaluations >= MAX_EVALUATIONS {
+            return Err(CalcError::NoConvergence(
+                "integrate".to_string(),
+                evaluations,
+                Span::default(),
+            ));
+        }
+        let m = p.a + (p.b - p.a) / 2.0;
+        let (lm, rm) = (p.a + (m - p.a) / 2.0, m + (p.b - m) / 2.0);
+        let (flm, frm) = (f(lm)?, f(rm)?);
+        evaluations += 2;
+        let left = simpson(p.a, m, p.fa, flm, p.fm);
+        let right = simpson(m, p.b, p.fm, frm, p.fb);
+        let delta = left + right - p.estimate;
+        if delta.abs() <= 15.0 * p.tolerance || p.depth >= MAX_DEPTH || !delta.is_finite() {
+            // Richardson extrapolation of the two estimates
+            value += left + right + delta / 15.0;
+            error += delta.abs() / 15.0;
+            continue;
+        }
+        let (tolerance, depth) = (p.tolerance / 2.0, p.depth + 1);
+        pieces.push(Piece {
+            a: p.a,
+            b: m,
+            fa: p.fa,
+            fm: flm,
+            fb: p.fm,
+            estimate: left,
+            tolerance,
+            depth,
+        });
+        pieces.push(Piece {
+            a: m,
+            b: p.b,
+            fa: p.fm,
+            fm: frm,
+            fb: p.fb,
+            estimate: right,
+            tolerance,
+            depth,
+        });
+    }
+    Ok(Integral {
+        value,
+        error,
+        evaluations,
+    })
+}
+
+/// `f(from) + f(from + 1) + ... + f(to)`, or 0 when `to < from`
+pub fn sum<N: Number>(
+    f: impl FnMut(N) -> Result<N, CalcError>,
+    from: i64,
+    to: i64,
+) -> Result<N, CalcError> {
+    fold("sum", Op::Add, 0.0, f, from, to)
+}
+
+/// `f(from) * f(from + 1) * ... * f(to)`, or 1 when `to < from`
+pub fn product<N: Number>(
+    f: impl FnMut(N) -> Result<N, CalcError>,
+    from: i64,
+    to: i64,
+) -> Result<N, CalcError> {
+    fold("prod", Op::Mul, 1.0, f, from, to)
+}
+
+/// Combine `f(i)` for every `i` in `from..=to` with `op`, starting from
+/// `identity`
+fn fold<N: Number>(
+    name: &str,
+    op: Op,
+    identity: f64,
+    mut f: impl FnMut(N) -> Result<N, CalcError>,
+    from: i64,
+    to: i64,
+) -> Result<N, CalcError> {
+    let whole = |n: f64| N::from_f64(n).ok_or(CalcError::TooLarge(Span::default()));
+    let terms = i128::from(to) - i128::from(from) + 1;
+    if terms > MAX_TERMS as i128 {
+        return Err(CalcError::IterationLimit(
+            name.to_string(),
+            MAX_TERMS,
+            Span::default(),
+        ));
+    }
+    let mut acc = whole(identity)?;
+    for i in from..=to {
+        // Exact for every index under the term limit
+        acc = N::apply(op, &acc, &f(whole(i as f64)?)?)?;
+    }
+    Ok(acc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rational;
+
+    #[test]
+    fn test_integrate() {
+        let result = integrate(|x| Ok(x * x), 0.0, 3.0).unwrap();
+        assert!((result.value - 9.0).abs() < 1e-12);
+        let result = integrate(|x| Ok(x.sin()), 0.0, std::f64::consts::PI).unwrap();
+        assert!((result.value - 2.0).abs() < 1e-10);
+        assert!(result.error <= TOLERANCE);
+        // Reversed bounds flip the sign
+        let result = integrate(|x| Ok(x.exp()), 1.0, 0.0).unwrap();
+        assert!((result.value + std::f64::consts::E - 1.0).abs() < 1e-10);
+        // A sharp peak needs many more evaluations than a smooth curve
+        let peak = integrate(|x| Ok(1.0 / (1e-4 + x * x)), -1.0, 1.0).unwrap();
+        assert!((peak.value - 2.0 * 100.0 * (100.0f64).atan()).abs() < 1e-6);
+        assert!(peak.evaluations > 10 * result.evaluations);
+    }
+
+    #[test]
+    fn test_integrate_budget() {
+        // An oscillation too fast to resolve never settles
+        let err = integrate(|x| Ok((1.0 / x).sin()), 1e-12, 1.0).unwrap_err();
+        assert!(matches!(err, CalcError::NoConvergence(_, n, _) if n >= MAX_EVALUATIONS));
+        assert!(matches!(
+            integrate(
+                |_| Err(CalcError::DivisionByZero(Span::default())),
+                0.0,
+                1.0
+            ),
+            Err(CalcError::DivisionByZero(_))
+        ));
+    }
+
+    #[test]
+    fn test_sum_and_product() {
+        assert_eq!(sum(|i: f64| Ok(i * i), 1, 10).unwrap(), 385.0);
+        assert_eq!(product(|i: f64| Ok(i), 1, 5).unwrap(), 120.0);
+        assert_eq!(sum(|i: f64| Ok(i), 5, 1).unwrap(), 0.0);
+        assert_eq!(product(|i: f64| Ok(i), 5, 1).unwrap(), 1.0);
+        // Exact in rational mode
+        let one = Rational::from_integer(1.into());
+        let harmonic = sum(|i: Rational| Rational::apply(Op::Div, &one, &i), 1, 4).unwrap();
+        assert_eq!(harmonic.to_string(), "25/12");
+        assert!(matches!(
+            sum(|i: f64| Ok(i), 0, i64::MAX),
+            Err(CalcError::IterationLimit(..))
+        ));
+    }
+}
@@ -0,0 +1,238 @@
+//! Symbolic differentiation of expression trees
+//!
+//! `derive` applies the sum, product, quotient, power and chain rules, and
+//! treats every other variable as a constant, so it also gives the partial
+//! derivatives of a gradient. Functions that are not smooth, like `floor`,
+//! `min` or `%`, and user functions and custom operators, which the tree does
+//! not define, are reported as `NotDifferentiable` unless they do not depend
+//! on the variable. With angles in degrees, the trigonometric functions and
+//! their inverses pick up a factor of `pi / 180` or `180 / pi`.
+
+use crate::ast::{Expr, ExprKind};
+use crate::functions::{ANGLE_ARGUMENT, ANGLE_RESULT};
+use crate::simplify::simplify;
+use crate::{AngleUnit, CalcError, EvalPolicy, Op};
+
+/// Derivative of `expr` with respect to `var`, simplified for finite
+/// variables, where the derivative is defined
+pub fn derive(expr: &Expr, var: &str) -> Result<Expr, CalcError> {
+    derive_in(expr, var, AngleUnit::Radians)
+}
+
+/// `derive` for trigonometric functions working in `angle`
+pub fn derive_in(expr: &Expr, var: &str, angle: AngleUnit) -> Result<Expr, CalcError> {
+    Ok(simplify(
+        &differentiate(expr, var, angle)?,
+        EvalPolicy::Strict,
+    ))
+}
+
+/// Whether `expr` mentions variable `var`
+fn depends_on(expr: &Expr, var: &str) -> bool {
+    match &expr.kind {
+        ExprKind::Number(_) => false,
+        ExprKind::Var(name) => name == var,
+        ExprKind::Unary(_, operand) | ExprKind::Factorial(operand) => depends_on(operand, var),
+        ExprKind::Binary(_, lhs, rhs) => depends_on(lhs, var) || depends_on(rhs, var),
+        ExprKind::Call(_, args) | ExprKind::Operator(_, args) => {
+            args.iter().any(|arg| depends_on(arg, var))
+        }
+    }
+}
+
+fn num(value: f64) -> Expr {
+    Expr::number(value)
+}
+
+fn add(a: Expr, b: Expr) -> Expr {
+    Expr::binary(Op::Add, a, b)
+}
+
+fn sub(a: Expr, b: Expr) -> Expr {
+    Expr::binary(Op::Sub, a, b)
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    Expr::binary(Op::Mul, a, b)
+}
+
+fn div(a: Expr, b: Expr) -> Expr {
+    Expr::binary(Op::Div, a, b)
+}
+
+fn pow(a: Expr, b: Expr) -> Expr {
+    Expr::binary(Op::Pow, a, b)
+}
+
+fn call(name: &str, arg: Expr) -> Expr {
+    Expr::call(name, vec![arg])
+}
+
+/// Unsimplified derivative of `expr`
+fn differentiate(expr: &Expr, var: &str, angle: AngleUnit) -> Result<Expr, CalcError> {
+    if !depends_on(expr, var) {
+        return Ok(num(0.0));
+    }
+    let d = |e: &Expr| differentiate(e, var, angle);
+    let not_differentiable = || CalcError::NotDifferentiable(expr.to_string(), expr.span);
+    Ok(match &expr.kind {
+        // Anything else is constant and handled above
+        ExprKind::Var(_) => num(1.0),
+        ExprKind::Unary(Op::Sub, u) => Expr::unary(Op::Sub, d(u)?),
+        ExprKind::Unary(Op::Add, u) => d(u)?,
+        ExprKind::Unary(..) => return Err(not_differentiable()),
+        ExprKind::Binary(op, u, v) => {
+            let (u, v) = (&**u, &**v);
+            match op {
+                Op::Add => add(d(u)?, d(v)?),
+                Op::Sub => sub(d(u)?, d(v)?),
+                Op::Mul => add(mul(d(u)?, v.clone()), mul(u.clone(), d(v)?)),
+                Op::Div => div(
+                    sub(mul(d(u)?, v.clone()), mul(u.clone(), d(v)?)),
+                    pow(v.clone(), num(2.0)),
+                ),
+                // u^n: n * u^(n - 1) * u'
+                Op::Pow if !depends_on(v, var) => mul(
+                    mul(v.clone(), pow(u.clone(), sub(v.clone(), num(1.0)))),
+                    d(u)?,
+                ),
+                // a^v: a^v * ln(a) * v'
+                Op::Pow if !depends_on(u, var) => {
+                    mul(mul(expr.clone(), call("ln", u.clone())), d(v)?)
+                }
+                // u^v: u^v * (v' * ln(u) + v * u' / u)
+                Op::Pow => mul(
+                    expr.clone(),
+                    add(
+                        mul(d(v)?, call("ln", u.clone())),
+                        div(mul(v.clone(), d(u)?), u.clone()),
+                    ),
+                ),
+                // Integer and bitwise operators have no derivative
+                _ => return Err(not_differentiable()),
+            }
+        }
+        ExprKind::Call(name, args) => match (name.as_str(), args.as_slice()) {
+            // log(b, u) = ln(u) / ln(b)
+            ("log", [base, u]) => {
+                let ln_ratio = div(call("ln", u.clone()), call("ln", base.clone()));
+                return differentiate(&ln_ratio, var, angle);
+            }
+            (name, [u]) => match outer_derivative(name, u, angle) {
+                Some(outer) => mul(outer, d(u)?),
+                None => return Err(not_differentiable()),
+            },
+            _ => return Err(not_differentiable()),
+        },
+        ExprKind::Operator(..) => return Err(not_differentiable()),
+        ExprKind::Factorial(_) => return Err(not_differentiable()),
+        ExprKind::Number(_) => num(0.0),
+    })
+}
+
+/// Derivative of built-in `name` evaluated at `u`, for the chain rule
+fn outer_derivative(name: &str, u: &Expr, angle: AngleUnit) -> Option<Expr> {
+    let radians = outer_radians(name, u)?;
+    let pi = || Expr::var("pi");
+    Some(match angle {
+        AngleUnit::Degrees if ANGLE_ARGUMENT.contains(&name) => mul(div(pi(), num(180.0)), radians),
+        AngleUnit::Degrees if ANGLE_RESULT.contains(&name) => mul(div(num(180.0), pi()), radians),
+        _ => radians,
+    })
+}
+
+/// `outer_derivative` with angles in radians
+fn outer_radians(name: &str, u: &Expr) -> Option<Expr> {
+    let u = || u.clone();
+    Some(match name {
+        "sqrt" => div(num(1.0), mul(num(2.0), call("sqrt", u()))),
+        "cbrt" => div(num(1.0), mul(num(3.0), pow(call("cbrt", u()), num(2.0)))),
+        "ln" => div(num(1.0), u()),
+        "log10" => div(num(1.0), mul(u(), call("ln", num(10.0)))),
+        "exp" => call("exp", u()),
+        "sin" => call("cos", u()),
+        "cos" => Expr::unary(Op::Sub, call("sin", u())),
+        "tan" => div(num(1.0), pow(call("cos", u()), num(2.0))),
+        "asin" => div(num(1.0), call("sqrt", sub(num(1.0), pow(u(), num(2.0))))),
+        "acos" => Expr::unary(
+            Op::Sub,
+            div(num(1.0), call("sqrt", sub(num(1.0), pow(u(), num(2.0))))),
+        ),
+        "atan" => div(num(1.0), add(num(1.0), pow(u(), num(2.0)))),
+        // Undefined at 0, where u / abs(u) divides by zero
+        "abs" => div(u(), call("abs", u())),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, evaluate, parse};
+
+    fn derived(input: &str) -> String {
+        derive(&parse(input).unwrap(), "x").unwrap().to_string()
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(derived("x^3 + sin(x)"), "3 * x ^ 2 + cos(x)");
+        assert_eq!(derived("5 * x - 7"), "5");
+        assert_eq!(derived("a * x^2 + b * x + c"), "2 * a * x + b");
+        assert_eq!(derived("1 / x"), "-1 / x ^ 2");
+        assert_eq!(derived("exp(2 * x)"), "2 * exp(2 * x)");
+        assert_eq!(derived("ln(x)"), "1 / x");
+        assert_eq!(derived("2 ^ x"), "2 ^ x * ln(2)");
+        assert_eq!(derived("y ^ 2"), "0");
+    }
+
+    #[test]
+    fn test_not_differentiable() {
+        let err = derive(&parse("1 + floor(x)").unwrap(), "x").unwrap_err();
+        assert_eq!(err.to_string(), "cannot differentiate floor(x)");
+        assert_eq!(err.span(), crate::Span::new(4, 12));
+        assert!(derive(&parse("x % 2").unwrap(), "x").is_err());
+        assert!(derive(&parse("f(x)").unwrap(), "x").is_err());
+        // Constant in x, so the rule is never needed
+        assert_eq!(derived("floor(y) * x"), "floor(y)");
+    }
+
+    /// Derivatives agree with central differences
+    #[test]
+    fn test_matches_finite_differences() {
+        let inputs = [
+            "x^3 - 2*x",
+            "sin(x) * cos(x) / x",
+            "sqrt(x^2 + 1) + cbrt(x)",
+            "ln(x) * log10(x) + log(2, x)",
+            "tan(x) + asin(x / 4) - acos(x / 5) + atan(x)",
+            "x ^ x + 2 ^ (x * y)",
+            "abs(x - 3) * exp(-x)",
+            "-(x / (1 + x^2)) ^ 3",
+        ];
+        let mut env = Environment::new();
+        env.set("y", 0.5);
+        for input in inputs {
+            let expr = parse(input).unwrap();
+            let derivative = derive(&expr, "x").unwrap();
+            for x in [0.3, 1.1, 2.0] {
+                let h = 1e-6;
+                let at = |x: f64, e: &Expr, env: &mut Environment| {
+                    env.set("x", x);
+                    evaluate(e, env).unwrap()
+                };
+                let numeric = (at(x + h, &expr, &mut env) - at(x - h, &expr, &mut env)) / (2.0 * h);
+                let exact = at(x, &derivative, &mut env);
+                assert!(
+                    (numeric - exact).abs() < 1e-6 * exact.abs().max(1.0),
+                    "d/dx {} at {}: {} vs {} from {}",
+                    input,
+                    x,
+                    numeric,
+                    exact,
+                    derivative
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Evaluation environment: named values that live for a whole session
+
+use crate::calculus::MAX_TERMS;
+use crate::error::closest_match;
+use crate::eval::{AngleUnit, EvalPolicy};
+use crate::functions::{BUILTINS, CONSTANTS, IMAGINARY_UNIT, constant};
+use crate::number::{self, Number};
+use crate::registry::Registry;
+use crate::{CalcError, Expr, Span};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Name the result of the last evaluated line is bound to
+pub const ANS: &str = "ans";
+
+/// How deeply user functions may call each other before evaluation gives up
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+
+/// A function defined with `name(params) = body`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFunction {
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+/// Variables and user functions visible to `evaluate`, with variables held
+/// in number type `N`
+#[derive(Debug, Clone)]
+pub struct Environment<N: Number = f64> {
+    vars: BTreeMap<String, N>,
+    functions: BTreeMap<String, UserFunction>,
+    max_depth: usize,
+    policy: EvalPolicy,
+    angle: AngleUnit,
+    registry: Arc<Registry>,
+    context: N::Context,
+    /// `sum` and `prod` terms the evaluation in progress has used
+    terms: Cell<usize>,
+}
+
+impl<N: Number> Default for Environment<N> {
+    fn default() -> Environment<N> {
+        Environment {
+            vars: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            policy: EvalPolicy::default(),
+            angle: AngleUnit::default(),
+            registry: Arc::new(Registry::default()),
+            context: N::Context::default(),
+            terms: Cell::new(0),
+        }
+    }
+}
+
+impl<N: Number> Environment<N> {
+    pub fn new() -> Environment<N> {
+        Environment::default()
+    }
+
+    /// The same session in number type `M`: functions, limits, the policy, the
+    /// angle unit and the registry carry over, variables convert as `number::convert` does and
+    /// are dropped if `M` cannot hold them, and the context is `M`'s default
+    pub fn convert<M: Number>(&self) -> Environment<M> {
+        Environment {
+            vars: self
+                .vars
+                .iter()
+                .filter_map(|(name, value)| Some((name.clone(), number::convert(value)?)))
+                .collect(),
+            functions: self.functions.clone(),
+            max_depth: self.max_depth,
+            policy: self.policy,
+            angle: self.angle,
+            registry: Arc::clone(&self.registry),
+            context: M::Context::default(),
+            terms: Cell::new(0),
+        }
+    }
+
+    /// Settings for values made from literals, such as the integer type in
+    /// programmer mode
+    pub fn context(&self) -> &N::Context {
+        &self.context
+    }
+
+    pub fn set_context(&mut self, context: N::Context) {
+        self.context = context;
+    }
+
+    /// Operators and extra functions; parse input for this environment with
+    /// the same registry
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// The registry, to add operators and functions to
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        Arc::make_mut(&mut self.registry)
+    }
+
+    /// Give the next evaluation the whole `MAX_TERMS` budget
+    pub(crate) fn reset_terms(&self) {
+        self.terms.set(0);
+    }
+
+    /// Spend `count` terms of a `sum` or `prod` called `name` from the budget
+    /// every form in one evaluation shares
+    pub(crate) fn spend_terms(&self, name: &str, count: usize) -> Result<(), CalcError> {
+        let spent = self.terms.get().saturating_add(count);
+        if spent > MAX_TERMS {
+            return Err(CalcError::IterationLimit(
+                name.to_string(),
+                MAX_TERMS,
+                Span::default(),
+            ));
+        }
+        self.terms.set(spent);
+        Ok(())
+    }
+
+    /// Limit on nested user function calls; deeper recursion is a `RecursionLimit` error
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Whether NaN and infinite results are errors or pass through
+    pub fn policy(&self) -> EvalPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: EvalPolicy) {
+        self.policy = policy;
+    }
+
+    /// Unit of the angles the trigonometric built-ins take and return
+    pub fn angle(&self) -> AngleUnit {
+        self.angle
+    }
+
+    pub fn set_angle(&mut self, angle: AngleUnit) {
+        self.angle = angle;
+    }
+
+    pub fn get(&self, name: &str) -> Option<&N> {
+        self.vars.get(name)
+    }
+
+    pub fn set(&mut self, name: impl Into<String>, value: N) {
+        self.vars.insert(name.into(), value);
+    }
+
+    /// Value of `name`, falling back to the built-in constants, or an
+    /// `UndefinedVariable` error at `span` suggesting a similarly spelled name
+    pub fn lookup(&self, name: &str, span: Span) -> Result<N, CalcError> {
+        if let Some(value) = self.get(name) {
+            return Ok(value.clone());
+        }
+        if let Some(approx) = constant(name) {
+            return N::constant(name, approx).map_err(|e| e.at(span));
+        }
+        if name == IMAGINARY_UNIT {
+            return N::imaginary(1.0).map_err(|e| e.at(span));
+        }
+        let names = self.vars.keys().map(String::as_str);
+        let suggestion = closest_match(name, names.chain(CONSTANTS.iter().map(|(n, _)| *n)));
+        Err(CalcError::UndefinedVariable(
+            name.to_string(),
+            suggestion.map(str::to_string),
+            span,
+        ))
+    }
+
+    /// Variables in name order
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &N)> {
+        self.vars.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Define or replace user function `name`
+    pub fn define(&mut self, name: impl Into<String>, params: Vec<String>, body: Expr) {
+        self.functions
+            .insert(name.into(), UserFunction { params, body });
+    }
+
+    pub fn function(&self, name: &str) -> Option<&UserFunction> {
+        self.functions.get(name)
+    }
+
+    /// User functions in name order
+    pub fn functions(&self) -> impl Iterator<Item = (&str, &UserFunction)> {
+        self.functions.iter().map(|(name, f)| (name.as_str(), f))
+    }
+
+    /// Forget every variable and user function, keeping the limits, policy,
+    /// angle unit, registry and context
+    pub fn clear(&mut self) {
+        self.vars.clear();
+        self.functions.clear();
+    }
+
+    /// `UnknownFunction` error for `name`, suggesting a similarly spelled user,
+    /// registered or built-in function
+    pub fn unknown_function(&self, name: &str, span: Span) -> CalcError {
+        let names = self
+            .functions
+            .keys()
+            .map(String::as_str)
+            .chain(self.registry.functions().map(|f| f.name.as_str()));
+        let suggestion = closest_match(name, names.chain(BUILTINS.iter().map(|b| b.name)));
+        CalcError::UnknownFunction(name.to_string(), suggestion.map(str::to_string), span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_lookup() {
+        let mut env = Environment::new();
+        env.set("x", 4.2);
+        env.set("x", 5.0);
+        assert_eq!(env.lookup("x", Span::default()).unwrap(), 5.0);
+        assert_eq!(env.variables().collect::<Vec<_>>(), vec![("x", &5.0)]);
+    }
+
+    #[test]
+    fn test_undefined_variable_suggestion() {
+        let mut env: Environment = Environment::new();
+        env.set("width", 3.0);
+        env.set("height", 4.0);
+        let err = env.lookup("widht", Span::new(0, 5)).unwrap_err();
+        assert!(matches!(
+            &err,
+            CalcError::UndefinedVariable(name, Some(s), _) if name == "widht" && s == "width"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "undefined variable: widht (did you mean 'width'?)"
+        );
+        assert!(matches!(
+            env.lookup("depth_of_field", Span::default()),
+            Err(CalcError::UndefinedVariable(_, None, _))
+        ));
+    }
+
+    #[test]
+    fn test_convert_between_modes() {
+        use crate::number::Rational;
+        let mut env: Environment = Environment::new();
+        env.set("x", 0.1);
+        env.set("big", f64::INFINITY);
+        let exact: Environment<Rational> = env.convert();
+        assert_eq!(exact.get("x").unwrap().to_string(), "1/10");
+        assert!(exact.get("big").is_none());
+        assert!(matches!(
+            exact.lookup("pi", Span::new(0, 2)),
+            Err(CalcError::Inexact(..))
+        ));
+    }
+
+    #[test]
+    fn test_convert_keeps_exact_values() {
+        use crate::number::{BigInt, Rational};
+        use crate::{execute, parse_statement};
+        let mut exact: Environment<Rational> = Environment::new();
+        for line in ["big = 2^200 + 1", "third = 1/3"] {
+            execute(&parse_statement(line).unwrap(), &mut exact).unwrap();
+        }
+        let integer: Environment<BigInt> = exact.convert();
+        assert_eq!(
+            integer.get("big").unwrap().to_string(),
+            "1606938044258990275541962092341162602522202993782792835301377"
+        );
+        assert!(integer.get("third").is_none());
+        let back: Environment<Rational> = integer.convert();
+        assert_eq!(back.get("big"), exact.get("big"));
+    }
+}
@@ -0,0 +1,367 @@
+//! Error type shared by the lexer, parser and evaluator
+//!
+//! Every error carries the byte span of the input it refers to, and `render`
+//! underlines that span under the input line.
+
+use crate::functions::Arity;
+use crate::lexer::Span;
+use std::fmt;
+use std::num::{ParseFloatError, ParseIntError};
+
+/// Error type for parse/eval issues
+#[derive(Debug)]
+pub enum CalcError {
+    /// A number literal that did not parse, with its text
+    ParseFloat(String, ParseFloatError, Span),
+    /// A number literal whose `e` has no exponent digits, such as `1e` or
+    /// `2e+`: the literal text, pointing at the `e`
+    MalformedExponent(String, Span),
+    /// A `0x`, `0o` or `0b` literal with a bad digit or too many of them
+    ParseInt(String, ParseIntError, Span),
+    UnknownOperator(String, Span),
+    WrongArity(Span),
+    DivisionByZero(Span),
+    UnexpectedToken(String, Span),
+    UnexpectedEnd(Span),
+    /// An expression nested deeper than `parser::MAX_NESTING`
+    TooDeep(Span),
+    /// A name with no value, and the closest defined name if one is similar
+    UndefinedVariable(String, Option<String>, Span),
+    /// A call to a name that is not a function, and the closest function name
+    UnknownFunction(String, Option<String>, Span),
+    /// A function called with the wrong number of arguments: name, accepted, given
+    FunctionArity(String, Arity, usize, Span),
+    /// User function calls nested deeper than the environment allows: name, limit
+    RecursionLimit(String, usize, Span),
+    /// A parameter name listed twice in a function definition
+    DuplicateParameter(String, Span),
+    /// A result the current number mode cannot represent exactly: what, mode
+    Inexact(String, &'static str, Span),
+    /// An exact result too large to compute
+    TooLarge(Span),
+    /// An imaginary number outside complex mode
+    Imaginary(Span),
+    /// An operation that needs real operands applied to a non-real value
+    NotReal(String, Span),
+    /// An RPN operation given fewer values than it pops: name, needed, available
+    StackUnderflow(String, usize, usize, Span),
+    /// A sub-expression with no derivative rule, such as `floor(x)`
+    NotDifferentiable(String, Span),
+    /// An iterative method that gave up: what was being computed, iterations
+    NoConvergence(String, usize, Span),
+    /// A loop that would run longer than allowed: what, the limit
+    IterationLimit(String, usize, Span),
+    /// A value that must be a whole number, such as the bounds of a sum
+    NotInteger(String, Span),
+    /// A result outside the range of its type, such as `10 ^ 400` in float
+    /// mode: what, the type or mode
+    Overflow(String, &'static str, Span),
+    /// An operation the current number mode does not have: what, mode
+    Unsupported(String, &'static str, Span),
+    /// An operation outside its domain, such as `sqrt(-1)` or `ln(0)` in
+    /// float mode
+    Domain(String, Span),
+    /// A NaN or infinite value reaching a strict evaluation
+    NotANumber(String, Span),
+    /// A `$n` history reference to a line that is gone or gave no value
+    NoHistory(usize, Span),
+}
+
+impl CalcError {
+    /// Byte span of the input the error points at
+    pub fn span(&self) -> Span {
+        match self {
+            CalcError::ParseFloat(_, _, span)
+            | CalcError::MalformedExponent(_, span)
+            | CalcError::ParseInt(_, _, span)
+            | CalcError::UnknownOperator(_, span)
+            | CalcError::WrongArity(span)
+            | CalcError::DivisionByZero(span)
+            | CalcError::UnexpectedToken(_, span)
+            | CalcError::UnexpectedEnd(span)
+            | CalcError::TooDeep(span)
+            | CalcError::UndefinedVariable(_, _, span)
+            | CalcError::UnknownFunction(_, _, span)
+            | CalcError::FunctionArity(_, _, _, span)
+            | CalcError::RecursionLimit(_, _, span)
+            | CalcError::DuplicateParameter(_, span)
+            | CalcError::Inexact(_, _, span)
+            | CalcError::TooLarge(span)
+            | CalcError::Imaginary(span)
+            | CalcError::NotReal(_, span)
+            | CalcError::StackUnderflow(_, _, _, span)
+            | CalcError::NotDifferentiable(_, span)
+            | CalcError::NoConvergence(_, _, span)
+            | CalcError::IterationLimit(_, _, span)
+            | CalcError::NotInteger(_, span)
+            | CalcError::Overflow(_, _, span)
+            | CalcError::Unsupported(_, _, span)
+            | CalcError::Domain(_, span)
+            | CalcError::NotANumber(_, span)
+            | CalcError::NoHistory(_, span) => *span,
+        }
+    }
+
+    /// Stable snake_case name of the variant, for machine-readable output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CalcError::ParseFloat(..) => "parse_float",
+            CalcError::MalformedExponent(..) => "malformed_exponent",
+            CalcError::ParseInt(..) => "parse_int",
+            CalcError::UnknownOperator(..) => "unknown_operator",
+            CalcError::WrongArity(..) => "wrong_arity",
+            CalcError::DivisionByZero(..) => "division_by_zero",
+            CalcError::UnexpectedToken(..) => "unexpected_token",
+            CalcError::UnexpectedEnd(..) => "unexpected_end",
+            CalcError::TooDeep(..) => "too_deep",
+            CalcError::UndefinedVariable(..) => "undefined_variable",
+            CalcError::UnknownFunction(..) => "unknown_function",
+            CalcError::FunctionArity(..) => "function_arity",
+            CalcError::RecursionLimit(..) => "recursion_limit",
+            CalcError::DuplicateParameter(..) => "duplicate_parameter",
+            CalcError::Inexact(..) => "inexact",
+            CalcError::TooLarge(..) => "too_large",
+            CalcError::Imaginary(..) => "imaginary",
+            CalcError::NotReal(..) => "not_real",
+            CalcError::StackUnderflow(..) => "stack_underflow",
+            CalcError::NotDifferentiable(..) => "not_differentiable",
+            CalcError::NoConvergence(..) => "no_convergence",
+            CalcError::IterationLimit(..) => "iteration_limit",
+            CalcError::NotInteger(..) => "not_integer",
+            CalcError::Overflow(..) => "overflow",
+            CalcError::Unsupported(..) => "unsupported",
+            CalcError::Domain(..) => "domain",
+            CalcError::NotANumber(..) => "not_a_number",
+            CalcError::NoHistory(..) => "no_history",
+        }
+    }
+
+    /// The same error pointing at `span` instead
+    pub fn at(mut self, new_span: Span) -> CalcError {
+        match &mut self {
+            CalcError::ParseFloat(_, _, span)
+            | CalcError::MalformedExponent(_, span)
+            | CalcError::ParseInt(_, _, span)
+            | CalcError::UnknownOperator(_, span)
+            | CalcError::WrongArity(span)
+            | CalcError::DivisionByZero(span)
+            | CalcError::UnexpectedToken(_, span)
+            | CalcError::UnexpectedEnd(span)
+            | CalcError::TooDeep(span)
+            | CalcError::UndefinedVariable(_, _, span)
+            | CalcError::UnknownFunction(_, _, span)
+            | CalcError::FunctionArity(_, _, _, span)
+            | CalcError::RecursionLimit(_, _, span)
+            | CalcError::DuplicateParameter(_, span)
+            | CalcError::Inexact(_, _, span)
+            | CalcError::TooLarge(span)
+            | CalcError::Imaginary(span)
+            | CalcError::NotReal(_, span)
+            | CalcError::StackUnderflow(_, _, _, span)
+            | CalcError::NotDifferentiable(_, span)
+            | CalcError::NoConvergence(_, _, span)
+            | CalcError::IterationLimit(_, _, span)
+            | CalcError::NotInteger(_, span)
+            | CalcError::Overflow(_, _, span)
+            | CalcError::Unsupported(_, _, span)
+            | CalcError::Domain(_, span)
+            | CalcError::NotANumber(_, span)
+            | CalcError::NoHistory(_, span) => *span = new_span,
+        }
+        self
+    }
+
+    /// Message followed by `source` with the offending part underlined, e.g.
+    ///
+    /// ```text
+    /// division by zero
+    ///   10 / (2 - 2)
+    ///        ^~~~~~~
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let span = self.span();
+        let start = span.start.min(source.len());
+        let end = span.end.clamp(start, source.len());
+        let column = source[..start].chars().count();
+        let width = source[start..end].chars().count().max(1);
+        format!(
+            "{}\n  {}\n  {}^{}",
+            self,
+            source,
+            " ".repeat(column),
+            "~".repeat(width - 1)
+        )
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::ParseFloat(text, e, _) => {
+                write!(f, "number parse error: '{}': {}", text, e)
+            }
+            CalcError::MalformedExponent(text, _) => {
+                write!(f, "malformed exponent in '{}'", text)
+            }
+            CalcError::ParseInt(text, e, _) => {
+                write!(f, "number parse error: '{}': {}", text, e)
+            }
+            CalcError::UnknownOperator(op, _) => write!(f, "unknown operator: {}", op),
+            CalcError::WrongArity(_) => write!(f, "wrong number of operands"),
+            CalcError::DivisionByZero(_) => write!(f, "division by zero"),
+            CalcError::UnexpectedToken(tok, _) => write!(f, "unexpected token: {}", tok),
+            CalcError::UnexpectedEnd(_) => write!(f, "unexpected end of expression"),
+            CalcError::TooDeep(_) => write!(f, "expression nested too deeply"),
+            CalcError::UndefinedVariable(name, suggestion, _) => {
+                write!(f, "undefined variable: {}", name)?;
+                write_suggestion(f, suggestion)
+            }
+            CalcError::UnknownFunction(name, suggestion, _) => {
+                write!(f, "unknown function: {}", name)?;
+                write_suggestion(f, suggestion)
+            }
+            CalcError::FunctionArity(name, arity, given, _) => {
+                write!(f, "{} expects {}, got {}", name, arity, given)
+            }
+            CalcError::RecursionLimit(name, limit, _) => {
+                write!(f, "{} recursed deeper than {} calls", name, limit)
+            }
+            CalcError::DuplicateParameter(name, _) => {
+                write!(f, "parameter {} is listed more than once", name)
+            }
+            CalcError::Inexact(what, mode, _) => {
+                write!(f, "{} has no exact value in {} mode", what, mode)
+            }
+            CalcError::TooLarge(_) => write!(f, "result is too large to represent exactly"),
+            CalcError::Imaginary(_) => write!(f, "imaginary numbers need complex mode"),
+            CalcError::NotReal(what, _) => {
+                write!(f, "{} is only defined for real numbers", what)
+            }
+            CalcError::StackUnderflow(name, needed, available, _) => {
+                let plural = if *needed == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "stack underflow: {} needs {} value{}, the stack has {}",
+                    name, needed, plural, available
+                )
+            }
+            CalcError::NotDifferentiable(what, _) => write!(f, "cannot differentiate {}", what),
+            CalcError::NoConvergence(what, iterations, _) => {
+                write!(
+                    f,
+                    "{} did not converge after {} iterations",
+                    what, iterations
+                )
+            }
+            CalcError::IterationLimit(what, limit, _) => {
+                write!(f, "{} needs more than {} steps", what, limit)
+            }
+            CalcError::NotInteger(what, _) => write!(f, "{} must be a whole number", what),
+            CalcError::Overflow(what, ty, _) => write!(f, "{} overflows {}", what, ty),
+            CalcError::Unsupported(what, mode, _) => {
+                write!(f, "{} is not supported in {} mode", what, mode)
+            }
+            CalcError::Domain(what, _) => write!(f, "{} is undefined", what),
+            CalcError::NotANumber(what, _) => write!(f, "{} is not a finite number", what),
+            CalcError::NoHistory(n, _) => write!(f, "history has no value for ${}", n),
+        }
+    }
+}
+impl std::error::Error for CalcError {}
+
+fn write_suggestion(f: &mut fmt::Formatter<'_>, suggestion: &Option<String>) -> fmt::Result {
+    match suggestion {
+        Some(s) => write!(f, " (did you mean '{}'?)", s),
+        None => Ok(()),
+    }
+}
+
+/// Edit distance between `a` and `b`, counting insertions, deletions,
+/// substitutions and swaps of adjacent characters as one edit each
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // d[i][j] is the distance between the first i chars of a and the first j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Candidate closest to `name`, if any is close enough to be a likely typo
+pub fn closest_match<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, evaluate, parse};
+
+    fn render_err(input: &str) -> String {
+        match parse(input).and_then(|expr| evaluate(&expr, &Environment::<f64>::new())) {
+            Err(e) => e.render(input),
+            Ok(v) => panic!("{} evaluated to {}", input, v),
+        }
+    }
+
+    #[test]
+    fn test_render_underlines_span() {
+        assert_eq!(
+            render_err("10 / (2 - 2)"),
+            "division by zero\n  10 / (2 - 2)\n       ^~~~~~~"
+        );
+        assert_eq!(
+            render_err("1 + 2 @ 3"),
+            "unknown operator: @\n  1 + 2 @ 3\n        ^"
+        );
+    }
+
+    #[test]
+    fn test_render_at_end_of_input() {
+        assert_eq!(
+            render_err("(1 + 2"),
+            "unexpected end of expression\n  (1 + 2\n        ^"
+        );
+    }
+
+    #[test]
+    fn test_parse_float_names_token() {
+        let err = parse("1 + .").unwrap_err();
+        assert!(matches!(&err, CalcError::ParseFloat(text, _, _) if text == "."));
+        assert_eq!(err.span(), Span::new(4, 5));
+        assert!(err.to_string().contains("'.'"));
+    }
+
+    #[test]
+    fn test_closest_match() {
+        let names = ["sqrt", "sin", "x"];
+        assert_eq!(closest_match("sqr", names), Some("sqrt"));
+        assert_eq!(closest_match("sni", names), Some("sin"));
+        assert_eq!(closest_match("y", names), Some("x"));
+        assert_eq!(closest_match("cosine", names), None);
+    }
+}
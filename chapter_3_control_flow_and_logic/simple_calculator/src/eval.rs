@@ -0,0 +1,868 @@
+//! Tree-walking evaluator for expressions and statements
+
+use crate::ast::{Expr, ExprKind, Literal, Statement};
+use crate::calculus::{self, BOUND_FORMS, Integral};
+use crate::env::{ANS, Environment, UserFunction};
+use crate::functions::{self, Arity};
+use crate::number::{Number, call_text};
+use crate::registry::{CustomFn, Implementation};
+use crate::{CalcError, Op, Span};
+
+/// What evaluation does with results that are not finite numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvalPolicy {
+    /// NaN and infinite results are errors: `Domain` for an operation outside
+    /// its domain, `Overflow` for a result too large for the type, and
+    /// `NotANumber` for a NaN or infinite value coming in
+    #[default]
+    Strict,
+    /// NaN and infinities pass through, as IEEE 754 arithmetic has them, and
+    /// dividing by zero gives one
+    Ieee,
+}
+
+impl EvalPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            EvalPolicy::Strict => "strict",
+            EvalPolicy::Ieee => "ieee",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EvalPolicy> {
+        [EvalPolicy::Strict, EvalPolicy::Ieee]
+            .into_iter()
+            .find(|p| p.name() == name)
+    }
+
+    /// `a op b` under this policy
+    pub fn apply<N: Number>(self, op: Op, a: &N, b: &N) -> Result<N, CalcError> {
+        let value = match self {
+            EvalPolicy::Strict => N::apply(op, a, b)?,
+            EvalPolicy::Ieee => N::apply_ieee(op, a, b)?,
+        };
+        self.check(value, [a, b], || {
+            format!("{} {} {}", operand_text(a), op, operand_text(b))
+        })
+    }
+
+    /// `value`, the result of `what` on `inputs`; in strict mode a NaN or
+    /// infinite one is an error
+    ///
+    /// An infinite result from finite inputs is an overflow, unless one of
+    /// them is zero, as in `ln(0)` or `0 ^ -1`, which is a pole and outside
+    /// the domain. Errors carry an empty span.
+    pub fn check<'a, N: Number + 'a>(
+        self,
+        value: N,
+        inputs: impl IntoIterator<Item = &'a N>,
+        what: impl FnOnce() -> String,
+    ) -> Result<N, CalcError> {
+        if self == EvalPolicy::Ieee || value.is_finite() {
+            return Ok(value);
+        }
+        let span = Span::default();
+        let inputs: Vec<&N> = inputs.into_iter().collect();
+        Err(if inputs.iter().any(|input| !input.is_finite()) {
+            CalcError::NotANumber(what(), span)
+        } else if value.is_nan() || inputs.iter().any(|input| input.to_f64() == 0.0) {
+            CalcError::Domain(what(), span)
+        } else {
+            CalcError::Overflow(what(), N::MODE, span)
+        })
+    }
+}
+
+/// Unit of the angles that trigonometric functions take and return
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AngleUnit {
+    #[default]
+    Radians,
+    /// Degrees; `sin(30)` is exactly 0.5
+    Degrees,
+}
+
+impl AngleUnit {
+    pub fn name(self) -> &'static str {
+        match self {
+            AngleUnit::Radians => "radians",
+            AngleUnit::Degrees => "degrees",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AngleUnit> {
+        [AngleUnit::Radians, AngleUnit::Degrees]
+            .into_iter()
+            .find(|unit| unit.name() == name)
+    }
+}
+
+/// `value` as an operand in a message, in parentheses if negative
+pub(crate) fn operand_text<N: Number>(value: &N) -> String {
+    let text = value.to_string();
+    if text.starts_with('-') {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+/// Apply a binary operation to two f64 operands
+///
+/// Errors carry an empty span; `evaluate` points them at the offending operand.
+pub fn apply(op: Op, a: f64, b: f64) -> Result<f64, CalcError> {
+    match op {
+        Op::Div | Op::IntDiv | Op::Rem if b == 0.0 => {
+            Err(CalcError::DivisionByZero(Span::default()))
+        }
+        _ => apply_ieee(op, a, b),
+    }
+}
+
+/// `apply` with IEEE 754 semantics, where dividing by zero gives an infinity
+/// or NaN instead of an error
+pub fn apply_ieee(op: Op, a: f64, b: f64) -> Result<f64, CalcError> {
+    match op {
+        Op::Add => Ok(a + b),
+        Op::Sub => Ok(a - b),
+        Op::Mul => Ok(a * b),
+        Op::Div => Ok(a / b),
+        Op::IntDiv => Ok(a.div_euclid(b)),
+        Op::Rem => Ok(a.rem_euclid(b)),
+        Op::Pow => Ok(a.powf(b)),
+        _ => Err(CalcError::Unsupported(
+            format!("{} {} {}", a, op, b),
+            f64::MODE,
+            Span::default(),
+        )),
+    }
+}
+
+/// Evaluate an expression tree in number type `N`, looking variables and
+/// functions up in `env`
+pub fn evaluate<N: Number>(expr: &Expr, env: &Environment<N>) -> Result<N, CalcError> {
+    env.reset_terms();
+    eval_in(expr, env, &[], 0)
+}
+
+/// Evaluate `expr` with `locals` shadowing the variables in `env`, as the
+/// parameters of a call would
+pub(crate) fn evaluate_with<N: Number>(
+    expr: &Expr,
+    env: &Environment<N>,
+    locals: &[(&str, N)],
+) -> Result<N, CalcError> {
+    env.reset_terms();
+    eval_in(expr, env, locals, 0)
+}
+
+/// Evaluate `expr` inside a user function call `depth` levels deep, where
+/// `locals` holds that call's parameters
+fn eval_in<N: Number>(
+    expr: &Expr,
+    env: &Environment<N>,
+    locals: &[(&str, N)],
+    depth: usize,
+) -> Result<N, CalcError> {
+    let eval = |e: &Expr| eval_in(e, env, locals, depth);
+    let policy = env.policy();
+    match &expr.kind {
+        ExprKind::Number(lit) => literal(lit, env.context())
+            .and_then(|value| policy.check(value, [], || lit.text.clone()))
+            .map_err(|e| e.at(expr.span)),
+        ExprKind::Var(name) => {
+            let value = match locals.iter().find(|(local, _)| local == name) {
+                Some((_, value)) => value.clone(),
+                None => env.lookup(name, expr.span)?,
+            };
+            if policy == EvalPolicy::Strict && !value.is_finite() {
+                return Err(CalcError::NotANumber(name.clone(), expr.span));
+            }
+            Ok(value)
+        }
+        // The sign is part of the literal, so `-128` fits an `i8`
+        ExprKind::Unary(Op::Sub, operand)
+            if let ExprKind::Number(lit) = &operand.kind
+                && !lit.imaginary =>
+        {
+            N::from_negated_literal(lit, env.context())
+                .and_then(|value| policy.check(value, [], || expr.to_string()))
+                .map_err(|e| e.at(expr.span))
+        }
+        ExprKind::Unary(op, operand) => {
+            let v = eval(operand)?;
+            let result = match op {
+                Op::Add => return Ok(v),
+                Op::Sub => v.negate(),
+                Op::BitNot => v.bit_not(),
+                _ => return Err(CalcError::WrongArity(expr.span)),
+            };
+            result
+                .and_then(|value| policy.check(value, [&v], || expr.to_string()))
+                .map_err(|e| e.at(expr.span))
+        }
+        ExprKind::Binary(op, lhs, rhs) => policy
+            .apply(
+                *op,
+                &eval(lhs)?,
+                &right_operand(*op, rhs, env, locals, depth)?,
+            )
+            .map_err(|e| e.at(rhs.span)),
+        ExprKind::Factorial(operand) => {
+            let v = eval(operand)?;
+            v.factorial()
+                .and_then(|value| policy.check(value, [&v], || format!("{}!", operand_text(&v))))
+                .map_err(|e| e.at(expr.span))
+        }
+        ExprKind::Call(name, args) if is_bound_form(name, env) => {
+            eval_bound(name, args, env, locals, depth, expr.span)
+        }
+        ExprKind::Call(name, args) => {
+            let values = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+            call_in(name, &values, env, depth, expr.span)
+        }
+        ExprKind::Operator(symbol, operands) => {
+            let values = operands.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+            let Some(Implementation::Custom(f)) =
+                env.registry().operator(symbol).map(|op| &op.implementation)
+            else {
+                return Err(CalcError::UnknownOperator(symbol.clone(), expr.span));
+            };
+            custom(f, &values, policy, || expr.to_string()).map_err(|e| e.at(expr.span))
+        }
+    }
+}
+
+/// Value of `rhs` as the right operand of `op`. A literal exponent or shift
+/// amount is read in `Number::count_context`, since it counts rather than
+/// being a value of the mode's type.
+fn right_operand<N: Number>(
+    op: Op,
+    rhs: &Expr,
+    env: &Environment<N>,
+    locals: &[(&str, N)],
+    depth: usize,
+) -> Result<N, CalcError> {
+    let (lit, negated) = match &rhs.kind {
+        ExprKind::Number(lit) => (lit, false),
+        ExprKind::Unary(Op::Sub, operand) if let ExprKind::Number(lit) = &operand.kind => {
+            (lit, true)
+        }
+        _ => return eval_in(rhs, env, locals, depth),
+    };
+    if !matches!(op, Op::Pow | Op::Shl | Op::Shr) || lit.imaginary {
+        return eval_in(rhs, env, locals, depth);
+    }
+    let context = N::count_context(env.context());
+    let value = if negated {
+        N::from_negated_literal(lit, &context)
+    } else {
+        N::from_literal(lit, &context)
+    };
+    value
+        .and_then(|value| env.policy().check(value, [], || rhs.to_string()))
+        .map_err(|e| e.at(rhs.span))
+}
+
+/// `f` of `args`, the result of `what`: custom operators and functions compute
+/// in `f64`, so exact modes report the result as inexact
+pub(crate) fn custom<N: Number>(
+    f: &CustomFn,
+    args: &[N],
+    policy: EvalPolicy,
+    what: impl Fn() -> String,
+) -> Result<N, CalcError> {
+    let floats: Vec<f64> = args.iter().map(N::to_f64).collect();
+    N::constant(&what(), f(&floats)).and_then(|value| policy.check(value, args, what))
+}
+
+/// Whether a call to `name` is `integrate`, `sum` or `prod`, which no user
+/// function of the same name hides
+fn is_bound_form<N: Number>(name: &str, env: &Environment<N>) -> bool {
+    BOUND_FORMS.contains(&name) && env.function(name).is_none()
+}
+
+/// Arguments of `name(body, var, from, to)`, checking that `var` is a name
+fn bound_args<'e>(
+    name: &str,
+    args: &'e [Expr],
+    span: Span,
+) -> Result<(&'e Expr, &'e str, &'e Expr, &'e Expr), CalcError> {
+    let [body, var, from, to] = args else {
+        return Err(CalcError::FunctionArity(
+            name.to_string(),
+            Arity::Exact(4),
+            args.len(),
+            span,
+        ));
+    };
+    match &var.kind {
+        ExprKind::Var(var) => Ok((body, var, from, to)),
+        _ => Err(CalcError::UnexpectedToken(var.to_string(), var.span)),
+    }
+}
+
+/// `body` as a function of `var`, which shadows everything else in scope
+fn bound_body<'a, N: Number>(
+    body: &'a Expr,
+    var: &'a str,
+    env: &'a Environment<N>,
+    locals: &'a [(&'a str, N)],
+    depth: usize,
+) -> impl FnMut(N) -> Result<N, CalcError> + 'a {
+    move |value| {
+        let mut frame = vec![(var, value)];
+        frame.extend_from_slice(locals);
+        eval_in(body, env, &frame, depth)
+    }
+}
+
+/// Point an error from an iteration at `span` unless it already points
+/// into the expression
+fn at_call(e: CalcError, span: Span) -> CalcError {
+    if e.span() == Span::default() {
+        e.at(span)
+    } else {
+        e
+    }
+}
+
+/// `integrate(body, var, a, b)` with its error estimate
+fn integrate_in<N: Number>(
+    args: &[Expr],
+    env: &Environment<N>,
+    locals: &[(&str, N)],
+    depth: usize,
+    span: Span,
+) -> Result<Integral, CalcError> {
+    let (body, var, a, b) = bound_args("integrate", args, span)?;
+    let a = eval_in(a, env, locals, depth)?.to_f64();
+    let b = eval_in(b, env, locals, depth)?.to_f64();
+    let mut f = bound_body(body, var, env, locals, depth);
+    calculus::integrate(
+        |x| {
+            let x = N::from_f64(x).ok_or(CalcError::TooLarge(span))?;
+            f(x).map(|y| y.to_f64())
+        },
+        a,
+        b,
+    )
+    .map_err(|e| at_call(e, span))
+}
+
+/// Evaluate `integrate`, `sum` or `prod`
+///
+/// Integrals are computed in floating point, so exact modes report them as
+/// inexact; sums and products stay exact.
+fn eval_bound<N: Number>(
+    name: &str,
+    args: &[Expr],
+    env: &Environment<N>,
+    locals: &[(&str, N)],
+    depth: usize,
+    span: Span,
+) -> Result<N, CalcError> {
+    if name == "integrate" {
+        let integral = integrate_in(args, env, locals, depth, span)?;
+        let text = Expr::call(name, args.to_vec()).to_string();
+        return N::constant(&text, integral.value).map_err(|e| e.at(span));
+    }
+    let (body, var, from, to) = bound_args(name, args, span)?;
+    let mut bounds = [0; 2];
+    for (bound, expr) in bounds.iter_mut().zip([from, to]) {
+        let value = eval_in(expr, env, locals, depth)?;
+        let whole = value.to_f64();
+        if whole.fract() != 0.0 || !whole.is_finite() {
+            let what = format!("{} bound {}", name, value);
+            return Err(CalcError::NotInteger(what, expr.span));
+        }
+        // Saturates, and anything that large is over the term limit anyway
+        *bound = whole as i64;
+    }
+    // Nested forms draw on one budget, so they are limited by their total
+    let terms = (i128::from(bounds[1]) - i128::from(bounds[0]) + 1).max(0);
+    env.spend_terms(name, usize::try_from(terms).unwrap_or(usize::MAX))
+        .map_err(|e| e.at(span))?;
+    let f = bound_body(body, var, env, locals, depth);
+    let result = if name == "sum" {
+        calculus::sum(f, bounds[0], bounds[1])
+    } else {
+        calculus::product(f, bounds[0], bounds[1])
+    };
+    // Every term is finite in strict mode, but their total may not be
+    result
+        .and_then(|value| {
+            let text = || Expr::call(name, args.to_vec()).to_string();
+            env.policy().check(value, [], text)
+        })
+        .map_err(|e| at_call(e, span))
+}
+
+/// `integrate(...)` call `expr` evaluated with its error estimate, or `None`
+/// if `expr` is not one
+pub fn integral<N: Number>(
+    expr: &Expr,
+    env: &Environment<N>,
+) -> Option<Result<Integral, CalcError>> {
+    match &expr.kind {
+        ExprKind::Call(name, args) if name == "integrate" && is_bound_form(name, env) => {
+            Some(integrate_in(args, env, &[], 0, expr.span))
+        }
+        _ => None,
+    }
+}
+
+/// Value of a number literal, real or imaginary
+pub(crate) fn literal<N: Number>(lit: &Literal, context: &N::Context) -> Result<N, CalcError> {
+    if lit.imaginary {
+        N::imaginary(lit.value)
+    } else {
+        N::from_literal(lit, context)
+    }
+}
+
+/// Call function `name` with already evaluated `args`: the user function of
+/// that name if there is one, then a function from the registry, then the
+/// built-in. `span` is the whole call.
+pub fn call<N: Number>(
+    name: &str,
+    args: &[N],
+    env: &Environment<N>,
+    span: Span,
+) -> Result<N, CalcError> {
+    call_in(name, args, env, 0, span)
+}
+
+fn call_in<N: Number>(
+    name: &str,
+    args: &[N],
+    env: &Environment<N>,
+    depth: usize,
+    span: Span,
+) -> Result<N, CalcError> {
+    if let Some(f) = env.function(name) {
+        call_user(name, f, args, env, depth, span)
+    } else if let Some(f) = env.registry().function(name) {
+        if !f.arity.accepts(args.len()) {
+            return Err(CalcError::FunctionArity(
+                name.to_string(),
+                f.arity,
+                args.len(),
+                span,
+            ));
+        }
+        custom(&f.eval, args, env.policy(), || call_text(name, args)).map_err(|e| e.at(span))
+    } else if let Some(f) = functions::builtin(name) {
+        if !f.arity.accepts(args.len()) {
+            return Err(CalcError::FunctionArity(
+                name.to_string(),
+                f.arity,
+                args.len(),
+                span,
+            ));
+        }
+        let value = match env.angle() {
+            AngleUnit::Radians => N::call(f, args),
+            AngleUnit::Degrees => N::call_degrees(f, args),
+        };
+        value
+            .and_then(|value| env.policy().check(value, args, || call_text(f.name, args)))
+            .map_err(|e| e.at(span))
+    } else {
+        Err(env.unknown_function(name, span))
+    }
+}
+
+/// Bind `args` to the parameters of `f` and evaluate its body
+fn call_user<N: Number>(
+    name: &str,
+    f: &UserFunction,
+    args: &[N],
+    env: &Environment<N>,
+    depth: usize,
+    span: Span,
+) -> Result<N, CalcError> {
+    if args.len() != f.params.len() {
+        return Err(CalcError::FunctionArity(
+            name.to_string(),
+            Arity::Exact(f.params.len()),
+            args.len(),
+            span,
+        ));
+    }
+    if depth >= env.max_depth() {
+        return Err(CalcError::RecursionLimit(
+            name.to_string(),
+            env.max_depth(),
+            span,
+        ));
+    }
+    let frame: Vec<(&str, N)> = f
+        .params
+        .iter()
+        .map(String::as_str)
+        .zip(args.iter().cloned())
+        .collect();
+    // Errors inside the body point at the call, since the body's spans belong to
+    // the line that defined it
+    eval_in(&f.body, env, &frame, depth + 1).map_err(|e| e.at(span))
+}
+
+/// Run one statement against `env`
+///
+/// Assignments store their value and definitions store their function. Every
+/// value produced is also bound to `ans`; definitions produce no value.
+pub fn execute<N: Number>(
+    stmt: &Statement,
+    env: &mut Environment<N>,
+) -> Result<Option<N>, CalcError> {
+    let value = match stmt {
+        Statement::Expr(expr) => evaluate(expr, env)?,
+        Statement::Assign(name, expr) => {
+            let value = evaluate(expr, env)?;
+            env.set(name.as_str(), value.clone());
+            value
+        }
+        Statement::Define(name, params, body) => {
+            env.define(name.as_str(), params.clone(), body.clone());
+            return Ok(None);
+        }
+    };
+    env.set(ANS, value.clone());
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculus::MAX_TERMS;
+    use crate::number::{BigInt, Complex, Format, IntType, Overflow, Rational, Word};
+    use crate::{parse, parse_statement};
+
+    fn run<N: Number>(env: &mut Environment<N>, line: &str) -> Result<Option<N>, CalcError> {
+        execute(&parse_statement(line)?, env)
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(apply(Op::Sub, 2.0, 5.0).unwrap(), -3.0);
+        assert_eq!(apply(Op::Pow, 2.0, 10.0).unwrap(), 1024.0);
+        assert!(matches!(
+            apply(Op::Div, 1.0, 0.0),
+            Err(CalcError::DivisionByZero(_))
+        ));
+    }
+
+    #[test]
+    fn test_execute_keeps_session_state() {
+        let mut env: Environment = Environment::new();
+        assert_eq!(run(&mut env, "x = 4.2").unwrap(), Some(4.2));
+        assert!((run(&mut env, "x * 3").unwrap().unwrap() - 12.6).abs() < 1e-12);
+        assert!((run(&mut env, "ans / 2").unwrap().unwrap() - 6.3).abs() < 1e-12);
+        assert!(matches!(
+            run(&mut env, "y + 1"),
+            Err(CalcError::UndefinedVariable(..))
+        ));
+        // A failed line leaves `ans` alone
+        assert!((run(&mut env, "ans").unwrap().unwrap() - 6.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_user_functions() {
+        let mut env: Environment = Environment::new();
+        assert_eq!(run(&mut env, "hyp(a, b) = sqrt(a^2 + b^2)").unwrap(), None);
+        assert_eq!(run(&mut env, "hyp(3, 4)").unwrap(), Some(5.0));
+        // Parameters shadow globals only inside the body
+        run(&mut env, "a = 10").unwrap();
+        run(&mut env, "scale(x) = x * a").unwrap();
+        assert_eq!(run(&mut env, "scale(hyp(6, 8))").unwrap(), Some(100.0));
+        assert_eq!(run(&mut env, "a").unwrap(), Some(10.0));
+        assert!(matches!(
+            run(&mut env, "x"),
+            Err(CalcError::UndefinedVariable(..))
+        ));
+    }
+
+    #[test]
+    fn test_user_function_errors() {
+        let mut env: Environment = Environment::new();
+        run(&mut env, "hyp(a, b) = sqrt(a^2 + b^2)").unwrap();
+        let err = run(&mut env, "hyp(3)").unwrap_err();
+        assert_eq!(err.to_string(), "hyp expects 2 arguments, got 1");
+        assert!(matches!(
+            run(&mut env, "hyq(3, 4)"),
+            Err(CalcError::UnknownFunction(name, Some(s), _)) if name == "hyq" && s == "hyp"
+        ));
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let mut env: Environment = Environment::new();
+        env.set_max_depth(20);
+        run(&mut env, "f(n) = f(n - 1) + 1").unwrap();
+        let err = run(&mut env, "1 + f(3)").unwrap_err();
+        assert!(matches!(
+            &err,
+            CalcError::RecursionLimit(name, 20, span) if name == "f" && *span == Span::new(4, 8)
+        ));
+        // Calls that stay within the limit are fine
+        run(&mut env, "g(n) = n").unwrap();
+        run(&mut env, "h(n) = g(g(g(n)))").unwrap();
+        assert_eq!(run(&mut env, "h(7)").unwrap(), Some(7.0));
+    }
+
+    #[test]
+    fn test_rational_mode_is_exact() {
+        let mut env: Environment<Rational> = Environment::new();
+        let show = |r: Option<Rational>| r.unwrap().to_string();
+        assert_eq!(show(run(&mut env, "0.1 + 0.2").unwrap()), "3/10");
+        assert_eq!(show(run(&mut env, "1/3 * 3").unwrap()), "1");
+        assert_eq!(show(run(&mut env, "x = 2^-3 - 1/8").unwrap()), "0");
+        assert_eq!(run(&mut env, "half(n) = n / 2").unwrap(), None);
+        assert_eq!(show(run(&mut env, "half(7) + abs(-1/2)").unwrap()), "4");
+        // Exact zero, even when f64 would round to something tiny
+        let err = run(&mut env, "1 / (0.1 + 0.2 - 0.3)").unwrap_err();
+        assert!(matches!(err, CalcError::DivisionByZero(span) if span == Span::new(4, 21)));
+        let err = run(&mut env, "1 / x").unwrap_err();
+        assert!(matches!(err, CalcError::DivisionByZero(_)));
+        assert!(matches!(
+            run(&mut env, "2 * sqrt(2)"),
+            Err(CalcError::Inexact(what, "rational", span)) if what == "sqrt(2)" && span == Span::new(4, 11)
+        ));
+        assert_eq!(show(run(&mut env, "sqrt(9/4)").unwrap()), "3/2");
+    }
+
+    #[test]
+    fn test_exact_results_stay_interactive() {
+        let mut env: Environment<Rational> = Environment::new();
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            run(&mut env, "x = 3^300000 / 2^300000"),
+            Err(CalcError::TooLarge(_))
+        ));
+        // Reducing fractions near the size limit is still quick
+        run(&mut env, "x = (3^30000 - 1) / (2^30000 - 1)").unwrap();
+        run(&mut env, "x * (5^20000 + 1) / 2^41").unwrap();
+        assert!(start.elapsed().as_secs() < 5, "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn test_integer_mode_is_exact() {
+        let mut env: Environment<BigInt> = Environment::new();
+        let mut show = |line: &str| run(&mut env, line).unwrap().unwrap().to_string();
+        assert_eq!(
+            show("2 ^ 200"),
+            "1606938044258990275541962092341162602522202993782792835301376"
+        );
+        assert_eq!(
+            show("50!"),
+            "30414093201713378043612608166064768844377641568960512000000000000"
+        );
+        assert_eq!(
+            show("12345678901234567890 * 98765432109876543210"),
+            "1219326311370217952237463801111263526900"
+        );
+        // Euclidean: the remainder is never negative
+        assert_eq!(show("-7 // 2"), "-4");
+        assert_eq!(show("-7 % 2"), "1");
+        assert_eq!(show("7 // -2"), "-3");
+        assert_eq!(show("7 mod -2"), "1");
+        assert_eq!(show("12 / 4 + sqrt(16) + 1e3"), "1007");
+        // ±1 to any power is exact
+        assert_eq!(show("1 ^ -1"), "1");
+        assert_eq!(show("(-1) ^ -1"), "-1");
+        assert_eq!(show("(-1) ^ (10 ^ 30)"), "1");
+
+        let err = run(&mut env, "1 + 7 / 2").unwrap_err();
+        assert_eq!(err.to_string(), "7 / 2 has no exact value in integer mode");
+        assert_eq!(err.span(), Span::new(8, 9));
+        for line in ["1.5", "2 ^ -1", "sqrt(2)", "pi", "(-1)!"] {
+            assert!(
+                matches!(
+                    run(&mut env, line),
+                    Err(CalcError::Inexact(_, "integer", _))
+                ),
+                "{}",
+                line
+            );
+        }
+        assert!(matches!(
+            run(&mut env, "5 % (3 - 3)"),
+            Err(CalcError::DivisionByZero(_))
+        ));
+        assert!(matches!(
+            run(&mut env, "100000!"),
+            Err(CalcError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_complex_mode() {
+        let mut env: Environment<Complex> = Environment::new();
+        let mut show = |line: &str| run(&mut env, line).unwrap().unwrap().to_string();
+        assert_eq!(show("sqrt(-4)"), "2i");
+        assert_eq!(show("i ^ 2"), "-1");
+        assert_eq!(show("(1 + 2i) * (3 - i)"), "5+5i");
+        assert_eq!(show("exp(i * pi)"), "-1");
+        assert_eq!(show("ln(-1) / pi"), "i");
+        assert_eq!(show("abs(3 + 4i) + arg(i) * 2 / pi"), "6");
+        assert_eq!(show("conj(re(2 - 3i) + im(2 - 3i) * i)"), "2+3i");
+        assert_eq!(show("polar(2, pi / 2)"), "2i");
+        assert_eq!(show("min(3, 2) + 7 % 4"), "5");
+        // A variable named `i` hides the imaginary unit, but not the suffix
+        assert_eq!(show("i = 5"), "5");
+        assert_eq!(show("i + 1i"), "5+i");
+
+        assert!(matches!(
+            run(&mut env, "1 / (2i - 2i)"),
+            Err(CalcError::DivisionByZero(_))
+        ));
+        let err = run(&mut env, "max(1, 2i)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "max(1, 2i) is only defined for real numbers"
+        );
+        assert_eq!(err.span(), Span::new(0, 10));
+    }
+
+    #[test]
+    fn test_programmer_mode() {
+        let mut env: Environment<Word> = Environment::new();
+        env.set_context(Format {
+            ty: IntType::U8,
+            overflow: Overflow::Error,
+        });
+        let mut show = |line: &str| run(&mut env, line).unwrap().unwrap().to_string();
+        assert_eq!(show("0xF0 | 0b1010 & 0o17"), "250");
+        assert_eq!(show("~0 >> 4 xor 1"), "14");
+        assert_eq!(show("sum(k, k, 1, 10) + 2 ^ 7"), "183");
+        assert_eq!(show("max(3, 200) - 5!"), "80");
+        let err = run(&mut env, "x = 2 * (100 + 28)").unwrap_err();
+        assert_eq!(err.to_string(), "2 * 128 overflows u8");
+        assert_eq!(err.span(), Span::new(8, 18));
+        assert!(matches!(
+            run(&mut env, "sqrt(2)"),
+            Err(CalcError::Inexact(_, "programmer", _))
+        ));
+
+        // The other modes have no bits to work on
+        let mut float: Environment = Environment::new();
+        let err = run(&mut float, "1 + (6 & 3)").unwrap_err();
+        assert_eq!(err.to_string(), "6 & 3 is not supported in float mode");
+        assert_eq!(err.span(), Span::new(9, 10));
+        let mut exact: Environment<BigInt> = Environment::new();
+        assert_eq!(
+            run(&mut exact, "0xFF + 0b1").unwrap(),
+            Some(BigInt::from(256))
+        );
+        assert!(matches!(
+            run(&mut exact, "~1"),
+            Err(CalcError::Unsupported(_, "integer", _))
+        ));
+    }
+
+    #[test]
+    fn test_eval_policy() {
+        let mut env: Environment = Environment::new();
+        let mut fails = |line: &str| run(&mut env, line).unwrap_err();
+        let err = fails("pow (-8) 0.5");
+        assert_eq!(err.to_string(), "(-8) ^ 0.5 is undefined");
+        assert_eq!(err.span(), Span::new(9, 12));
+        let err = fails("pow 10 400");
+        assert_eq!(err.to_string(), "10 ^ 400 overflows float");
+        assert_eq!(fails("ln(0)").to_string(), "ln(0) is undefined");
+        assert_eq!(fails("asin(2)").to_string(), "asin(2) is undefined");
+        assert_eq!(fails("171!").to_string(), "171! overflows float");
+        assert_eq!(fails("2 * 1e400").to_string(), "1e400 overflows float");
+        assert!(matches!(fails("1 / 0"), CalcError::DivisionByZero(_)));
+        let err = fails("sum(10 ^ 305, k, 1, 10000)");
+        assert!(matches!(err, CalcError::Overflow(..)), "{:?}", err);
+
+        // IEEE mode lets special values through, where strict mode rejects them
+        env.set_policy(EvalPolicy::Ieee);
+        assert_eq!(run(&mut env, "x = 1 / 0").unwrap(), Some(f64::INFINITY));
+        assert!(run(&mut env, "y = x - x").unwrap().unwrap().is_nan());
+        assert_eq!(run(&mut env, "exp(1000)").unwrap(), Some(f64::INFINITY));
+        env.set_policy(EvalPolicy::Strict);
+        let err = run(&mut env, "2 * y").unwrap_err();
+        assert_eq!(err.to_string(), "y is not a finite number");
+        assert_eq!(err.span(), Span::new(4, 5));
+        assert!(run(&mut env, "x = 3").is_ok());
+
+        // Complex mode has its own special values
+        let mut complex: Environment<Complex> = Environment::new();
+        assert!(matches!(
+            run(&mut complex, "exp(1000 + i)"),
+            Err(CalcError::Overflow(_, "complex", _))
+        ));
+        assert!(run(&mut complex, "ln(-1)").is_ok());
+    }
+
+    #[test]
+    fn test_imaginary_needs_complex_mode() {
+        let mut env: Environment = Environment::new();
+        let err = run(&mut env, "1 + 2i").unwrap_err();
+        assert!(matches!(err, CalcError::Imaginary(span) if span == Span::new(4, 6)));
+        assert!(matches!(run(&mut env, "i"), Err(CalcError::Imaginary(_))));
+        assert!(matches!(
+            run(&mut env, "sqrt(-4)"),
+            Err(CalcError::Domain(..))
+        ));
+        env.set_policy(EvalPolicy::Ieee);
+        assert!(run(&mut env, "sqrt(-4)").unwrap().unwrap().is_nan());
+        let mut exact: Environment<Rational> = Environment::new();
+        assert!(matches!(
+            run(&mut exact, "3i"),
+            Err(CalcError::Imaginary(_))
+        ));
+    }
+
+    #[test]
+    fn test_bound_forms() {
+        let mut env: Environment = Environment::new();
+        let value = run(&mut env, "integrate(x^2, x, 0, 3)").unwrap().unwrap();
+        assert!((value - 9.0).abs() < 1e-12);
+        assert_eq!(run(&mut env, "sum(i^2, i, 1, 10)").unwrap(), Some(385.0));
+        assert_eq!(run(&mut env, "prod(k, k, 1, 5)").unwrap(), Some(120.0));
+        // The bound variable shadows a session variable, which is untouched
+        run(&mut env, "x = 100").unwrap();
+        run(&mut env, "f(a) = sum(x * a, x, 1, 3)").unwrap();
+        assert_eq!(run(&mut env, "f(2) + x").unwrap(), Some(112.0));
+        let integral = integral(&parse("integrate(sin(t), t, 0, pi)").unwrap(), &env)
+            .unwrap()
+            .unwrap();
+        assert!((integral.value - 2.0).abs() < 1e-10 && integral.error <= 1e-10);
+
+        let err = run(&mut env, "sum(i, 2 * i, 1, 3)").unwrap_err();
+        assert!(matches!(err, CalcError::UnexpectedToken(_, span) if span == Span::new(7, 12)));
+        assert!(matches!(
+            run(&mut env, "sum(i, i, 1)"),
+            Err(CalcError::FunctionArity(_, Arity::Exact(4), 3, _))
+        ));
+        let err = run(&mut env, "sum(i, i, 1, 2.5)").unwrap_err();
+        assert_eq!(err.to_string(), "sum bound 2.5 must be a whole number");
+        assert_eq!(err.span(), Span::new(13, 16));
+        assert!(matches!(
+            run(&mut env, "sum(1 / (i - 3), i, 1, 5)"),
+            Err(CalcError::DivisionByZero(span)) if span == Span::new(8, 15)
+        ));
+        // Nested forms share one term limit
+        assert!(matches!(
+            run(&mut env, "sum(sum(i * j, j, 1, 3000), i, 1, 3000)"),
+            Err(CalcError::IterationLimit(name, MAX_TERMS, _)) if name == "sum"
+        ));
+        assert_eq!(
+            run(&mut env, "sum(sum(1, j, 1, 300), i, 1, 300)").unwrap(),
+            Some(90000.0)
+        );
+
+        // Sums stay exact; integrals are approximate
+        let mut exact: Environment<Rational> = Environment::new();
+        let harmonic = run(&mut exact, "sum(1 / n, n, 1, 4)").unwrap().unwrap();
+        assert_eq!(harmonic.to_string(), "25/12");
+        assert!(matches!(
+            run(&mut exact, "integrate(x, x, 0, 1)"),
+            Err(CalcError::Inexact(..))
+        ));
+    }
+}
@@ -0,0 +1,324 @@
+//! Number formatting for results: fixed decimals, scientific and engineering
+//! notation, significant figures and digit grouping
+//!
+//! The default notation, `auto`, never rounds: it writes the shortest decimal
+//! that reads back as the same `f64`, which is what `{}` writes, so `0.1 + 0.2`
+//! is `0.30000000000000004`. The only difference from `{}` is the layout of
+//! very large and very small magnitudes: exponents from -7 to 20 are written
+//! out in full, and others in scientific notation as `{:e}` writes them, so
+//! `10^21` is `1e21` rather than 22 digits. The other notations round:
+//! `0.1 + 0.2` is `0.3` to 15 significant figures and `0.30` to two
+//! decimals, and a negative value that rounds to zero loses its sign.
+
+use std::fmt;
+
+/// Decimal exponents below this are written in scientific notation by `Auto`
+const AUTO_MIN_EXPONENT: i32 = -7;
+/// Decimal exponents from this up are written in scientific notation by `Auto`
+const AUTO_MAX_EXPONENT: i32 = 21;
+/// Digits a notation may ask for; more would only show rounding noise
+const MAX_DIGITS: usize = 100;
+
+/// SI prefixes by power of ten, for engineering notation
+const SI_PREFIXES: [(i32, &str); 20] = [
+    (-30, "q"),
+    (-27, "r"),
+    (-24, "y"),
+    (-21, "z"),
+    (-18, "a"),
+    (-15, "f"),
+    (-12, "p"),
+    (-9, "n"),
+    (-6, "µ"),
+    (-3, "m"),
+    (3, "k"),
+    (6, "M"),
+    (9, "G"),
+    (12, "T"),
+    (15, "P"),
+    (18, "E"),
+    (21, "Z"),
+    (24, "Y"),
+    (27, "R"),
+    (30, "Q"),
+];
+
+/// How the digits of a number are laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+    /// Every digit needed to read the value back, unrounded, in scientific
+    /// notation outside `1e-7..1e21`
+    #[default]
+    Auto,
+    /// A fixed number of digits after the point: `fixed 2` writes `3.14`
+    Fixed(usize),
+    /// One digit before the point and a fixed number after: `sci 2` writes
+    /// `3.14e2`
+    Scientific(usize),
+    /// An exponent that is a multiple of 3, written as its SI prefix, and a
+    /// fixed number of digits after the point: `eng 2` writes `314.16k`
+    Engineering(usize),
+    /// Rounded to a number of significant figures, trailing zeros dropped:
+    /// `sig 3` writes `3.14`
+    Significant(usize),
+}
+
+impl Notation {
+    /// Notation from the words of the `format` command, such as `["fixed",
+    /// "2"]` or `["auto"]`
+    pub fn from_words(words: &[&str]) -> Option<Notation> {
+        let (name, digits) = match words {
+            ["auto"] => return Some(Notation::Auto),
+            [name, digits] => (*name, digits.parse().ok()?),
+            _ => return None,
+        };
+        if digits > MAX_DIGITS {
+            return None;
+        }
+        match name {
+            "fixed" => Some(Notation::Fixed(digits)),
+            "sci" => Some(Notation::Scientific(digits)),
+            "eng" => Some(Notation::Engineering(digits)),
+            "sig" if digits > 0 => Some(Notation::Significant(digits)),
+            _ => None,
+        }
+    }
+}
+
+/// The words `from_words` reads back, such as `fixed 2`
+impl fmt::Display for Notation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notation::Auto => f.write_str("auto"),
+            Notation::Fixed(digits) => write!(f, "fixed {}", digits),
+            Notation::Scientific(digits) => write!(f, "sci {}", digits),
+            Notation::Engineering(digits) => write!(f, "eng {}", digits),
+            Notation::Significant(digits) => write!(f, "sig {}", digits),
+        }
+    }
+}
+
+/// Writes numbers in one notation, optionally grouping the digits before
+/// the point in threes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Formatter {
+    pub notation: Notation,
+    /// Character between groups of three digits, like `,` in `1,234,567`
+    pub separator: Option<char>,
+}
+
+impl Formatter {
+    /// `x` in this notation; infinities and NaN are written as `{}` does
+    pub fn format(&self, x: f64) -> String {
+        if !x.is_finite() {
+            return x.to_string();
+        }
+        let text = match self.notation {
+            Notation::Auto => return self.group(&auto(x)),
+            Notation::Fixed(digits) => format!("{:.*}", digits, x),
+            Notation::Scientific(digits) => format!("{:.*e}", digits, x),
+            Notation::Engineering(digits) => engineering(x, digits),
+            Notation::Significant(digits) => significant(x, digits),
+        };
+        self.group(unsigned_zero(&text))
+    }
+
+    /// `number` with the separator between groups of three digits in its
+    /// first run of digits, the part before any point or exponent
+    pub fn group(&self, number: &str) -> String {
+        let Some(separator) = self.separator else {
+            return number.to_string();
+        };
+        let start = number
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(number.len());
+        let (sign, rest) = number.split_at(start);
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (digits, tail) = rest.split_at(end);
+        let mut grouped = sign.to_string();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                grouped.push(separator);
+            }
+            grouped.push(digit);
+        }
+        grouped + tail
+    }
+}
+
+/// `decimal`, a number with at most `places` digits after the point, padded
+/// with zeros to exactly `places`
+pub fn fixed_places(decimal: &str, places: usize) -> String {
+    if places == 0 {
+        return decimal.to_string();
+    }
+    let (int_part, frac_part) = decimal.split_once('.').unwrap_or((decimal, ""));
+    format!("{}.{:0<places$}", int_part, frac_part)
+}
+
+/// `number` without its minus sign if every digit is zero, as in `-0.00`
+fn unsigned_zero(number: &str) -> &str {
+    match number.strip_prefix('-') {
+        Some(rest) if !rest.contains(|c: char| ('1'..='9').contains(&c)) => rest,
+        _ => number,
+    }
+}
+
+/// Power of ten of the leading digit of `x`, which must be finite and not zero
+fn exponent(x: f64) -> i32 {
+    let text = format!("{:e}", x);
+    text.split_once('e')
+        .and_then(|(_, exponent)| exponent.parse().ok())
+        .unwrap_or_default()
+}
+
+/// `x` divided by `10^power`, dividing or multiplying by an exact power so
+/// that small powers stay exact
+fn scaled(x: f64, power: i32) -> f64 {
+    // `10^324` for a subnormal `x` is infinite, but its halves are not
+    if power.abs() > 300 {
+        let half = power / 2;
+        return scaled(scaled(x, half), power - half);
+    }
+    if power >= 0 {
+        x / 10f64.powi(power)
+    } else {
+        x * 10f64.powi(-power)
+    }
+}
+
+fn auto(x: f64) -> String {
+    // Including `-0`
+    if x == 0.0 {
+        return "0".to_string();
+    }
+    let exponent = exponent(x);
+    if (AUTO_MIN_EXPONENT..AUTO_MAX_EXPONENT).contains(&exponent) {
+        x.to_string()
+    } else {
+        format!("{:e}", x)
+    }
+}
+
+fn engineering(x: f64, digits: usize) -> String {
+    if x == 0.0 {
+        return format!("{:.*}", digits, x);
+    }
+    let mut power = exponent(x).div_euclid(3) * 3;
+    let mut mantissa = format!("{:.*}", digits, scaled(x, power));
+    // Rounding can carry into a fourth digit, as 999.96 to one place does
+    let unsigned = mantissa.trim_start_matches('-');
+    if unsigned.find('.').unwrap_or(unsigned.len()) > 3 {
+        power += 3;
+        mantissa = format!("{:.*}", digits, scaled(x, power));
+    }
+    let suffix = match SI_PREFIXES.iter().find(|(p, _)| *p == power) {
+        Some((_, prefix)) => prefix.to_string(),
+        None if power == 0 => String::new(),
+        None => format!("e{}", power),
+    };
+    mantissa + &suffix
+}
+
+fn significant(x: f64, digits: usize) -> String {
+    let rounded: f64 = format!("{:.*e}", digits.max(1) - 1, x).parse().unwrap_or(x);
+    auto(rounded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(notation: Notation, x: f64) -> String {
+        Formatter {
+            notation,
+            separator: None,
+        }
+        .format(x)
+    }
+
+    #[test]
+    fn test_auto() {
+        assert_eq!(format(Notation::Auto, 0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(format(Notation::Auto, 1e21), "1e21");
+        assert_eq!(
+            format(Notation::Auto, 123456789e12),
+            "123456789000000000000"
+        );
+        assert_eq!(format(Notation::Auto, 0.000001), "0.000001");
+        assert_eq!(format(Notation::Auto, -2.5e-8), "-2.5e-8");
+        assert_eq!(format(Notation::Auto, 0.0), "0");
+        assert_eq!(format(Notation::Auto, -0.0), "0");
+        assert_eq!(format(Notation::Auto, f64::NEG_INFINITY), "-inf");
+    }
+
+    #[test]
+    fn test_rounding_notations() {
+        assert_eq!(format(Notation::Fixed(2), 0.1 + 0.2), "0.30");
+        assert_eq!(format(Notation::Fixed(0), 2.5), "2");
+        assert_eq!(format(Notation::Fixed(2), -0.001), "0.00");
+        assert_eq!(format(Notation::Fixed(2), -0.005), "-0.01");
+        assert_eq!(format(Notation::Scientific(1), -0.0), "0.0e0");
+        assert_eq!(format(Notation::Engineering(1), -0.0), "0.0");
+        assert_eq!(format(Notation::Scientific(3), 123456.0), "1.235e5");
+        assert_eq!(format(Notation::Scientific(1), -0.000123), "-1.2e-4");
+        assert_eq!(format(Notation::Significant(15), 0.1 + 0.2), "0.3");
+        assert_eq!(format(Notation::Significant(3), 1234567.0), "1230000");
+        assert_eq!(format(Notation::Significant(2), 1e22 / 3.0), "3.3e21");
+        assert_eq!(format(Notation::Significant(2), f64::NAN), "NaN");
+    }
+
+    #[test]
+    fn test_engineering() {
+        assert_eq!(format(Notation::Engineering(2), 1234.5), "1.23k");
+        assert_eq!(format(Notation::Engineering(1), 47e-6), "47.0µ");
+        assert_eq!(format(Notation::Engineering(0), -0.0126), "-13m");
+        assert_eq!(format(Notation::Engineering(1), 999.96), "1.0k");
+        assert_eq!(format(Notation::Engineering(2), 12.0), "12.00");
+        assert_eq!(format(Notation::Engineering(1), 2e40), "20.0e39");
+        assert_eq!(format(Notation::Engineering(3), 0.0), "0.000");
+        // Subnormals, whose power of ten is past the largest f64
+        assert_eq!(format(Notation::Engineering(2), 1e-310), "100.00e-312");
+        assert_eq!(format(Notation::Engineering(2), -5e-324), "-4.94e-324");
+        assert_eq!(format(Notation::Engineering(1), 1.5e308), "150.0e306");
+    }
+
+    #[test]
+    fn test_grouping() {
+        let formatter = Formatter {
+            notation: Notation::Fixed(2),
+            separator: Some(','),
+        };
+        assert_eq!(formatter.format(-1234567.891), "-1,234,567.89");
+        assert_eq!(formatter.format(999.0), "999.00");
+        assert_eq!(formatter.group("1234/56789"), "1,234/56789");
+        let formatter = Formatter {
+            notation: Notation::Auto,
+            separator: Some('_'),
+        };
+        assert_eq!(formatter.format(1e20), "100_000_000_000_000_000_000");
+        assert_eq!(formatter.format(1e21), "1e21");
+        assert_eq!(fixed_places("-3.5", 3), "-3.500");
+        assert_eq!(fixed_places("7", 2), "7.00");
+    }
+
+    #[test]
+    fn test_notation_words() {
+        for notation in [
+            Notation::Auto,
+            Notation::Fixed(0),
+            Notation::Scientific(4),
+            Notation::Engineering(2),
+            Notation::Significant(15),
+        ] {
+            let text = notation.to_string();
+            let words: Vec<&str> = text.split(' ').collect();
+            assert_eq!(Notation::from_words(&words), Some(notation));
+        }
+        assert_eq!(Notation::from_words(&["sig", "0"]), None);
+        assert_eq!(Notation::from_words(&["fixed", "101"]), None);
+        assert_eq!(Notation::from_words(&["fixed"]), None);
+    }
+}
@@ -0,0 +1,234 @@
+//! Built-in math functions and constants
+//!
+//! Trigonometric functions work in radians, or in degrees through
+//! `in_degrees` when the environment says so. `re`, `im`, `arg`, `conj` and
+//! `polar` are for complex numbers; on reals they treat the value as having
+//! no imaginary part.
+
+use std::fmt;
+
+/// How many arguments a function accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exact(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (prefix, n) = match self {
+            Arity::Exact(n) => ("", *n),
+            Arity::AtLeast(n) => ("at least ", *n),
+        };
+        let plural = if n == 1 { "" } else { "s" };
+        write!(f, "{}{} argument{}", prefix, n, plural)
+    }
+}
+
+/// A callable built-in function
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub eval: fn(&[f64]) -> f64,
+}
+
+impl Builtin {
+    const fn new(name: &'static str, arity: Arity, eval: fn(&[f64]) -> f64) -> Builtin {
+        Builtin { name, arity, eval }
+    }
+}
+
+fn min(args: &[f64]) -> f64 {
+    args.iter().copied().fold(f64::INFINITY, f64::min)
+}
+
+fn max(args: &[f64]) -> f64 {
+    args.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+}
+
+/// Every built-in function
+pub const BUILTINS: &[Builtin] = &[
+    Builtin::new("sqrt", Arity::Exact(1), |a| a[0].sqrt()),
+    Builtin::new("cbrt", Arity::Exact(1), |a| a[0].cbrt()),
+    Builtin::new("ln", Arity::Exact(1), |a| a[0].ln()),
+    Builtin::new("log10", Arity::Exact(1), |a| a[0].log10()),
+    Builtin::new("log", Arity::Exact(2), |a| a[1].ln() / a[0].ln()),
+    Builtin::new("exp", Arity::Exact(1), |a| a[0].exp()),
+    Builtin::new("sin", Arity::Exact(1), |a| a[0].sin()),
+    Builtin::new("cos", Arity::Exact(1), |a| a[0].cos()),
+    Builtin::new("tan", Arity::Exact(1), |a| a[0].tan()),
+    Builtin::new("asin", Arity::Exact(1), |a| a[0].asin()),
+    Builtin::new("acos", Arity::Exact(1), |a| a[0].acos()),
+    Builtin::new("atan", Arity::Exact(1), |a| a[0].atan()),
+    Builtin::new("abs", Arity::Exact(1), |a| a[0].abs()),
+    Builtin::new("floor", Arity::Exact(1), |a| a[0].floor()),
+    Builtin::new("ceil", Arity::Exact(1), |a| a[0].ceil()),
+    Builtin::new("round", Arity::Exact(1), |a| a[0].round()),
+    Builtin::new("min", Arity::AtLeast(1), min),
+    Builtin::new("max", Arity::AtLeast(1), max),
+    Builtin::new("re", Arity::Exact(1), |a| a[0]),
+    Builtin::new("im", Arity::Exact(1), |_| 0.0),
+    Builtin::new("arg", Arity::Exact(1), |a| 0f64.atan2(a[0])),
+    Builtin::new("conj", Arity::Exact(1), |a| a[0]),
+    Builtin::new("polar", Arity::Exact(2), polar),
+];
+
+/// `r∠theta` as a real number, or NaN when it has an imaginary part beyond
+/// rounding noise
+fn polar(args: &[f64]) -> f64 {
+    let (r, theta) = (args[0], args[1]);
+    if (r * theta.sin()).abs() <= r.abs() * 1e-15 {
+        r * theta.cos()
+    } else {
+        f64::NAN
+    }
+}
+
+/// Trigonometric functions that take an angle
+pub const ANGLE_ARGUMENT: [&str; 3] = ["sin", "cos", "tan"];
+
+/// Inverse trigonometric functions that return an angle
+pub const ANGLE_RESULT: [&str; 3] = ["asin", "acos", "atan"];
+
+/// A named function of one real number
+type Unary = (&'static str, fn(f64) -> f64);
+
+/// The functions of `ANGLE_ARGUMENT` and `ANGLE_RESULT` in degrees
+const IN_DEGREES: [Unary; 6] = [
+    ("sin", sin_degrees),
+    ("cos", cos_degrees),
+    ("tan", tan_degrees),
+    ("asin", |x| to_degrees(x.asin())),
+    ("acos", |x| to_degrees(x.acos())),
+    ("atan", |x| to_degrees(x.atan())),
+];
+
+/// Built-in `name` taking or returning degrees rather than radians, if it
+/// is a trigonometric function
+pub fn in_degrees(name: &str) -> Option<fn(f64) -> f64> {
+    IN_DEGREES.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+}
+
+/// `sin` of `x` degrees, exact where the result is 0, ±1/2 or ±1
+fn sin_degrees(x: f64) -> f64 {
+    let turn = x.rem_euclid(360.0);
+    let sign = if turn < 180.0 { 1.0 } else { -1.0 };
+    match turn % 180.0 {
+        0.0 => 0.0,
+        30.0 | 150.0 => sign * 0.5,
+        90.0 => sign,
+        _ => x.to_radians().sin(),
+    }
+}
+
+fn cos_degrees(x: f64) -> f64 {
+    sin_degrees(x + 90.0)
+}
+
+/// `tan` of `x` degrees, exact where the result is 0 or ±1 and undefined at
+/// odd multiples of 90
+fn tan_degrees(x: f64) -> f64 {
+    match x.rem_euclid(180.0) {
+        0.0 => 0.0,
+        45.0 => 1.0,
+        90.0 => f64::NAN,
+        135.0 => -1.0,
+        _ => x.to_radians().tan(),
+    }
+}
+
+/// `radians` in degrees, rounded to a whole number of degrees when within
+/// rounding error of one, so that `asin(0.5)` is 30
+fn to_degrees(radians: f64) -> f64 {
+    let degrees = radians.to_degrees();
+    if (degrees - degrees.round()).abs() < 1e-9 {
+        degrees.round()
+    } else {
+        degrees
+    }
+}
+
+/// Name of the imaginary unit, which a variable of the same name hides
+pub const IMAGINARY_UNIT: &str = "i";
+
+/// Built-in constants
+pub const CONSTANTS: &[(&str, f64)] = &[
+    ("pi", std::f64::consts::PI),
+    ("e", std::f64::consts::E),
+    ("tau", std::f64::consts::TAU),
+];
+
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+pub fn constant(name: &str) -> Option<f64> {
+    CONSTANTS.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CalcError, Environment, Span, evaluate, parse};
+
+    fn eval_str(input: &str) -> Result<f64, CalcError> {
+        evaluate(&parse(input)?, &Environment::new())
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(eval_str("sqrt(16) + cbrt(27)").unwrap(), 7.0);
+        assert!(close(eval_str("log(2, 1024)").unwrap(), 10.0));
+        assert!(close(eval_str("ln(e) + log10(1000)").unwrap(), 4.0));
+        assert!(close(
+            eval_str("sin(pi / 2) + cos(0) + tan(0)").unwrap(),
+            2.0
+        ));
+        assert!(close(
+            eval_str("asin(1) * 2").unwrap(),
+            std::f64::consts::PI
+        ));
+        assert_eq!(
+            eval_str("abs(-2.5) + floor(1.7) + ceil(1.2) + round(2.5)").unwrap(),
+            8.5
+        );
+        assert_eq!(eval_str("min(3, -1, 2) * max(4)").unwrap(), -4.0);
+        assert!(close(eval_str("tau / 2").unwrap(), std::f64::consts::PI));
+    }
+
+    #[test]
+    fn test_arity_error_names_function() {
+        let err = eval_str("1 + sqrt(4, 9)").unwrap_err();
+        assert!(matches!(
+            &err,
+            CalcError::FunctionArity(name, Arity::Exact(1), 2, span)
+                if name == "sqrt" && *span == Span::new(4, 14)
+        ));
+        assert_eq!(err.to_string(), "sqrt expects 1 argument, got 2");
+        assert_eq!(
+            eval_str("max()").unwrap_err().to_string(),
+            "max expects at least 1 argument, got 0"
+        );
+    }
+
+    #[test]
+    fn test_unknown_function() {
+        assert!(matches!(
+            eval_str("sqr(4)"),
+            Err(CalcError::UnknownFunction(name, Some(s), _)) if name == "sqr" && s == "sqrt"
+        ));
+    }
+}
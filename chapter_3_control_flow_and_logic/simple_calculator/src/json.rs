@@ -0,0 +1,163 @@
+//! Minimal JSON values for machine-readable output
+//!
+//! Only writing is supported. Objects keep their fields in insertion order, and
+//! numbers that JSON cannot express (NaN and the infinities) are written as
+//! `null`.
+
+use crate::{CalcError, Span};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Object with the given fields, in order
+    pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// `{"start": .., "end": ..}`
+    pub fn span(span: Span) -> Json {
+        Json::object([
+            ("start", Json::from(span.start)),
+            ("end", Json::from(span.end)),
+        ])
+    }
+
+    /// `{"kind": .., "message": .., "span": ..}` for `error`
+    pub fn error(error: &CalcError) -> Json {
+        Json::object([
+            ("kind", Json::from(error.kind())),
+            ("message", Json::from(error.to_string())),
+            ("span", Json::span(error.span())),
+        ])
+    }
+}
+
+impl From<f64> for Json {
+    fn from(n: f64) -> Json {
+        Json::Number(n)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::String(s)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+/// Write `s` as a JSON string literal
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Compact JSON on a single line
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Json::Number(_) => f.write_str("null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, evaluate, parse};
+
+    #[test]
+    fn test_values() {
+        assert_eq!(Json::from(3.0).to_string(), "3");
+        assert_eq!(Json::from(-0.25).to_string(), "-0.25");
+        assert_eq!(Json::from(f64::NAN).to_string(), "null");
+        assert_eq!(Json::from(None::<&str>).to_string(), "null");
+        let value = Json::object([
+            ("list", Json::Array(vec![Json::from(true), Json::Null])),
+            ("empty", Json::object(Vec::<(String, Json)>::new())),
+        ]);
+        assert_eq!(value.to_string(), r#"{"list":[true,null],"empty":{}}"#);
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let s = Json::from("say \"hi\"\\\n\t\u{1}∠");
+        assert_eq!(s.to_string(), r#""say \"hi\"\\\n\t\u0001∠""#);
+    }
+
+    #[test]
+    fn test_error() {
+        let err = evaluate(&parse("1 / (2 - 2)").unwrap(), &Environment::<f64>::new()).unwrap_err();
+        assert_eq!(
+            Json::error(&err).to_string(),
+            r#"{"kind":"division_by_zero","message":"division by zero","span":{"start":4,"end":11}}"#
+        );
+    }
+}
@@ -0,0 +1,390 @@
+//! Lexer: turns an input line into typed tokens with byte spans
+//!
+//! Whitespace only separates tokens, so `2+3`, `(1)` and `3*-2` lex the same as
+//! their spaced-out forms.
+
+use crate::ast::Literal;
+use crate::registry::{Implementation, Registry};
+use crate::{CalcError, Op};
+use std::fmt;
+
+/// Byte range `start..end` into the input line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// Kinds of token the calculator understands
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// Number literal, including forms like `.5`, `6.02e23`, imaginary `2i`
+    /// and whole numbers in another radix: `0xFF`, `0o17`, `0b1010_0101`
+    Number(Literal),
+    /// One of the symbolic operators `+ - * / // % ^ & | ^^ << >> ~`, or
+    /// another symbol registered for a built-in operator
+    Op(Op),
+    /// Symbol of a custom operator from the registry
+    Operator(String),
+    /// Postfix `!` for factorial
+    Bang,
+    LParen,
+    RParen,
+    Comma,
+    /// `=` in an assignment
+    Assign,
+    /// Name made of letters, digits and `_`, not starting with a digit
+    Ident(String),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Number(n) => f.write_str(&n.text),
+            TokenKind::Op(op) => write!(f, "{}", op),
+            TokenKind::Operator(symbol) => f.write_str(symbol),
+            TokenKind::Bang => f.write_str("!"),
+            TokenKind::LParen => f.write_str("("),
+            TokenKind::RParen => f.write_str(")"),
+            TokenKind::Comma => f.write_str(","),
+            TokenKind::Assign => f.write_str("="),
+            TokenKind::Ident(name) => f.write_str(name),
+        }
+    }
+}
+
+/// A token and where it came from in the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Split `input` into tokens, knowing the built-in operators
+pub fn tokenize(input: &str) -> Result<Vec<Token>, CalcError> {
+    tokenize_with(input, Registry::builtin())
+}
+
+/// Split `input` into tokens, knowing the operator symbols in `registry`.
+/// The longest symbol wins, so `**` is one token when it is registered.
+pub fn tokenize_with(input: &str, registry: &Registry) -> Result<Vec<Token>, CalcError> {
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while let Some(c) = input[pos..].chars().next() {
+        let start = pos;
+        let kind = match c {
+            c if c.is_whitespace() => {
+                pos += c.len_utf8();
+                continue;
+            }
+            '0' if is_radix_prefix(bytes, pos) => {
+                pos += 2;
+                while bytes
+                    .get(pos)
+                    .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_')
+                {
+                    pos += 1;
+                }
+                let text = &input[start..pos];
+                let digits: String = text[2..].chars().filter(|&c| c != '_').collect();
+                let radix = Literal::radix_of(text).unwrap_or(10);
+                let n = u128::from_str_radix(&digits, radix)
+                    .map_err(|e| CalcError::ParseInt(text.to_string(), e, Span::new(start, pos)))?;
+                TokenKind::Number(Literal {
+                    value: n as f64,
+                    text: text.to_string(),
+                    imaginary: false,
+                })
+            }
+            '0'..='9' | '.' => {
+                pos = scan_number(input, pos)?;
+                let digits = &input[start..pos];
+                let n = digits.parse::<f64>().map_err(|e| {
+                    CalcError::ParseFloat(digits.to_string(), e, Span::new(start, pos))
+                })?;
+                // An `i` directly after the digits, not starting a longer name
+                let imaginary = bytes.get(pos) == Some(&b'i')
+                    && !bytes
+                        .get(pos + 1)
+                        .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_');
+                if imaginary {
+                    pos += 1;
+                }
+                TokenKind::Number(Literal {
+                    value: n,
+                    text: input[start..pos].to_string(),
+                    imaginary,
+                })
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                pos += input[pos..]
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(input.len() - pos);
+                TokenKind::Ident(input[start..pos].to_string())
+            }
+            _ if let Some((symbol, op)) = registry.match_symbol(&input[pos..]) => {
+                pos += symbol.len();
+                match &op.implementation {
+                    Implementation::Builtin(op) => TokenKind::Op(*op),
+                    Implementation::Custom(_) => TokenKind::Operator(op.symbol.clone()),
+                }
+            }
+            _ => {
+                pos += c.len_utf8();
+                match c {
+                    '!' => TokenKind::Bang,
+                    '(' => TokenKind::LParen,
+                    ')' => TokenKind::RParen,
+                    ',' => TokenKind::Comma,
+                    '=' => TokenKind::Assign,
+                    other => {
+                        return Err(CalcError::UnknownOperator(
+                            other.to_string(),
+                            Span::new(start, pos),
+                        ));
+                    }
+                }
+            }
+        };
+        tokens.push(Token {
+            kind,
+            span: Span::new(start, pos),
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Whether a `0x`, `0o` or `0b` literal starts at `pos`: the prefix must be
+/// followed by a letter or digit, so `0b` alone is still `0` and a name
+fn is_radix_prefix(bytes: &[u8], pos: usize) -> bool {
+    matches!(bytes.get(pos + 1), Some(b'x' | b'o' | b'b'))
+        && bytes.get(pos + 2).is_some_and(u8::is_ascii_alphanumeric)
+}
+
+/// End of the number literal starting at `pos`: digits, an optional fraction and
+/// an optional exponent. An `e` that starts a longer name, as in `2exp`, is left
+/// to the name; otherwise it needs digits, so `1e` and `2e+` are errors.
+fn scan_number(input: &str, mut pos: usize) -> Result<usize, CalcError> {
+    let bytes = input.as_bytes();
+    let start = pos;
+    let digits = |mut p: usize| {
+        while p < bytes.len() && bytes[p].is_ascii_digit() {
+            p += 1;
+        }
+        p
+    };
+    pos = digits(pos);
+    if bytes.get(pos) == Some(&b'.') {
+        pos = digits(pos + 1);
+    }
+    if matches!(bytes.get(pos), Some(b'e' | b'E')) {
+        let mut exp = pos + 1;
+        if matches!(bytes.get(exp), Some(b'+' | b'-')) {
+            exp += 1;
+        }
+        if bytes.get(exp).is_some_and(u8::is_ascii_digit) {
+            pos = digits(exp);
+        } else if exp > pos + 1
+            || !bytes
+                .get(exp)
+                .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        {
+            return Err(CalcError::MalformedExponent(
+                input[start..exp].to_string(),
+                Span::new(pos, exp),
+            ));
+        }
+    }
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_whitespace_independent() {
+        use TokenKind::*;
+        assert_eq!(kinds("2+3"), kinds("2 + 3"));
+        assert_eq!(kinds("(1)"), vec![LParen, Number(1.0.into()), RParen]);
+        assert_eq!(
+            kinds("3*-2"),
+            vec![
+                Number(3.0.into()),
+                Op(crate::Op::Mul),
+                Op(crate::Op::Sub),
+                Number(2.0.into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        use TokenKind::*;
+        assert_eq!(kinds("6.02e23"), vec![Number(6.02e23.into())]);
+        assert_eq!(
+            kinds("1E-3 .5"),
+            vec![Number(1e-3.into()), Number(0.5.into())]
+        );
+        assert_eq!(
+            kinds("2exp"),
+            vec![Number(2.0.into()), Ident("exp".to_string())]
+        );
+        for (input, span) in [("1e", Span::new(1, 2)), ("2 * 1.5E- 3", Span::new(7, 9))] {
+            assert!(
+                matches!(tokenize(input), Err(CalcError::MalformedExponent(_, s)) if s == span),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(tokenize("."), Err(CalcError::ParseFloat(..))));
+    }
+
+    #[test]
+    fn test_imaginary_literals() {
+        let tokens = tokenize("2.5i+1e3i").unwrap();
+        let TokenKind::Number(lit) = &tokens[0].kind else {
+            panic!("expected a number, got {:?}", tokens[0]);
+        };
+        assert!(lit.imaginary && lit.value == 2.5 && lit.text == "2.5i");
+        assert_eq!(tokens[0].span, Span::new(0, 4));
+        assert_eq!(tokens[2].span, Span::new(5, 9));
+        // Only a lone `i` is a suffix
+        assert_eq!(
+            kinds("2in"),
+            vec![
+                TokenKind::Number(2.0.into()),
+                TokenKind::Ident("in".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_division_operators() {
+        use TokenKind::*;
+        let tokens = tokenize("7//2/1%3!").unwrap();
+        assert_eq!(tokens[1].kind, Op(crate::Op::IntDiv));
+        assert_eq!(tokens[1].span, Span::new(1, 3));
+        assert_eq!(tokens[3].kind, Op(crate::Op::Div));
+        assert_eq!(tokens[5].kind, Op(crate::Op::Rem));
+        assert_eq!(tokens[7].kind, Bang);
+    }
+
+    #[test]
+    fn test_idents_commas_and_spans() {
+        let tokens = tokenize(" max(x_1, 2)").unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::Ident("max".to_string()));
+        assert_eq!(tokens[0].span, Span::new(1, 4));
+        assert_eq!(tokens[2].kind, TokenKind::Ident("x_1".to_string()));
+        assert_eq!(tokens[3].kind, TokenKind::Comma);
+        assert_eq!(tokens[5].span, Span::new(11, 12));
+    }
+
+    #[test]
+    fn test_radix_literals() {
+        let tokens = tokenize("0xFF+0b1010_0101 0o17").unwrap();
+        let values: Vec<f64> = tokens
+            .iter()
+            .filter_map(|t| match &t.kind {
+                TokenKind::Number(lit) => Some(lit.value),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(values, [255.0, 165.0, 15.0]);
+        assert_eq!(tokens[2].span, Span::new(5, 16));
+        assert!(matches!(
+            tokenize("1 + 0b102"),
+            Err(CalcError::ParseInt(text, _, span)) if text == "0b102" && span == Span::new(4, 9)
+        ));
+        // No digits after the prefix: zero times a name, as before
+        assert_eq!(
+            kinds("0b"),
+            vec![
+                TokenKind::Number(0.0.into()),
+                TokenKind::Ident("b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("~a & b | c ^^ d << 1 >> 2 ^ 3"),
+            vec![
+                Op(crate::Op::BitNot),
+                Ident("a".to_string()),
+                Op(crate::Op::BitAnd),
+                Ident("b".to_string()),
+                Op(crate::Op::BitOr),
+                Ident("c".to_string()),
+                Op(crate::Op::BitXor),
+                Ident("d".to_string()),
+                Op(crate::Op::Shl),
+                Number(1.0.into()),
+                Op(crate::Op::Shr),
+                Number(2.0.into()),
+                Op(crate::Op::Pow),
+                Number(3.0.into()),
+            ]
+        );
+        assert!(matches!(
+            tokenize("1 < 2"),
+            Err(CalcError::UnknownOperator(op, _)) if op == "<"
+        ));
+    }
+
+    #[test]
+    fn test_registered_symbols() {
+        use crate::registry::{Assoc, Operator};
+        let mut registry = Registry::default();
+        registry.register_operator(Operator::infix("<=>", 4, Assoc::Left, |a, b| {
+            (a - b).signum()
+        }));
+        registry.register_operator(Operator::builtin(Op::Pow).alias("**"));
+        let tokens = tokenize_with("1<=>2**3<<4", &registry).unwrap();
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Number(1.0.into()),
+                TokenKind::Operator("<=>".to_string()),
+                TokenKind::Number(2.0.into()),
+                TokenKind::Op(Op::Pow),
+                TokenKind::Number(3.0.into()),
+                TokenKind::Op(Op::Shl),
+                TokenKind::Number(4.0.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_character() {
+        assert!(matches!(
+            tokenize("10 @ 3"),
+            Err(CalcError::UnknownOperator(op, span)) if op == "@" && span == Span::new(3, 4)
+        ));
+    }
+}
@@ -1,124 +1,106 @@
-//! Calculator core: parsing and evaluating simple binary expressions
+//! Calculator core: parsing and evaluating arithmetic expressions
 //!
-//! The library exposes 'parse_expression 'and 'evaluate' for testing
+//! The library exposes 'parse_expression' and 'evaluate' for testing; the parsed
+//! tree ('Expr') prints back as a normalized formula, and 'execute' runs whole
+//! lines (including assignments and function definitions) against an 'Environment'.
+//! Evaluation is generic over 'Number': plain f64, exact 'Rational' and
+//! 'BigInt', 'Complex', or fixed-width 'Word' integers for programmer mode.
+//! 'Rpn' runs reverse Polish input on a persistent stack, 'simplify' tidies a
+//! formula, 'derive' differentiates one, 'solve' finds its roots, and 'compile'
+//! turns one into a 'Program' for fast repeated evaluation. 'integrate', 'sum'
+//! and 'prod' bind a variable inside expressions ('calculus'), 'plot' draws
+//! functions of one variable as text, and a 'Registry' adds custom operators
+//! and functions to the built-in ones. 'Formatter' writes results in fixed,
+//! scientific or engineering notation or to significant figures. 'Calculator'
+//! ties it all together as a session, configured with a builder, that runs
+//! input line by line the way the REPL does
 
-use std::fmt;
-use std::num::ParseFloatError;
+pub mod ast;
+pub mod calculator;
+pub mod calculus;
+pub mod derive;
+pub mod env;
+pub mod error;
+pub mod eval;
+pub mod formatter;
+pub mod functions;
+pub mod json;
+pub mod lexer;
+pub mod number;
+pub mod parser;
+pub mod plot;
+pub mod registry;
+pub mod rpn;
+pub mod simplify;
+pub mod solve;
+pub mod vm;
 
-/// Supported binary operations
+pub use ast::{Expr, ExprKind, Literal, Statement};
+pub use calculator::{
+    Calculator, CalculatorBuilder, HistoryEntry, Limits, Line, LineError, NumberMode, Output,
+    OutputFormat,
+};
+pub use calculus::Integral;
+pub use derive::{derive, derive_in};
+pub use env::{ANS, DEFAULT_MAX_DEPTH, Environment, UserFunction};
+pub use error::CalcError;
+pub use eval::{AngleUnit, EvalPolicy, apply, apply_ieee, call, evaluate, execute, integral};
+pub use formatter::{Formatter, Notation};
+pub use json::Json;
+pub use lexer::{Span, Token, TokenKind, tokenize, tokenize_with};
+pub use number::{BigInt, BigUint, Complex, Format, IntType, Number, Overflow, Rational, Word};
+pub use parser::{
+    parse, parse_expression, parse_expression_with, parse_statement, parse_statement_tokens,
+    parse_statement_tokens_with, parse_statement_with, parse_with,
+};
+pub use registry::{Assoc, CustomFunction, Implementation, Operator, Registry};
+pub use rpn::{Rpn, RpnView, parse_rpn, parse_rpn_with};
+pub use simplify::simplify;
+pub use solve::{parse_equation, parse_equation_with, solve, solve_all};
+pub use vm::{Program, compile, compile_expr};
+
+/// Supported operations: every one is binary except `BitNot`
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Op {
     Add,
     Sub,
     Mul,
     Div,
+    /// Euclidean division `//`: the quotient that leaves a non-negative remainder
+    IntDiv,
+    /// Euclidean remainder `%` or `mod`, always in `0..|b|`
+    Rem,
     Pow,
+    /// Bitwise `&`, `|` and `^^` (or `xor`), for programmer mode
+    BitAnd,
+    BitOr,
+    BitXor,
+    /// Shifts `<<` and `>>`; `>>` keeps the sign of signed integers
+    Shl,
+    Shr,
+    /// Prefix `~`, which flips every bit
+    BitNot,
 }
 
-/// Error type for parse/eval issues
-#[derive(Debug)]
-pub enum CalcError {
-    ParseFloat(ParseFloatError),
-    UnknownOperator(String),
-    WrongArity,
-    DivisionByZero,
-}
-
-impl fmt::Display for CalcError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Op {
+    /// Binding power of the operator; higher binds tighter
+    ///
+    /// The bitwise operators bind looser than arithmetic, in the order C
+    /// uses, so `1 << 4 - 1` is `1 << 3` and `a & 0xF | b` is `(a & 0xF) | b`.
+    pub fn precedence(self) -> u8 {
         match self {
-            CalcError::ParseFloat(e) => write!(f, "number parse error: {}", e),
-            CalcError::UnknownOperator(op) => write!(f, "unknown operator: {}", op),
-            CalcError::WrongArity => write!(f, "wrong number of operands"),
-            CalcError::DivisionByZero => write!(f, "division by zero"),
+            Op::BitOr => 1,
+            Op::BitXor => 2,
+            Op::BitAnd => 3,
+            Op::Shl | Op::Shr => 4,
+            Op::Add | Op::Sub => 5,
+            Op::Mul | Op::Div | Op::IntDiv | Op::Rem => 6,
+            Op::Pow | Op::BitNot => 7,
         }
     }
-}
-impl std::error::Error for CalcError {}
-
-impl From<ParseFloatError> for CalcError {
-    fn from(e: ParseFloatError) -> Self {
-        CalcError::ParseFloat(e)
-    }
-}
-
-/// Evaluate an operation on two f64 operands
-pub fn evaluate(op: Op, a: f64, b: f64) -> Result<f64, CalcError> {
-    match op {
-        Op::Add => Ok(a + b),
-        Op::Sub => Ok(a - b),
-        Op::Mul => Ok(a * b),
-        Op::Div => {
-            if b == 0.0 {
-                Err(CalcError::DivisionByZero)
-            } else {
-                Ok(a / b)
-            }
-        }
-        Op::Pow => Ok(a.powf(b)),
-    }
-}
-
-/// Try to parse operator token
-fn parse_op(token: &str) -> Result<Op, CalcError> {
-    match token {
-        "+" | "add" => Ok(Op::Add),
-        "-" | "sub" => Ok(Op::Sub),
-        "*" | "mul" => Ok(Op::Mul),
-        "/" | "div" => Ok(Op::Div),
-        "^" | "pow" => Ok(Op::Pow),
-        other => Err(CalcError::UnknownOperator(other.to_string())),
-    }
-}
-
-/// Parse a tokenized expression into (Op, operand1, operand2)
-pub fn parse_expression(tokens: &[&str]) -> Result<(Op, f64, f64), CalcError> {
-    match tokens {
-        [op, a, b] => {
-            let op = parse_op(*op)?;
-            let a = a.parse::<f64>()?;
-            let b = b.parse::<f64>()?;
-            Ok((op, a, b))
-        }
-        _ => Err(CalcError::WrongArity),
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_add_prefix() {
-        let tokens = ["add", "2", "3"];
-        let (op, a, b) = parse_expression(&tokens).unwrap();
-        assert_eq!(op, Op::Add);
-        assert_eq!(evaluate(op, a, b).unwrap(), 5.0);
-    }
-
-    #[test]
-    fn test_mul_infix() {
-        let tokens = ["*", "4", "2.5"];
-        let (op, a, b) = parse_expression(&tokens).unwrap();
-        assert_eq!(op, Op::Mul);
-        assert!((evaluate(op, a, b).unwrap() - 10.0).abs() < 1e-12);
-    }
-
-    #[test]
-    fn test_div_by_zero() {
-        let tokens = ["/", "10", "0"];
-        let (op, a, b) = parse_expression(&tokens).unwrap();
-        let r = evaluate(op, a, b);
-        println!("{:?}", r);
-        assert!(matches!(evaluate(op, a, b), Err(CalcError::DivisionByZero)));
-    }
 
-    #[test]
-    fn test_unknown_operator() {
-        let tokens = ["%", "10", "3"];
-        assert!(matches!(
-            parse_expression(&tokens),
-            Err(CalcError::UnknownOperator(_))
-        ));
+    /// `^` groups to the right (`2 ^ 3 ^ 2` is `2 ^ (3 ^ 2)`), everything else to the left
+    pub fn is_right_assoc(self) -> bool {
+        matches!(self, Op::Pow)
     }
 }
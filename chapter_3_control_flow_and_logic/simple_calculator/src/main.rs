@@ -1,23 +1,87 @@
-use simple_calculator::{evaluate, parse_expression};
-use std::io::{self, Write};
+use simple_calculator::calculator::{HELP, strip_comment};
+use simple_calculator::plot::{Canvas, Charset};
+use simple_calculator::{Calculator, LineError, Output, OutputFormat};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::ExitCode;
 
 // Simple calculator example
 
 // Design goals
-//   Support binary operations
+//   Support full infix expressions with precedence and parentheses
 //   Accept both words and symbols: add 2 3, + 2 3, 2 + 3
+//   Built-in functions (sqrt, ln, sin, min, max, ...) and constants (pi, e, tau)
+//   Remember variables (x = 4.2) and the last result (ans) for the whole session
+//   User-defined functions: hyp(a, b) = sqrt(a^2 + b^2)
+//   Exact rational mode, where 0.1 + 0.2 is exactly 3/10
+//   Exact integer mode for big results like 2^200 and 50!
+//   Complex mode with the imaginary unit i, where sqrt(-4) is 2i
+//   Programmer mode in i8..u128 with bitwise operators, 0x/0o/0b literals and a
+//   choice of wrapping, saturating or failing on overflow
+//   RPN mode with a persistent stack: 3 4 + 2 *
+//   Symbolic work on formulas: :simplify 2*x + x, d/dx x^3 + sin(x)
+//   Numeric root finding: solve x^3 - 2*x = 5 for x in [0, 3]
+//   Integrals, sums and products: integrate(x^2, x, 0, 3), sum(1/n^2, n, 1, 100)
+//   Text plots sized to the terminal: plot sin(x) * x; cos(x) from -10 to 10
 //   Provide a REPL (read, eval print loop)
+//   Batch mode for files and pipes, with exit codes for scripts
+//   JSON output for tools that run the calculator as a subprocess
 //   Gracefully handle errors (bad parse, division by zero)
-//   Expose testable functions
+//   Report undefined and overflowing results (sqrt(-1), 10^400) as errors, or
+//   let NaN and inf through with 'policy ieee'
+//   Trigonometry in radians or, with 'angle degrees', in degrees
+//   Results in fixed, scientific or engineering notation or to significant
+//   figures, with optional digit grouping: format fixed 2, grouping on
+//   Meta-commands (:help, :vars, :history, :mode, :reset, :save, :load), and
+//   $3 for the value of history entry 3
+//   Expose testable functions, and the whole session as a 'Calculator' for
+//   other front ends
 
-fn main() -> Result<(), Box<dyn ::std::error::Error>> {
+const USAGE: &str = "\
+Usage: simple_calculator [--fail-fast] [--json] [FILE | -]
+
+Without FILE and with stdin on a terminal, starts the interactive REPL.
+Otherwise reads FILE (or stdin for '-' or a pipe) and prints one result per
+line, without prompts. '#' starts a comment.
+
+Options:
+  --fail-fast  stop at the first line that fails
+  --json       print one JSON object per line, errors included
+  -h, --help   show this help
+
+Exit status: 0 if every line succeeded, 1 if the first failure was an
+evaluation error, 2 if it was a parse error, 3 for bad arguments or input.";
+
+/// Exit status for a line that failed to evaluate
+const EXIT_EVAL_ERROR: u8 = 1;
+/// Exit status for a line that failed to parse
+const EXIT_PARSE_ERROR: u8 = 2;
+/// Exit status for bad arguments or unreadable input
+const EXIT_USAGE: u8 = 3;
+
+fn exit_code(e: &LineError) -> u8 {
+    match e {
+        LineError::Eval(_) => EXIT_EVAL_ERROR,
+        LineError::Parse(_) | LineError::Usage(_) | LineError::UnknownCommand(..) => {
+            EXIT_PARSE_ERROR
+        }
+        LineError::File(_) => EXIT_USAGE,
+    }
+}
+
+fn repl(json: bool) -> io::Result<()> {
     println!("Simple calculator REPL");
-    println!("Enter expressions like + 2 3 or add 2 3");
-    println!("Type 'quit' or 'exit' to leave");
+    println!("{}", HELP);
 
     let stdin = io::stdin();
+    let mut calculator = Calculator::builder()
+        .output(OutputFormat {
+            json,
+            ..OutputFormat::default()
+        })
+        .canvas(terminal_canvas())
+        .build();
     loop {
-        print!("> ");
+        print!("{}> ", if calculator.rpn() { "rpn" } else { "" });
         io::stdout().flush()?;
 
         let mut line = String::new();
@@ -27,7 +91,7 @@ fn main() -> Result<(), Box<dyn ::std::error::Error>> {
             break;
         }
 
-        let trimmed = line.trim();
+        let trimmed = strip_comment(&line);
         if trimmed.is_empty() {
             continue;
         }
@@ -35,15 +99,417 @@ fn main() -> Result<(), Box<dyn ::std::error::Error>> {
             break;
         }
 
-        let tokens: Vec<&str> = trimmed.split_whitespace().collect();
-        match parse_expression(&tokens) {
-            Ok((op, a, b)) => match evaluate(op, a, b) {
-                Ok(result) => println!(" = {}", result),
-                Err(e) => eprintln!("Error evaluating expression: {}", e),
-            },
-            Err(e) => eprintln!("Parse error: {}", e),
+        let json = calculator.output().json;
+        let line = calculator.eval_line(trimmed);
+        if json {
+            println!("{}", line.to_json(trimmed, None));
+            continue;
+        }
+        match line.outcome {
+            Ok(Output::Value(value, _)) => println!(" = {}", value),
+            Ok(Output::Defined(stmt)) => println!(" defined {}", stmt),
+            Ok(Output::Note(text)) => println!(" {}", text),
+            Ok(Output::Plot(text)) => print!("{}", text),
+            Ok(Output::Listing(lines)) => {
+                for line in lines {
+                    println!(" {}", line);
+                }
+            }
+            Ok(Output::Stack(values)) if values.is_empty() => println!(" (empty stack)"),
+            Ok(Output::Stack(values)) => {
+                // Level 1 is the top, as on HP calculators
+                for (i, value) in values.iter().enumerate() {
+                    println!(" {}: {}", values.len() - i, value);
+                }
+            }
+            Ok(Output::Empty) => println!(" (empty stack)"),
+            Err(e) => eprintln!("{}", e.render(trimmed)),
         }
     }
 
     Ok(())
 }
+
+/// A plot canvas filling the terminal, as far as the environment tells; the
+/// rows leave room for the axis labels, a legend and the next prompt
+fn terminal_canvas() -> Canvas {
+    let size = |var: &str| {
+        std::env::var(var)
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+    };
+    let default = Canvas::default();
+    let unicode = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .is_some_and(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        });
+    Canvas {
+        width: size("COLUMNS").unwrap_or(default.width),
+        height: size("LINES").map_or(default.height, |lines| lines.saturating_sub(6)),
+        charset: if unicode {
+            Charset::Unicode
+        } else {
+            Charset::Ascii
+        },
+    }
+}
+
+/// Run every line of `input`, writing bare results to `out` and errors, with
+/// their line numbers, to stderr; in JSON output every line, failed or not,
+/// goes to `out`. Returns the exit status.
+fn batch(input: impl BufRead, out: &mut impl Write, json: bool, fail_fast: bool) -> io::Result<u8> {
+    let mut calculator = Calculator::builder()
+        .output(OutputFormat {
+            json,
+            ..OutputFormat::default()
+        })
+        .build();
+    let mut status = 0;
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        let trimmed = strip_comment(&line);
+        if trimmed.is_empty() {
+            continue;
+        }
+        // The line is reported in the output format it was typed in
+        let json = calculator.output().json;
+        let result = calculator.eval_line(trimmed);
+        if json {
+            writeln!(out, "{}", result.to_json(trimmed, Some(number + 1)))?;
+        }
+        match result.outcome {
+            Ok(_) if json => {}
+            Ok(Output::Value(value, _)) => writeln!(out, "{}", value)?,
+            Ok(Output::Plot(text)) => write!(out, "{}", text)?,
+            Ok(Output::Listing(lines)) => {
+                for line in lines {
+                    writeln!(out, "{}", line)?;
+                }
+            }
+            Ok(Output::Stack(values)) => {
+                for (i, value) in values.iter().enumerate() {
+                    writeln!(out, "{}: {}", values.len() - i, value)?;
+                }
+            }
+            Ok(Output::Defined(_) | Output::Note(_) | Output::Empty) => {}
+            Err(e) => {
+                if !json {
+                    out.flush()?;
+                    eprintln!("line {}: {}", number + 1, e.render(trimmed));
+                }
+                if status == 0 {
+                    status = exit_code(&e);
+                }
+                if fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+    out.flush()?;
+    Ok(status)
+}
+
+fn main() -> ExitCode {
+    let mut fail_fast = false;
+    let mut json = false;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--fail-fast" => fail_fast = true,
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            flag if flag.starts_with('-') && flag != "-" => {
+                eprintln!("unknown option: {}\n\n{}", flag, USAGE);
+                return ExitCode::from(EXIT_USAGE);
+            }
+            _ if path.is_some() => {
+                eprintln!("only one input file may be given\n\n{}", USAGE);
+                return ExitCode::from(EXIT_USAGE);
+            }
+            file => path = Some(file.to_string()),
+        }
+    }
+
+    let out = &mut io::stdout().lock();
+    let result = match path.as_deref() {
+        Some("-") => batch(io::stdin().lock(), out, json, fail_fast),
+        Some(file) => match std::fs::File::open(file) {
+            Ok(f) => batch(io::BufReader::new(f), out, json, fail_fast),
+            Err(e) => {
+                eprintln!("cannot open {}: {}", file, e);
+                return ExitCode::from(EXIT_USAGE);
+            }
+        },
+        None if !io::stdin().is_terminal() => batch(io::stdin().lock(), out, json, fail_fast),
+        None => repl(json).map(|()| 0),
+    };
+    match result {
+        Ok(status) => ExitCode::from(status),
+        Err(e) => {
+            eprintln!("I/O error: {}", e);
+            ExitCode::from(EXIT_USAGE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_calculator::{CalcError, Span};
+
+    fn run_batch(script: &str, fail_fast: bool) -> (String, u8) {
+        let mut out = Vec::new();
+        let status = batch(script.as_bytes(), &mut out, false, fail_fast).unwrap();
+        (String::from_utf8(out).unwrap(), status)
+    }
+
+    #[test]
+    fn test_batch_prints_one_result_per_line() {
+        let script = "\
+# Monthly totals
+rent = 1200   # fixed
+rent * 12
+sq(x) = x * x
+
+sq(3) + ans
+mode rational
+0.1 + 0.2
+";
+        assert_eq!(
+            run_batch(script, false),
+            ("1200\n14400\n14409\n3/10\n".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn test_batch_exit_codes() {
+        assert_eq!(
+            run_batch("1 / 0\n2 +\n3", false),
+            ("3\n".to_string(), EXIT_EVAL_ERROR)
+        );
+        assert_eq!(
+            run_batch("2 +\n1 / 0\n3", false),
+            ("3\n".to_string(), EXIT_PARSE_ERROR)
+        );
+        assert_eq!(
+            run_batch("1\n2 +\n3", true),
+            ("1\n".to_string(), EXIT_PARSE_ERROR)
+        );
+        assert_eq!(run_batch("mode hex", false).1, EXIT_PARSE_ERROR);
+        assert_eq!(run_batch(":vras", false).1, EXIT_PARSE_ERROR);
+        assert_eq!(
+            run_batch(":load /nonexistent/session.txt\n1", false),
+            ("1\n".to_string(), EXIT_USAGE)
+        );
+    }
+
+    #[test]
+    fn test_simplify_command() {
+        assert_eq!(
+            run_batch(
+                ":simplify 2*x + x*1 - 0\n:simplify f(x) = --x ^ 1\nx",
+                false
+            ),
+            ("3 * x\nf(x) = x\n".to_string(), EXIT_EVAL_ERROR)
+        );
+        let mut calculator = Calculator::new();
+        let line = calculator.eval_line(":simplify 1 +");
+        assert!(matches!(line.outcome, Err(LineError::Parse(e)) if e.span() == Span::new(13, 13)));
+        assert!(matches!(
+            calculator.eval_line(":simplify").outcome,
+            Err(LineError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn test_derivative_command() {
+        assert_eq!(
+            run_batch("d/dx x^3 + sin(x)\nd/dy x*y^2\nd/dx = 1", false),
+            (
+                "3 * x ^ 2 + cos(x)\n2 * x * y\n".to_string(),
+                EXIT_PARSE_ERROR
+            )
+        );
+        let line = Calculator::new().eval_line("d/dt 2 * floor(t)");
+        assert!(matches!(line.outcome, Err(LineError::Eval(e)) if e.span() == Span::new(9, 17)));
+    }
+
+    #[test]
+    fn test_solve_command() {
+        let script = "\
+a = 2
+solve x^2 = a for x in [0, a]
+solve_all x^3 = x for x in [-2, 2]
+solve_all x^2 + 1 for t in [0, 1]
+solve x^2 + 1 for x in [0, 1]
+solve x for x in 0, 1
+";
+        let (out, status) = run_batch(script, false);
+        assert_eq!(out, "2\n1.414213562373095\n-1, 0, 1\n");
+        assert_eq!(status, EXIT_EVAL_ERROR);
+        let mut calculator = Calculator::new();
+        let line = calculator.eval_line("solve x = 1 for x in [0, 1 +]");
+        assert!(matches!(line.outcome, Err(LineError::Parse(e)) if e.span() == Span::new(28, 28)));
+        let line = calculator.eval_line("solve x^2 + 1 for x in [-1, 1]");
+        assert!(matches!(line.outcome, Err(LineError::Eval(e)) if e.span() == Span::new(6, 13)),);
+    }
+
+    #[test]
+    fn test_integrate_and_sum() {
+        let script = "\
+integrate(2 * x, x, 0, 3)
+ans + sum(i, i, 1, 4)
+mode rational
+sum(1 / k, k, 1, 4) * prod(k, k, 1, 3)
+integrate(1, x, 0, 1)
+";
+        let (out, status) = run_batch(script, false);
+        assert_eq!(out, "9\n19\n25/2\n");
+        assert_eq!(status, EXIT_EVAL_ERROR);
+    }
+
+    #[test]
+    fn test_plot_command() {
+        let mut calculator = Calculator::builder()
+            .canvas(Canvas {
+                width: 32,
+                height: 5,
+                charset: Charset::Ascii,
+            })
+            .build();
+        let line = calculator.eval_line("plot x^2; 2 - x from -1 to 1 + 1");
+        assert_eq!(line.normalized.as_deref(), Some("x ^ 2; 2 - x"));
+        let Ok(Output::Plot(text)) = line.outcome else {
+            panic!("expected a plot");
+        };
+        let expected = "
+         4 |      |            *
+           |ooooo |          ***
+           |    ooooooo    ***
+           |***   |   ooooooo
+         0 |--**********----oooo
+           +--------------------
+           -1     0            2
+            * x ^ 2
+            o 2 - x
+";
+        assert_eq!(text, &expected[1..], "\n{}", text);
+        let line = calculator.eval_line("plot x; sqrt(y) from 0 to 1");
+        assert!(matches!(line.outcome, Err(LineError::Eval(e)) if e.span() == Span::new(13, 14)));
+        let line = calculator.eval_line("plot x; ( from 0 to 1");
+        assert!(matches!(line.outcome, Err(LineError::Parse(e)) if e.span() == Span::new(9, 9)));
+    }
+
+    #[test]
+    fn test_programmer_mode() {
+        let script = "\
+x = 200
+mode u8
+x + 0x0F ^^ 0b1
+~x >> 2 | 1 << 7
+overflow wrapping
+x * 2
+mode i8
+0xFF xor 1
+overflow saturating
+-100 - 100
+overflow error
+100 + 100
+";
+        let (out, status) = run_batch(script, false);
+        let expected = "\
+200
+214 = 0xD6 = 0o326 = 0b1101_0110
+141 = 0x8D = 0o215 = 0b1000_1101
+144 = 0x90 = 0o220 = 0b1001_0000
+-2 = 0xFE = 0o376 = 0b1111_1110
+-128 = 0x80 = 0o200 = 0b1000_0000
+";
+        assert_eq!(out, expected);
+        assert_eq!(status, EXIT_EVAL_ERROR);
+        let mut calculator = Calculator::new();
+        calculator.eval_line("mode u16");
+        let line = calculator.eval_line("1 + 0x1_0000");
+        assert!(matches!(line.outcome, Err(LineError::Eval(e)) if e.span() == Span::new(4, 12)));
+        let line = calculator.eval_line("mode float");
+        assert!(line.outcome.is_ok());
+        let line = calculator.eval_line("6 & 3");
+        assert!(matches!(
+            line.outcome,
+            Err(LineError::Eval(CalcError::Unsupported(..)))
+        ));
+    }
+
+    #[test]
+    fn test_policy_command() {
+        let script = "\
+ln(0)
+policy ieee
+x = ln(0)
+1 / 0 - x
+x + x
+policy strict
+x
+";
+        let (out, status) = run_batch(script, false);
+        assert_eq!(out, "-inf\ninf\n-inf\n");
+        assert_eq!(status, EXIT_EVAL_ERROR);
+        let mut calculator = Calculator::new();
+        calculator.eval_line("policy ieee");
+        calculator.eval_line("mode complex");
+        let line = calculator.eval_line("sqrt(-1) / 0");
+        assert!(matches!(line.outcome, Ok(Output::Value(text, _)) if text == "infi"));
+        let line = calculator.eval_line("sqrt(-1) / 0 * 0");
+        assert!(matches!(line.outcome, Ok(Output::Value(text, _)) if text == "NaN+NaNi"));
+        calculator.eval_line("policy strict");
+        let line = calculator.eval_line("1 / 0");
+        assert!(matches!(
+            line.outcome,
+            Err(LineError::Eval(CalcError::DivisionByZero(_)))
+        ));
+        calculator.eval_line("policy ieee");
+        calculator.eval_line("mode float");
+        let line = calculator.eval_line("ln(-1)");
+        assert!(matches!(line.outcome, Ok(Output::Value(text, _)) if text == "NaN"));
+    }
+
+    #[test]
+    fn test_batch_rpn() {
+        let (out, status) = run_batch("mode rpn\n3 4 +\n2 * dup stack\n( 1", false);
+        assert_eq!(
+            (out.as_str(), status),
+            ("7\n2: 14\n1: 14\n", EXIT_PARSE_ERROR)
+        );
+    }
+
+    #[test]
+    fn test_batch_json() {
+        let mut out = Vec::new();
+        let script = "2^10 # note\nmode rpn\n1 0 /\noutput text\n3 x";
+        let status = batch(script.as_bytes(), &mut out, true, false).unwrap();
+        assert_eq!(status, EXIT_EVAL_ERROR);
+        // Timings vary, so compare everything before them
+        let lines: Vec<String> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| l.split(",\"eval_time_us\"").next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"line":1,"input":"2^10","normalized":"2 ^ 10","result":"1024","value":1024"#,
+                r#"{"line":2,"input":"mode rpn","normalized":null,"setting":"mode rpn""#,
+                r#"{"line":3,"input":"1 0 /","normalized":"1 0 /","error":{"kind":"division_by_zero","message":"division by zero","span":{"start":4,"end":5}}"#,
+                r#"{"line":4,"input":"output text","normalized":null,"setting":"output text""#,
+            ]
+        );
+    }
+}
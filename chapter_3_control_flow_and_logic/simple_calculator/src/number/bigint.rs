@@ -0,0 +1,723 @@
+//! Arbitrary-size integers, enough for exact rational arithmetic
+//!
+//! Magnitudes are little-endian vectors of 32-bit limbs with no trailing zero
+//! limbs, so zero is the empty vector and equal values compare equal.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Non-negative arbitrary-size integer
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BigUint {
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    pub fn zero() -> BigUint {
+        BigUint::default()
+    }
+
+    pub fn one() -> BigUint {
+        BigUint::from(1u64)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_one(&self) -> bool {
+        self.limbs == [1]
+    }
+
+    pub fn is_even(&self) -> bool {
+        self.limbs.first().is_none_or(|l| l % 2 == 0)
+    }
+
+    /// Number of significant bits; zero has none
+    pub fn bits(&self) -> u64 {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() as u64 * 32 - u64::from(top.leading_zeros()),
+            None => 0,
+        }
+    }
+
+    fn from_limbs(mut limbs: Vec<u32>) -> BigUint {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigUint { limbs }
+    }
+
+    /// The value if it fits in a `u64`
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.limbs[..] {
+            [] => Some(0),
+            [lo] => Some(u64::from(lo)),
+            [lo, hi] => Some(u64::from(lo) | (u64::from(hi) << 32)),
+            _ => None,
+        }
+    }
+
+    /// The value if it fits in a `u128`
+    pub fn to_u128(&self) -> Option<u128> {
+        if self.limbs.len() > 4 {
+            return None;
+        }
+        let limbs = self.limbs.iter().rev();
+        Some(limbs.fold(0, |acc, &limb| (acc << 32) | u128::from(limb)))
+    }
+
+    /// Nearest `f64` (ties to even), or infinity when out of range
+    pub fn to_f64(&self) -> f64 {
+        let bits = self.bits();
+        if bits <= 64 {
+            return self.to_u64().unwrap_or_default() as f64;
+        }
+        // Keep the top 64 bits and fold every bit below them into the lowest
+        // one, so the conversion rounds as if it saw them all: a tie is only a
+        // tie when they are all zero
+        let shift = bits - 64;
+        let words = (shift / 32) as usize;
+        let low_mask = (1u32 << (shift % 32)) - 1;
+        let sticky =
+            self.limbs[..words].iter().any(|&l| l != 0) || self.limbs[words] & low_mask != 0;
+        let top = self.shr(shift).to_u64().unwrap_or_default() | u64::from(sticky);
+        top as f64 * 2f64.powi(shift.min(2000) as i32)
+    }
+
+    /// Value of a string of ASCII decimal digits
+    pub fn parse_decimal(digits: &str) -> Option<BigUint> {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut n = BigUint::zero();
+        for chunk in digits.as_bytes().chunks(9) {
+            let value = chunk
+                .iter()
+                .fold(0u32, |acc, d| acc * 10 + u32::from(d - b'0'));
+            n = n.mul_small(10u32.pow(chunk.len() as u32)).add_small(value);
+        }
+        Some(n)
+    }
+
+    fn add_small(&self, n: u32) -> BigUint {
+        self + &BigUint::from(u64::from(n))
+    }
+
+    fn mul_small(&self, n: u32) -> BigUint {
+        let mut carry = 0u64;
+        let mut limbs = Vec::with_capacity(self.limbs.len() + 1);
+        for &l in &self.limbs {
+            let t = u64::from(l) * u64::from(n) + carry;
+            limbs.push(t as u32);
+            carry = t >> 32;
+        }
+        limbs.push(carry as u32);
+        BigUint::from_limbs(limbs)
+    }
+
+    /// Quotient and remainder of division by a single nonzero limb
+    fn divmod_small(&self, d: u32) -> (BigUint, u32) {
+        let mut rem = 0u64;
+        let mut limbs = vec![0; self.limbs.len()];
+        for (i, &l) in self.limbs.iter().enumerate().rev() {
+            let cur = (rem << 32) | u64::from(l);
+            limbs[i] = (cur / u64::from(d)) as u32;
+            rem = cur % u64::from(d);
+        }
+        (BigUint::from_limbs(limbs), rem as u32)
+    }
+
+    pub fn shl(&self, bits: u64) -> BigUint {
+        if self.is_zero() {
+            return BigUint::zero();
+        }
+        let (words, bits) = ((bits / 32) as usize, (bits % 32) as u32);
+        let mut limbs = vec![0; words];
+        let mut carry = 0u32;
+        for &l in &self.limbs {
+            if bits == 0 {
+                limbs.push(l);
+            } else {
+                limbs.push((l << bits) | carry);
+                carry = l >> (32 - bits);
+            }
+        }
+        limbs.push(carry);
+        BigUint::from_limbs(limbs)
+    }
+
+    pub fn shr(&self, bits: u64) -> BigUint {
+        let words = (bits / 32) as usize;
+        if words >= self.limbs.len() {
+            return BigUint::zero();
+        }
+        let bits = (bits % 32) as u32;
+        let src = &self.limbs[words..];
+        let limbs = (0..src.len())
+            .map(|i| {
+                let hi = src.get(i + 1).copied().unwrap_or(0);
+                if bits == 0 {
+                    src[i]
+                } else {
+                    (src[i] >> bits) | (hi << (32 - bits))
+                }
+            })
+            .collect();
+        BigUint::from_limbs(limbs)
+    }
+
+    /// Quotient and remainder; `divisor` must be nonzero
+    pub fn divmod(&self, divisor: &BigUint) -> (BigUint, BigUint) {
+        assert!(!divisor.is_zero(), "BigUint division by zero");
+        if self < divisor {
+            return (BigUint::zero(), self.clone());
+        }
+        if let [d] = divisor.limbs[..] {
+            let (q, r) = self.divmod_small(d);
+            return (q, BigUint::from(u64::from(r)));
+        }
+        // Long division one limb at a time (Knuth's algorithm D). Shifting the
+        // divisor's top bit up makes each estimated quotient limb at most two
+        // too large.
+        const BASE: u64 = 1 << 32;
+        let shift = divisor.limbs[divisor.limbs.len() - 1].leading_zeros();
+        let v = divisor.shl(u64::from(shift)).limbs;
+        let mut u = self.shl(u64::from(shift)).limbs;
+        u.resize(self.limbs.len() + 1, 0);
+        let n = v.len();
+        let mut quotient = vec![0u32; u.len() - n];
+        for j in (0..quotient.len()).rev() {
+            let top = (u64::from(u[j + n]) << 32) | u64::from(u[j + n - 1]);
+            let mut qhat = top / u64::from(v[n - 1]);
+            let mut rhat = top % u64::from(v[n - 1]);
+            while qhat >= BASE
+                || qhat * u64::from(v[n - 2]) > (rhat << 32) | u64::from(u[j + n - 2])
+            {
+                qhat -= 1;
+                rhat += u64::from(v[n - 1]);
+                if rhat >= BASE {
+                    break;
+                }
+            }
+            // Subtract `qhat * v` from the window of `u` starting at limb `j`
+            let (mut carry, mut borrow) = (0u64, 0i64);
+            for i in 0..n {
+                let p = qhat * u64::from(v[i]) + carry;
+                carry = p >> 32;
+                let t = i64::from(u[i + j]) - borrow - (p & 0xffff_ffff) as i64;
+                u[i + j] = t as u32;
+                borrow = i64::from(t < 0);
+            }
+            let t = i64::from(u[j + n]) - borrow - carry as i64;
+            u[j + n] = t as u32;
+            if t < 0 {
+                // The estimate was one too large: add `v` back
+                qhat -= 1;
+                let mut carry = 0u64;
+                for i in 0..n {
+                    let sum = u64::from(u[i + j]) + u64::from(v[i]) + carry;
+                    u[i + j] = sum as u32;
+                    carry = sum >> 32;
+                }
+                u[j + n] = u[j + n].wrapping_add(carry as u32);
+            }
+            quotient[j] = qhat as u32;
+        }
+        u.truncate(n);
+        (
+            BigUint::from_limbs(quotient),
+            BigUint::from_limbs(u).shr(u64::from(shift)),
+        )
+    }
+
+    /// Greatest common divisor (Lehmer's form of Euclid's algorithm)
+    pub fn gcd(&self, other: &BigUint) -> BigUint {
+        let (mut a, mut b) = if self >= other {
+            (self.clone(), other.clone())
+        } else {
+            (other.clone(), self.clone())
+        };
+        while !b.is_zero() {
+            // Run Euclid on the leading 63 bits for as long as the quotients
+            // are certain to match the full values' quotients
+            let shift = a.bits().saturating_sub(63);
+            let mut x = i128::from(a.shr(shift).to_u64().unwrap_or_default());
+            let mut y = i128::from(b.shr(shift).to_u64().unwrap_or_default());
+            let (mut ca, mut cb, mut cc, mut cd) = (1i128, 0i128, 0i128, 1i128);
+            while y + cc > 0 && y + cd > 0 {
+                let q = (x + ca) / (y + cc);
+                if q != (x + cb) / (y + cd) {
+                    break;
+                }
+                (ca, cc) = (cc, ca - q * cc);
+                (cb, cd) = (cd, cb - q * cd);
+                (x, y) = (y, x - q * y);
+            }
+            if cb == 0 {
+                // No certain step: take one full division instead
+                let (_, r) = a.divmod(&b);
+                a = std::mem::replace(&mut b, r);
+            } else {
+                let term = |n: &BigUint, k: i128| {
+                    BigInt::from_parts(k < 0, n * &BigUint::from(k.unsigned_abs()))
+                };
+                let next_a = &term(&a, ca) + &term(&b, cb);
+                let next_b = &term(&a, cc) + &term(&b, cd);
+                (a, b) = (next_a.magnitude, next_b.magnitude);
+            }
+        }
+        a
+    }
+
+    pub fn pow(&self, mut exp: u64) -> BigUint {
+        let mut base = self.clone();
+        let mut result = BigUint::one();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = &result * &base;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    /// `n!`
+    pub fn factorial(n: u32) -> BigUint {
+        (2..=n).fold(BigUint::one(), |acc, k| acc.mul_small(k))
+    }
+
+    /// Largest integer whose square is at most `self` (Newton's method)
+    pub fn isqrt(&self) -> BigUint {
+        if self.is_zero() {
+            return BigUint::zero();
+        }
+        let mut x = BigUint::one().shl(self.bits().div_ceil(2));
+        loop {
+            let (q, _) = self.divmod(&x);
+            let next = (&x + &q).shr(1);
+            if next >= x {
+                return x;
+            }
+            x = next;
+        }
+    }
+}
+
+impl From<u64> for BigUint {
+    fn from(n: u64) -> BigUint {
+        BigUint::from_limbs(vec![n as u32, (n >> 32) as u32])
+    }
+}
+
+impl From<u128> for BigUint {
+    fn from(n: u128) -> BigUint {
+        BigUint::from_limbs((0..4).map(|i| (n >> (32 * i)) as u32).collect())
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &BigUint {
+    type Output = BigUint;
+
+    fn add(self, other: &BigUint) -> BigUint {
+        let len = self.limbs.len().max(other.limbs.len());
+        let mut limbs = Vec::with_capacity(len + 1);
+        let mut carry = 0u64;
+        for i in 0..len {
+            let a = u64::from(self.limbs.get(i).copied().unwrap_or(0));
+            let b = u64::from(other.limbs.get(i).copied().unwrap_or(0));
+            let t = a + b + carry;
+            limbs.push(t as u32);
+            carry = t >> 32;
+        }
+        limbs.push(carry as u32);
+        BigUint::from_limbs(limbs)
+    }
+}
+
+/// Subtraction of magnitudes; panics if `other > self`
+impl Sub for &BigUint {
+    type Output = BigUint;
+
+    fn sub(self, other: &BigUint) -> BigUint {
+        assert!(self >= other, "BigUint subtraction underflow");
+        let mut limbs = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0i64;
+        for (i, &l) in self.limbs.iter().enumerate() {
+            let b = i64::from(other.limbs.get(i).copied().unwrap_or(0));
+            let mut t = i64::from(l) - b - borrow;
+            borrow = i64::from(t < 0);
+            if t < 0 {
+                t += 1 << 32;
+            }
+            limbs.push(t as u32);
+        }
+        BigUint::from_limbs(limbs)
+    }
+}
+
+impl Mul for &BigUint {
+    type Output = BigUint;
+
+    fn mul(self, other: &BigUint) -> BigUint {
+        if self.is_zero() || other.is_zero() {
+            return BigUint::zero();
+        }
+        let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let t = u64::from(a) * u64::from(b) + u64::from(limbs[i + j]) + carry;
+                limbs[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+        BigUint::from_limbs(limbs)
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return f.pad_integral(true, "", "0");
+        }
+        // Peel off nine decimal digits at a time
+        let mut chunks = Vec::new();
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (q, r) = n.divmod_small(1_000_000_000);
+            chunks.push(r);
+            n = q;
+        }
+        let mut s = chunks.pop().unwrap_or_default().to_string();
+        for chunk in chunks.iter().rev() {
+            s.push_str(&format!("{:09}", chunk));
+        }
+        f.pad_integral(true, "", &s)
+    }
+}
+
+/// Signed arbitrary-size integer
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: BigUint,
+}
+
+impl BigInt {
+    /// Integer with the given sign and magnitude; zero is never negative
+    pub fn from_parts(negative: bool, magnitude: BigUint) -> BigInt {
+        BigInt {
+            negative: negative && !magnitude.is_zero(),
+            magnitude,
+        }
+    }
+
+    pub fn zero() -> BigInt {
+        BigInt::default()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn magnitude(&self) -> &BigUint {
+        &self.magnitude
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::from_parts(false, self.magnitude.clone())
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let m = self.magnitude.to_f64();
+        if self.negative { -m } else { m }
+    }
+
+    /// The value if it fits in an `i64`
+    pub fn to_i64(&self) -> Option<i64> {
+        let m = i64::try_from(self.magnitude.to_u64()?).ok()?;
+        Some(if self.negative { -m } else { m })
+    }
+
+    /// Quotient rounded toward zero and remainder with the sign of `self`;
+    /// `divisor` must be nonzero
+    pub fn div_rem(&self, divisor: &BigInt) -> (BigInt, BigInt) {
+        let (q, r) = self.magnitude.divmod(&divisor.magnitude);
+        (
+            BigInt::from_parts(self.negative != divisor.negative, q),
+            BigInt::from_parts(self.negative, r),
+        )
+    }
+
+    /// Euclidean quotient and remainder: the remainder is in `0..|divisor|`;
+    /// `divisor` must be nonzero
+    pub fn div_rem_euclid(&self, divisor: &BigInt) -> (BigInt, BigInt) {
+        let (q, r) = self.div_rem(divisor);
+        if !r.is_negative() {
+            return (q, r);
+        }
+        let one = BigInt::from(1);
+        if divisor.is_negative() {
+            (&q + &one, &r - divisor)
+        } else {
+            (&q - &one, &r + divisor)
+        }
+    }
+
+    pub fn pow(&self, exp: u64) -> BigInt {
+        BigInt::from_parts(self.negative && exp % 2 == 1, self.magnitude.pow(exp))
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> BigInt {
+        BigInt::from_parts(n < 0, BigUint::from(n.unsigned_abs()))
+    }
+}
+
+impl From<BigUint> for BigInt {
+    fn from(magnitude: BigUint) -> BigInt {
+        BigInt::from_parts(false, magnitude)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => self.magnitude.cmp(&other.magnitude),
+            (true, true) => other.magnitude.cmp(&self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.magnitude.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, &self.magnitude + &other.magnitude);
+        }
+        // Opposite signs: subtract the smaller magnitude from the larger
+        match self.magnitude.cmp(&other.magnitude) {
+            Ordering::Less => {
+                BigInt::from_parts(other.negative, &other.magnitude - &self.magnitude)
+            }
+            _ => BigInt::from_parts(self.negative, &self.magnitude - &other.magnitude),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(
+            self.negative != other.negative,
+            &self.magnitude * &other.magnitude,
+        )
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad_integral(!self.negative, "", &self.magnitude.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        match s.strip_prefix('-') {
+            Some(digits) => -&BigInt::from(BigUint::parse_decimal(digits).unwrap()),
+            None => BigInt::from(BigUint::parse_decimal(s).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let s = "123456789012345678901234567890";
+        assert_eq!(big(s).to_string(), s);
+        assert_eq!(big("-42").to_string(), "-42");
+        assert_eq!(big("000").to_string(), "0");
+        assert!(BigUint::parse_decimal("12a").is_none());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = big("99999999999999999999");
+        let b = big("-12345678901234567890");
+        assert_eq!((&a + &b).to_string(), "87654321098765432109");
+        assert_eq!((&b - &a).to_string(), "-112345678901234567889");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-1234567890123456788987654321098765432110"
+        );
+        assert_eq!(
+            BigInt::from(2).pow(100).to_string(),
+            "1267650600228229401496703205376"
+        );
+    }
+
+    #[test]
+    fn test_division() {
+        let a = big("1267650600228229401496703205377");
+        let (q, r) = a.div_rem(&big("-98765432109876543210"));
+        assert_eq!(q.to_string(), "-12834962325");
+        assert_eq!(
+            (&(&q * &big("-98765432109876543210")) + &r).to_string(),
+            a.to_string()
+        );
+        assert!(!r.is_negative());
+        let (q, r) = big("-7").div_rem(&big("2"));
+        assert_eq!((q.to_string(), r.to_string()), ("-3".into(), "-1".into()));
+
+        // Operands where the first quotient estimate is one too large
+        let a = BigUint::from_limbs(vec![0, 0, 0x8000_0000, 0x7fff_ffff]);
+        let b = BigUint::from_limbs(vec![1, 0, 0x8000_0000]);
+        let (q, r) = a.divmod(&b);
+        assert_eq!(&(&q * &b) + &r, a);
+        assert!(r < b);
+        // Pseudo-random operands of many sizes
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = |limbs: usize| {
+            let limbs = (0..limbs).map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u32 >> (seed % 32)
+            });
+            BigUint::from_limbs(limbs.collect())
+        };
+        for (m, n) in [(3, 2), (8, 3), (20, 7), (40, 39), (64, 64)] {
+            for _ in 0..50 {
+                let (a, b) = (next(m), next(n));
+                if b.is_zero() {
+                    continue;
+                }
+                let (q, r) = a.divmod(&b);
+                assert_eq!(&(&q * &b) + &r, a, "{} / {}", a, b);
+                assert!(r < b, "{} % {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_euclidean_division() {
+        let cases = [
+            (-7, 2, -4, 1),
+            (7, -2, -3, 1),
+            (-7, -2, 4, 1),
+            (6, -3, -2, 0),
+        ];
+        for (a, b, q, r) in cases {
+            let (quot, rem) = BigInt::from(a).div_rem_euclid(&BigInt::from(b));
+            assert_eq!(
+                (quot, rem),
+                (BigInt::from(q), BigInt::from(r)),
+                "{} / {}",
+                a,
+                b
+            );
+        }
+        assert_eq!(
+            BigUint::factorial(25).to_string(),
+            "15511210043330985984000000"
+        );
+        assert_eq!(BigUint::factorial(0), BigUint::one());
+    }
+
+    #[test]
+    fn test_gcd_and_isqrt() {
+        let a = BigUint::parse_decimal("1071").unwrap();
+        let b = BigUint::parse_decimal("462").unwrap();
+        assert_eq!(a.gcd(&b).to_string(), "21");
+        let (a, b) = (BigUint::from(6u64).pow(300), BigUint::from(10u64).pow(200));
+        assert_eq!(a.gcd(&b), BigUint::from(2u64).pow(200));
+        assert_eq!(b.gcd(&a), BigUint::from(2u64).pow(200));
+        // Agrees with plain Euclid on values with a large common factor
+        let common = BigUint::from(3u64).pow(500);
+        for k in 1..40u64 {
+            let a = &common * &BigUint::from(k * 7919).pow(k);
+            let b = &common * &(&BigUint::from(2u64).pow(k * 23) + &BigUint::one());
+            let (mut x, mut y) = (a.clone(), b.clone());
+            while !y.is_zero() {
+                let (_, r) = x.divmod(&y);
+                x = std::mem::replace(&mut y, r);
+            }
+            assert_eq!(a.gcd(&b), x, "gcd({}, {})", a, b);
+        }
+        let square = BigUint::from(12345678901u64).pow(2);
+        assert_eq!(square.isqrt().to_string(), "12345678901");
+        assert_eq!(
+            (&square - &BigUint::one()).isqrt().to_string(),
+            "12345678900"
+        );
+    }
+
+    #[test]
+    fn test_to_f64() {
+        assert_eq!(big("-1024").to_f64(), -1024.0);
+        assert_eq!(BigInt::from(2).pow(200).to_f64(), 2f64.powi(200));
+        // Correctly rounded: the bits past the top 64 decide near-ties
+        for s in [
+            "-28194492663993712248012",
+            "9007199254740993000000000000000001",
+            "18446744073709552639",
+            "18446744073709552640",
+            "179769313486231580793728971405303415079934132710037826936173778980444968292764750946649017977587207096330286416692887910946555547851940402630657488671505820681908902000708383676273854845817711531764475730270069855571366959622842914819860834936475292719074168444365510704342711559699508093042880177904174497791",
+        ] {
+            assert_eq!(big(s).to_f64(), s.parse::<f64>().unwrap(), "{}", s);
+        }
+    }
+}
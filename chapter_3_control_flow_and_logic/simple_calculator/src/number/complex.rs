@@ -0,0 +1,313 @@
+//! Complex numbers over `f64`
+//!
+//! Multi-valued functions return their principal value: `sqrt` and `ln` cut
+//! along the negative real axis, and `arg` lies in `(-pi, pi]`.
+
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub const I: Complex = Complex { re: 0.0, im: 1.0 };
+
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn real(re: f64) -> Complex {
+        Complex { re, im: 0.0 }
+    }
+
+    /// Value with modulus `r` at angle `theta` radians
+    pub fn from_polar(r: f64, theta: f64) -> Complex {
+        Complex::new(r * theta.cos(), r * theta.sin())
+    }
+
+    pub fn is_real(self) -> bool {
+        self.im == 0.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.re == 0.0 && self.im == 0.0
+    }
+
+    /// Modulus `|z|`
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// Angle to the positive real axis, in `(-pi, pi]`
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn scale(self, k: f64) -> Complex {
+        Complex::new(self.re * k, self.im * k)
+    }
+
+    pub fn exp(self) -> Complex {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    pub fn ln(self) -> Complex {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+
+    pub fn sqrt(self) -> Complex {
+        if self.is_real() && self.re >= 0.0 {
+            return Complex::real(self.re.sqrt());
+        }
+        let r = self.abs();
+        let re = ((r + self.re) / 2.0).sqrt();
+        let im = ((r - self.re) / 2.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    /// `self ^ n` by repeated squaring, which keeps `i ^ 2` exactly `-1`
+    pub fn powi(self, n: i32) -> Complex {
+        let mut base = self;
+        let mut result = Complex::real(1.0);
+        let mut e = n.unsigned_abs();
+        while e > 0 {
+            if e & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            e >>= 1;
+        }
+        if n < 0 {
+            Complex::real(1.0) / result
+        } else {
+            result
+        }
+    }
+
+    /// Principal value of `self ^ exp`
+    pub fn pow(self, exp: Complex) -> Complex {
+        if exp.is_real() {
+            if exp.re.fract() == 0.0 && exp.re.abs() <= 1024.0 {
+                return self.powi(exp.re as i32);
+            }
+            if self.is_real() && self.re >= 0.0 {
+                return Complex::real(self.re.powf(exp.re));
+            }
+        }
+        if self.is_zero() {
+            return Complex::real(0f64.powf(exp.re));
+        }
+        (exp * self.ln()).exp()
+    }
+
+    pub fn sin(self) -> Complex {
+        Complex::new(
+            self.re.sin() * self.im.cosh(),
+            self.re.cos() * self.im.sinh(),
+        )
+    }
+
+    pub fn cos(self) -> Complex {
+        Complex::new(
+            self.re.cos() * self.im.cosh(),
+            -self.re.sin() * self.im.sinh(),
+        )
+    }
+
+    pub fn tan(self) -> Complex {
+        self.sin() / self.cos()
+    }
+
+    /// `-i ln(iz + sqrt(1 - z^2))`
+    pub fn asin(self) -> Complex {
+        if self.is_real() && self.re.abs() <= 1.0 {
+            return Complex::real(self.re.asin());
+        }
+        let root = (Complex::real(1.0) - self * self).sqrt();
+        let w = (Complex::I * self + root).ln();
+        Complex::new(w.im, -w.re)
+    }
+
+    /// `pi/2 - asin(z)`
+    pub fn acos(self) -> Complex {
+        if self.is_real() && self.re.abs() <= 1.0 {
+            return Complex::real(self.re.acos());
+        }
+        Complex::real(std::f64::consts::FRAC_PI_2) - self.asin()
+    }
+
+    /// `(i/2) ln((i + z) / (i - z))`
+    pub fn atan(self) -> Complex {
+        if self.is_real() {
+            return Complex::real(self.re.atan());
+        }
+        let w = ((Complex::I + self) / (Complex::I - self)).ln();
+        Complex::new(-w.im / 2.0, w.re / 2.0)
+    }
+
+    /// The same value with parts that are rounding noise next to the other
+    /// part, like the `1.2e-16i` in `exp(i * pi)`, set to zero
+    fn cleaned(self) -> Complex {
+        let noise = self.abs() * 1e-15;
+        let clean = |x: f64| if x.abs() < noise { 0.0 } else { x };
+        Complex::new(clean(self.re), clean(self.im))
+    }
+
+    /// Polar form `r∠θ`, with `θ` in radians
+    pub fn to_polar_string(self) -> String {
+        self.to_polar_string_with(|x| x.to_string())
+    }
+
+    /// Polar form with `r` and `θ` written by `part`
+    pub fn to_polar_string_with(self, part: impl Fn(f64) -> String) -> String {
+        let z = self.cleaned();
+        format!("{}∠{}", part(z.abs()), part(z.arg()))
+    }
+
+    /// Rectangular form as `Display` writes it, with each part written by
+    /// `part`
+    pub fn to_string_with(self, part: impl Fn(f64) -> String) -> String {
+        let z = self.cleaned();
+        let imag = match z.im {
+            1.0 => "i".to_string(),
+            -1.0 => "-i".to_string(),
+            im => format!("{}i", part(im)),
+        };
+        if z.im == 0.0 {
+            part(z.re)
+        } else if z.re == 0.0 {
+            imag
+        } else if z.im < 0.0 {
+            format!("{}{}", part(z.re), imag)
+        } else {
+            // Positive, or NaN
+            format!("{}+{}", part(z.re), imag)
+        }
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let d = other.re * other.re + other.im * other.im;
+        let n = self * other.conj();
+        Complex::new(n.re / d, n.im / d)
+    }
+}
+
+/// Rectangular form `a+bi`, leaving out a zero part and a unit coefficient
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_with(|x| x.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    fn close(a: Complex, b: Complex) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn test_arithmetic_and_display() {
+        let z = Complex::new(1.0, 2.0) * Complex::new(3.0, -1.0);
+        assert_eq!(z, Complex::new(5.0, 5.0));
+        assert_eq!(z.to_string(), "5+5i");
+        assert_eq!(Complex::new(3.0, -4.0).to_string(), "3-4i");
+        assert_eq!(Complex::new(0.0, -1.0).to_string(), "-i");
+        assert_eq!(Complex::real(-2.5).to_string(), "-2.5");
+        assert_eq!(Complex::new(1.0, f64::NAN).to_string(), "1+NaNi");
+        assert!(close(
+            Complex::new(5.0, 5.0) / Complex::new(3.0, -1.0),
+            Complex::new(1.0, 2.0)
+        ));
+        assert_eq!(Complex::I.powi(2), Complex::real(-1.0));
+        assert_eq!(Complex::I.powi(-1), Complex::new(0.0, -1.0));
+    }
+
+    #[test]
+    fn test_principal_values() {
+        assert_eq!(Complex::real(-4.0).sqrt(), Complex::new(0.0, 2.0));
+        assert_eq!(
+            Complex::new(0.0, -4.0).sqrt().to_string(),
+            "1.4142135623730951-1.4142135623730951i"
+        );
+        assert!(close(Complex::real(-1.0).ln(), Complex::new(0.0, PI)));
+        assert!(close(
+            Complex::I.pow(Complex::I),
+            Complex::real((-FRAC_PI_2).exp())
+        ));
+        assert_eq!(Complex::new(0.0, PI).exp().to_string(), "-1");
+        assert!(close(
+            Complex::real(-8.0).pow(Complex::real(1.0 / 3.0)),
+            Complex::new(1.0, 3f64.sqrt())
+        ));
+    }
+
+    #[test]
+    fn test_trig_round_trips() {
+        let z = Complex::new(0.5, -1.25);
+        assert!(close(z.sin().asin(), z));
+        assert!(close(z.cos().acos(), z));
+        assert!(close(z.tan().atan(), z));
+        assert!(close(Complex::real(2.0).asin().sin(), Complex::real(2.0)));
+        assert_eq!(Complex::real(0.5).asin(), Complex::real(0.5f64.asin()));
+    }
+
+    #[test]
+    fn test_polar() {
+        let z = Complex::from_polar(2.0, FRAC_PI_2);
+        assert_eq!(z.to_string(), "2i");
+        assert_eq!(
+            Complex::new(3.0, 4.0).to_polar_string(),
+            "5∠0.9272952180016122"
+        );
+        assert_eq!(Complex::real(-1.0).to_polar_string(), format!("1∠{}", PI));
+    }
+}
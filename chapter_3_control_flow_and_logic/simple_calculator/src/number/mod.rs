@@ -0,0 +1,599 @@
+//! Number backends the evaluator can compute with
+//!
+//! `f64` is the default. `Rational` and `BigInt` compute exactly and report an
+//! `Inexact` error for operations whose result they cannot represent, such as
+//! `sqrt(2)`, or `7 / 2` for integers. `Word` is a fixed-width integer for
+//! programmer mode, the only one with bitwise operators.
+
+pub mod bigint;
+pub mod complex;
+pub mod rational;
+pub mod word;
+
+pub use bigint::{BigInt, BigUint};
+pub use complex::Complex;
+pub use rational::Rational;
+pub use word::{Format, IntType, Overflow, Word};
+
+use crate::ast::Literal;
+use crate::functions::{self, ANGLE_ARGUMENT, ANGLE_RESULT, Builtin};
+use crate::{CalcError, Op, Span, eval};
+use std::fmt;
+
+/// Arithmetic the evaluator needs from a number type
+///
+/// Errors carry an empty span; the evaluator points them at the expression
+/// that produced them.
+pub trait Number: Clone + fmt::Debug + fmt::Display + PartialEq {
+    /// Name of the mode in messages, e.g. `rational`
+    const MODE: &'static str;
+
+    /// Settings an `Environment` keeps for values it creates from literals,
+    /// like the integer type of programmer mode; `()` for most types
+    type Context: Clone + Default + fmt::Debug;
+
+    /// Value of a number literal from the input
+    fn from_literal(literal: &Literal, context: &Self::Context) -> Result<Self, CalcError>;
+
+    /// Value of `-literal`, for a minus sign written straight before a
+    /// literal; differs from negating `from_literal` only for types where
+    /// the negated value fits and the literal does not, like `-128` in `i8`
+    fn from_negated_literal(literal: &Literal, context: &Self::Context) -> Result<Self, CalcError> {
+        Self::from_literal(literal, context)?.negate()
+    }
+
+    /// Context for a literal that counts, the exponent of `^` or the amount
+    /// of `<<` and `>>`, rather than being a value of the mode's type; the
+    /// same context for every type but `Word`
+    fn count_context(context: &Self::Context) -> Self::Context {
+        context.clone()
+    }
+
+    /// Value of the built-in constant `name`, whose `f64` value is `approx`
+    fn constant(name: &str, approx: f64) -> Result<Self, CalcError>;
+
+    /// The imaginary number `im * i`; only complex mode has one
+    fn imaginary(_im: f64) -> Result<Self, CalcError> {
+        Err(CalcError::Imaginary(Span::default()))
+    }
+
+    /// Nearest value of this type to `value`, if there is one; used when
+    /// switching modes
+    fn from_f64(value: f64) -> Option<Self>;
+
+    fn to_f64(&self) -> f64;
+
+    /// The exact value as a fraction, for the exact types; `None` for the
+    /// floating point ones
+    fn to_rational(&self) -> Option<Rational> {
+        None
+    }
+
+    /// Value of this type equal to `value`, if there is one; types that are
+    /// not exact take the nearest `f64`
+    fn from_rational(value: &Rational) -> Option<Self> {
+        Self::from_f64(value.to_f64())
+    }
+
+    fn negate(&self) -> Result<Self, CalcError>;
+
+    /// `~self`; only programmer mode has bitwise operators
+    fn bit_not(&self) -> Result<Self, CalcError> {
+        Err(unsupported::<Self>(format!("~{}", self)))
+    }
+
+    fn apply(op: Op, a: &Self, b: &Self) -> Result<Self, CalcError>;
+
+    /// `apply` letting IEEE 754 special values through, so that `1 / 0` is
+    /// infinite; the same as `apply` for types without them
+    fn apply_ieee(op: Op, a: &Self, b: &Self) -> Result<Self, CalcError> {
+        Self::apply(op, a, b)
+    }
+
+    /// False for infinities and NaN, which only floating point types have
+    fn is_finite(&self) -> bool {
+        true
+    }
+
+    fn is_nan(&self) -> bool {
+        false
+    }
+
+    fn factorial(&self) -> Result<Self, CalcError>;
+
+    /// Call built-in `f`; the argument count has already been checked
+    fn call(f: &Builtin, args: &[Self]) -> Result<Self, CalcError>;
+
+    /// `call` with angles in degrees: the trigonometric functions take them
+    /// and their inverses return them. The default is `call`, for types
+    /// whose trigonometric functions have no exact values anyway
+    fn call_degrees(f: &Builtin, args: &[Self]) -> Result<Self, CalcError> {
+        Self::call(f, args)
+    }
+}
+
+/// `value` in number type `M`, used when switching modes: exact between the
+/// exact types and through `f64` otherwise; `None` if `M` cannot hold it
+pub fn convert<N: Number, M: Number>(value: &N) -> Option<M> {
+    match value.to_rational() {
+        Some(exact) => M::from_rational(&exact),
+        None => M::from_f64(value.to_f64()),
+    }
+}
+
+impl Number for f64 {
+    const MODE: &'static str = "float";
+
+    type Context = ();
+
+    fn from_literal(literal: &Literal, _context: &()) -> Result<f64, CalcError> {
+        Ok(literal.value)
+    }
+
+    fn constant(_name: &str, approx: f64) -> Result<f64, CalcError> {
+        Ok(approx)
+    }
+
+    fn from_f64(value: f64) -> Option<f64> {
+        Some(value)
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+
+    fn negate(&self) -> Result<f64, CalcError> {
+        Ok(-self)
+    }
+
+    fn apply(op: Op, a: &f64, b: &f64) -> Result<f64, CalcError> {
+        eval::apply(op, *a, *b)
+    }
+
+    fn apply_ieee(op: Op, a: &f64, b: &f64) -> Result<f64, CalcError> {
+        eval::apply_ieee(op, *a, *b)
+    }
+
+    fn is_finite(&self) -> bool {
+        f64::is_finite(*self)
+    }
+
+    fn is_nan(&self) -> bool {
+        f64::is_nan(*self)
+    }
+
+    /// NaN for negative or fractional operands, like other undefined results
+    fn factorial(&self) -> Result<f64, CalcError> {
+        let n = *self;
+        if n < 0.0 || n.fract() != 0.0 {
+            return Ok(f64::NAN);
+        }
+        // 171! already overflows
+        Ok((2..=n.min(171.0) as u32).map(f64::from).product())
+    }
+
+    fn call(f: &Builtin, args: &[f64]) -> Result<f64, CalcError> {
+        Ok((f.eval)(args))
+    }
+
+    fn call_degrees(f: &Builtin, args: &[f64]) -> Result<f64, CalcError> {
+        match functions::in_degrees(f.name) {
+            Some(g) => Ok(g(args[0])),
+            None => f64::call(f, args),
+        }
+    }
+}
+
+/// Error for an operation `what` that `N` does not have, such as the bitwise
+/// operators outside programmer mode
+fn unsupported<N: Number>(what: String) -> CalcError {
+    CalcError::Unsupported(what, N::MODE, Span::default())
+}
+
+/// `Unsupported` error for `a op b`, for the bitwise operators
+fn unsupported_op<N: Number>(op: Op, a: &N, b: &N) -> CalcError {
+    unsupported::<N>(format!("{} {} {}", a, op, b))
+}
+
+/// Exact value of a `0x`, `0o` or `0b` literal
+fn radix_integer(literal: &Literal) -> Option<BigInt> {
+    let value = literal.radix_value()?;
+    let high = BigUint::from((value >> 64) as u64).shl(64);
+    Some(BigInt::from(&high + &BigUint::from(value as u64)))
+}
+
+/// Error for a result `what` that `N` cannot represent exactly
+fn inexact<N: Number>(what: String) -> CalcError {
+    CalcError::Inexact(what, N::MODE, Span::default())
+}
+
+/// Largest number of bits an exact power may produce before it is refused
+const MAX_EXACT_BITS: u64 = 1 << 16;
+
+/// Largest operand an exact factorial accepts
+const MAX_FACTORIAL: u32 = 20_000;
+
+/// Check that raising a base of `base_bits` bits to `exp` stays exact-sized
+fn check_power_size(base_bits: u64, exp: i64) -> Result<(), CalcError> {
+    if base_bits.saturating_mul(exp.unsigned_abs()) > MAX_EXACT_BITS {
+        Err(CalcError::TooLarge(Span::default()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Exact `n!`, or an `Inexact` error naming `shown` when `n` is negative
+fn exact_factorial<N: Number>(n: &BigInt, shown: &N) -> Result<BigInt, CalcError> {
+    if n.is_negative() {
+        return Err(inexact::<N>(format!("({})!", shown)));
+    }
+    let n = n
+        .to_i64()
+        .and_then(|n| u32::try_from(n).ok())
+        .filter(|&n| n <= MAX_FACTORIAL)
+        .ok_or(CalcError::TooLarge(Span::default()))?;
+    Ok(BigInt::from(BigUint::factorial(n)))
+}
+
+/// Function `name` called with `args`, as text for an error message
+pub(crate) fn call_text<N: Number>(name: &str, args: &[N]) -> String {
+    let shown: Vec<String> = args.iter().map(N::to_string).collect();
+    format!("{}({})", name, shown.join(", "))
+}
+
+/// `Inexact` error for built-in `f` called with `args`
+fn inexact_call<N: Number>(f: &Builtin, args: &[N]) -> CalcError {
+    inexact::<N>(call_text(f.name, args))
+}
+
+impl Number for Rational {
+    const MODE: &'static str = "rational";
+
+    type Context = ();
+
+    fn from_literal(literal: &Literal, _context: &()) -> Result<Rational, CalcError> {
+        if let Some(n) = radix_integer(literal) {
+            return Ok(Rational::from_integer(n));
+        }
+        Rational::parse_decimal(&literal.text).ok_or(CalcError::TooLarge(Span::default()))
+    }
+
+    fn constant(name: &str, _approx: f64) -> Result<Rational, CalcError> {
+        Err(inexact::<Rational>(name.to_string()))
+    }
+
+    fn from_f64(value: f64) -> Option<Rational> {
+        Rational::from_f64(value)
+    }
+
+    fn to_f64(&self) -> f64 {
+        Rational::to_f64(self)
+    }
+
+    fn to_rational(&self) -> Option<Rational> {
+        Some(self.clone())
+    }
+
+    fn from_rational(value: &Rational) -> Option<Rational> {
+        Some(value.clone())
+    }
+
+    fn negate(&self) -> Result<Rational, CalcError> {
+        Ok(-self)
+    }
+
+    fn apply(op: Op, a: &Rational, b: &Rational) -> Result<Rational, CalcError> {
+        let by_zero = || CalcError::DivisionByZero(Span::default());
+        match op {
+            Op::Add => Ok(a + b),
+            Op::Sub => Ok(a - b),
+            Op::Mul => Ok(a * b),
+            Op::Div => Ok(a * &b.recip().ok_or_else(by_zero)?),
+            Op::IntDiv => Ok(Rational::from_integer(a.div_euclid(b).ok_or_else(by_zero)?)),
+            Op::Rem => {
+                let q = Rational::from_integer(a.div_euclid(b).ok_or_else(by_zero)?);
+                Ok(a - &(b * &q))
+            }
+            Op::Pow => {
+                let exp = b
+                    .is_integer()
+                    .then(|| b.numer().to_i64())
+                    .flatten()
+                    .ok_or_else(|| inexact::<Rational>(format!("{} ^ ({})", a, b)))?;
+                check_power_size(a.numer().magnitude().bits().max(a.denom().bits()), exp)?;
+                a.powi(exp).ok_or_else(by_zero)
+            }
+            _ => Err(unsupported_op(op, a, b)),
+        }
+    }
+
+    fn factorial(&self) -> Result<Rational, CalcError> {
+        if !self.is_integer() {
+            return Err(inexact::<Rational>(format!("({})!", self)));
+        }
+        exact_factorial(self.numer(), self).map(Rational::from_integer)
+    }
+
+    fn call(f: &Builtin, args: &[Rational]) -> Result<Rational, CalcError> {
+        let x = &args[0];
+        match f.name {
+            "abs" => Ok(x.abs()),
+            "floor" => Ok(Rational::from_integer(x.floor())),
+            "ceil" => Ok(Rational::from_integer(x.ceil())),
+            "round" => Ok(Rational::from_integer(x.round())),
+            "min" => Ok(args.iter().min().unwrap_or(x).clone()),
+            "max" => Ok(args.iter().max().unwrap_or(x).clone()),
+            "re" | "conj" => Ok(x.clone()),
+            "im" => Ok(Rational::zero()),
+            "arg" if !x.numer().is_negative() => Ok(Rational::zero()),
+            "sqrt" => x.sqrt().ok_or_else(|| inexact_call(f, args)),
+            _ => Err(inexact_call(f, args)),
+        }
+    }
+}
+
+impl Number for BigInt {
+    const MODE: &'static str = "integer";
+
+    type Context = ();
+
+    /// Literals must denote whole numbers; `1e3` is fine, `1.5` is not
+    fn from_literal(literal: &Literal, _context: &()) -> Result<BigInt, CalcError> {
+        if let Some(n) = radix_integer(literal) {
+            return Ok(n);
+        }
+        let value =
+            Rational::parse_decimal(&literal.text).ok_or(CalcError::TooLarge(Span::default()))?;
+        if value.is_integer() {
+            Ok(value.numer().clone())
+        } else {
+            Err(inexact::<BigInt>(literal.text.clone()))
+        }
+    }
+
+    fn constant(name: &str, _approx: f64) -> Result<BigInt, CalcError> {
+        Err(inexact::<BigInt>(name.to_string()))
+    }
+
+    fn from_f64(value: f64) -> Option<BigInt> {
+        if value.fract() != 0.0 {
+            return None;
+        }
+        Rational::from_f64(value).map(|r| r.numer().clone())
+    }
+
+    fn to_f64(&self) -> f64 {
+        BigInt::to_f64(self)
+    }
+
+    fn to_rational(&self) -> Option<Rational> {
+        Some(Rational::from_integer(self.clone()))
+    }
+
+    fn from_rational(value: &Rational) -> Option<BigInt> {
+        value.is_integer().then(|| value.numer().clone())
+    }
+
+    fn negate(&self) -> Result<BigInt, CalcError> {
+        Ok(-self)
+    }
+
+    fn apply(op: Op, a: &BigInt, b: &BigInt) -> Result<BigInt, CalcError> {
+        if matches!(op, Op::Div | Op::IntDiv | Op::Rem) && b.is_zero() {
+            return Err(CalcError::DivisionByZero(Span::default()));
+        }
+        match op {
+            Op::Add => Ok(a + b),
+            Op::Sub => Ok(a - b),
+            Op::Mul => Ok(a * b),
+            // `/` only divides exactly; `//` and `%` are there to discard a remainder
+            Op::Div => match a.div_rem(b) {
+                (q, r) if r.is_zero() => Ok(q),
+                _ => Err(inexact::<BigInt>(format!("{} / {}", a, b))),
+            },
+            Op::IntDiv => Ok(a.div_rem_euclid(b).0),
+            Op::Rem => Ok(a.div_rem_euclid(b).1),
+            // ±1 has an exact power for any exponent, even a negative or huge one
+            Op::Pow if a.magnitude().is_one() => {
+                let negative = a.is_negative() && !b.magnitude().is_even();
+                Ok(BigInt::from(if negative { -1 } else { 1 }))
+            }
+            Op::Pow => {
+                let exp = b
+                    .to_i64()
+                    .filter(|&e| e >= 0)
+                    .ok_or_else(|| inexact::<BigInt>(format!("{} ^ ({})", a, b)))?;
+                check_power_size(a.magnitude().bits(), exp)?;
+                Ok(a.pow(exp as u64))
+            }
+            _ => Err(unsupported_op(op, a, b)),
+        }
+    }
+
+    fn factorial(&self) -> Result<BigInt, CalcError> {
+        exact_factorial(self, self)
+    }
+
+    fn call(f: &Builtin, args: &[BigInt]) -> Result<BigInt, CalcError> {
+        let x = &args[0];
+        match f.name {
+            "abs" => Ok(x.abs()),
+            "floor" | "ceil" | "round" | "re" | "conj" => Ok(x.clone()),
+            "im" => Ok(BigInt::zero()),
+            "arg" if !x.is_negative() => Ok(BigInt::zero()),
+            "min" => Ok(args.iter().min().unwrap_or(x).clone()),
+            "max" => Ok(args.iter().max().unwrap_or(x).clone()),
+            "sqrt" if !x.is_negative() => {
+                let root = x.magnitude().isqrt();
+                if &(&root * &root) == x.magnitude() {
+                    Ok(BigInt::from(root))
+                } else {
+                    Err(inexact_call(f, args))
+                }
+            }
+            _ => Err(inexact_call(f, args)),
+        }
+    }
+}
+
+/// `NotReal` error for an operation `what` given a non-real operand
+fn not_real(what: String) -> CalcError {
+    CalcError::NotReal(what, Span::default())
+}
+
+/// Real part of `z`, or a `NotReal` error naming `what()` if it has an
+/// imaginary part
+fn real_part(z: Complex, what: impl FnOnce() -> String) -> Result<f64, CalcError> {
+    if z.is_real() {
+        Ok(z.re)
+    } else {
+        Err(not_real(what()))
+    }
+}
+
+impl Number for Complex {
+    const MODE: &'static str = "complex";
+
+    type Context = ();
+
+    fn from_literal(literal: &Literal, _context: &()) -> Result<Complex, CalcError> {
+        Ok(Complex::real(literal.value))
+    }
+
+    fn constant(_name: &str, approx: f64) -> Result<Complex, CalcError> {
+        Ok(Complex::real(approx))
+    }
+
+    fn imaginary(im: f64) -> Result<Complex, CalcError> {
+        Ok(Complex::new(0.0, im))
+    }
+
+    fn from_f64(value: f64) -> Option<Complex> {
+        Some(Complex::real(value))
+    }
+
+    /// The real part, or NaN for a value with an imaginary part
+    fn to_f64(&self) -> f64 {
+        if self.is_real() { self.re } else { f64::NAN }
+    }
+
+    /// Keeps a zero imaginary part positive, so `-4` stays on the upper side of
+    /// the branch cut and `sqrt(-4)` is `2i`
+    fn negate(&self) -> Result<Complex, CalcError> {
+        Ok(Complex::new(-self.re, 0.0 - self.im))
+    }
+
+    fn apply(op: Op, a: &Complex, b: &Complex) -> Result<Complex, CalcError> {
+        let (a, b) = (*a, *b);
+        match op {
+            Op::Add => Ok(a + b),
+            Op::Sub => Ok(a - b),
+            Op::Mul => Ok(a * b),
+            Op::Div if b.is_zero() => Err(CalcError::DivisionByZero(Span::default())),
+            Op::Div => Ok(a / b),
+            Op::IntDiv | Op::Rem => {
+                let what = || format!("{} {} {}", a, op, b);
+                let x = real_part(a, what)?;
+                let y = real_part(b, what)?;
+                eval::apply(op, x, y).map(Complex::real)
+            }
+            Op::Pow => Ok(a.pow(b)),
+            _ => Err(unsupported_op(op, &a, &b)),
+        }
+    }
+
+    /// Dividing by zero sends each non-zero part of `a` to an infinity, and
+    /// `0 / 0` is NaN; `//` and `%` work as they do for `f64`
+    fn apply_ieee(op: Op, a: &Complex, b: &Complex) -> Result<Complex, CalcError> {
+        let (a, b) = (*a, *b);
+        match op {
+            Op::Div if b.is_zero() && a.is_zero() => Ok(Complex::new(f64::NAN, f64::NAN)),
+            Op::Div if b.is_zero() => {
+                let part = |x: f64| if x == 0.0 { 0.0 } else { x / b.re };
+                Ok(Complex::new(part(a.re), part(a.im)))
+            }
+            Op::IntDiv | Op::Rem => {
+                let what = || format!("{} {} {}", a, op, b);
+                let x = real_part(a, what)?;
+                let y = real_part(b, what)?;
+                eval::apply_ieee(op, x, y).map(Complex::real)
+            }
+            _ => Complex::apply(op, &a, &b),
+        }
+    }
+
+    fn is_finite(&self) -> bool {
+        self.re.is_finite() && self.im.is_finite()
+    }
+
+    fn is_nan(&self) -> bool {
+        self.re.is_nan() || self.im.is_nan()
+    }
+
+    fn factorial(&self) -> Result<Complex, CalcError> {
+        let x = real_part(*self, || format!("({})!", self))?;
+        x.factorial().map(Complex::real)
+    }
+
+    fn call(f: &Builtin, args: &[Complex]) -> Result<Complex, CalcError> {
+        let z = args[0];
+        match f.name {
+            "sqrt" => Ok(z.sqrt()),
+            "ln" => Ok(z.ln()),
+            "log10" => Ok(z.ln().scale(std::f64::consts::LOG10_E)),
+            "log" => Ok(args[1].ln() / z.ln()),
+            "exp" => Ok(z.exp()),
+            "sin" => Ok(z.sin()),
+            "cos" => Ok(z.cos()),
+            "tan" => Ok(z.tan()),
+            "asin" => Ok(z.asin()),
+            "acos" => Ok(z.acos()),
+            "atan" => Ok(z.atan()),
+            "abs" => Ok(Complex::real(z.abs())),
+            "arg" => Ok(Complex::real(z.arg())),
+            "re" => Ok(Complex::real(z.re)),
+            "im" => Ok(Complex::real(z.im)),
+            "conj" => Ok(z.conj()),
+            "polar" => {
+                let what = || format!("polar({}, {})", z, args[1]);
+                Ok(Complex::from_polar(
+                    real_part(z, what)?,
+                    real_part(args[1], what)?,
+                ))
+            }
+            "floor" => Ok(Complex::new(z.re.floor(), z.im.floor())),
+            "ceil" => Ok(Complex::new(z.re.ceil(), z.im.ceil())),
+            "round" => Ok(Complex::new(z.re.round(), z.im.round())),
+            // Everything else (cbrt, min, max) is defined on the real line only
+            _ => {
+                let reals = args
+                    .iter()
+                    .map(|a| real_part(*a, || call_text(f.name, args)))
+                    .collect::<Result<Vec<f64>, _>>()?;
+                Ok(Complex::real((f.eval)(&reals)))
+            }
+        }
+    }
+
+    /// Real arguments take the exact values of `f64` where the result is
+    /// real too; others convert the angle, so `asin(2)` is still complex
+    fn call_degrees(f: &Builtin, args: &[Complex]) -> Result<Complex, CalcError> {
+        let z = args[0];
+        if let Some(g) = functions::in_degrees(f.name)
+            && z.is_real()
+        {
+            let x = g(z.re);
+            if ANGLE_ARGUMENT.contains(&f.name) || !x.is_nan() {
+                return Ok(Complex::real(x));
+            }
+        }
+        let per_degree = std::f64::consts::PI / 180.0;
+        if ANGLE_ARGUMENT.contains(&f.name) {
+            Complex::call(f, &[z.scale(per_degree)])
+        } else if ANGLE_RESULT.contains(&f.name) {
+            Complex::call(f, args).map(|w| w.scale(per_degree.recip()))
+        } else {
+            Complex::call(f, args)
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Exact rational numbers over `BigInt`
+//!
+//! Values are kept in lowest terms with a positive denominator, so every
+//! rational has exactly one representation.
+
+use super::bigint::{BigInt, BigUint};
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rational {
+    numer: BigInt,
+    denom: BigUint,
+}
+
+impl Rational {
+    /// `numer / denom` in lowest terms, or `None` when `denom` is zero
+    pub fn new(numer: BigInt, denom: BigInt) -> Option<Rational> {
+        if denom.is_zero() {
+            return None;
+        }
+        let negative = numer.is_negative() != denom.is_negative();
+        let g = numer.magnitude().gcd(denom.magnitude());
+        let (n, _) = numer.magnitude().divmod(&g);
+        let (d, _) = denom.magnitude().divmod(&g);
+        Some(Rational {
+            numer: BigInt::from_parts(negative, n),
+            denom: d,
+        })
+    }
+
+    pub fn from_integer(n: BigInt) -> Rational {
+        Rational {
+            numer: n,
+            denom: BigUint::one(),
+        }
+    }
+
+    pub fn zero() -> Rational {
+        Rational::from_integer(BigInt::zero())
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.numer
+    }
+
+    pub fn denom(&self) -> &BigUint {
+        &self.denom
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numer.is_zero()
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denom.is_one()
+    }
+
+    /// Exact value of a decimal literal such as `12.5`, `-6.02e23` or `1E-3`
+    pub fn parse_decimal(text: &str) -> Option<Rational> {
+        if let Some(rest) = text.strip_prefix('-') {
+            return Rational::parse_decimal(rest).map(|r| -&r);
+        }
+        let (mantissa, exp) = match text.find(['e', 'E']) {
+            Some(i) => (&text[..i], text[i + 1..].parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits = format!("{}{}", int_part, frac_part);
+        let scale = exp.checked_sub(frac_part.len() as i64)?;
+        // Refuse exponents that would need absurd amounts of memory
+        if scale.unsigned_abs() > 100_000 {
+            return None;
+        }
+        let n = BigInt::from(BigUint::parse_decimal(&digits)?);
+        let ten = BigInt::from(10);
+        if scale >= 0 {
+            Some(Rational::from_integer(&n * &ten.pow(scale as u64)))
+        } else {
+            Rational::new(n, ten.pow(scale.unsigned_abs()))
+        }
+    }
+
+    /// Exact value of the shortest decimal that prints as `value`; `None` for
+    /// NaN and infinities
+    pub fn from_f64(value: f64) -> Option<Rational> {
+        if !value.is_finite() {
+            return None;
+        }
+        Rational::parse_decimal(&format!("{:e}", value))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        if self.is_integer() {
+            return self.numer.to_f64();
+        }
+        // Scale so the integer quotient keeps about 64 significant bits
+        let shift = 64 + self.denom.bits() as i64 - self.numer.magnitude().bits() as i64;
+        let (q, _) = if shift >= 0 {
+            self.numer.magnitude().shl(shift as u64).divmod(&self.denom)
+        } else {
+            self.numer
+                .magnitude()
+                .divmod(&self.denom.shl(shift.unsigned_abs()))
+        };
+        let mut value = q.to_f64();
+        // Apply the power of two in steps so intermediate values stay finite
+        let mut remaining = -shift;
+        while remaining != 0 {
+            let step = remaining.clamp(-1000, 1000);
+            value *= 2f64.powi(step as i32);
+            remaining -= step;
+        }
+        if self.numer.is_negative() {
+            -value
+        } else {
+            value
+        }
+    }
+
+    pub fn recip(&self) -> Option<Rational> {
+        Rational::new(BigInt::from(self.denom.clone()), self.numer.clone())
+    }
+
+    /// `self` raised to an integer power; `None` for a negative power of zero
+    pub fn powi(&self, exp: i64) -> Option<Rational> {
+        let base = if exp < 0 { self.recip()? } else { self.clone() };
+        let e = exp.unsigned_abs();
+        Some(Rational {
+            numer: base.numer.pow(e),
+            denom: base.denom.pow(e),
+        })
+    }
+
+    /// Exact square root, if both numerator and denominator are perfect squares
+    pub fn sqrt(&self) -> Option<Rational> {
+        if self.numer.is_negative() {
+            return None;
+        }
+        let n = self.numer.magnitude().isqrt();
+        let d = self.denom.isqrt();
+        if &(&n * &n) == self.numer.magnitude() && (&d * &d) == self.denom {
+            Some(Rational {
+                numer: BigInt::from(n),
+                denom: d,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Euclidean quotient `q` of `self / divisor`, so that `self - divisor * q`
+    /// lies in `0..|divisor|`; `None` when `divisor` is zero
+    pub fn div_euclid(&self, divisor: &Rational) -> Option<BigInt> {
+        let ratio = self * &divisor.recip()?;
+        Some(if divisor.numer.is_negative() {
+            ratio.ceil()
+        } else {
+            ratio.floor()
+        })
+    }
+
+    /// Largest integer not greater than `self`
+    pub fn floor(&self) -> BigInt {
+        let (q, r) = self.numer.div_rem(&BigInt::from(self.denom.clone()));
+        if r.is_negative() {
+            &q - &BigInt::from(1)
+        } else {
+            q
+        }
+    }
+
+    /// Smallest integer not less than `self`
+    pub fn ceil(&self) -> BigInt {
+        -&(-self).floor()
+    }
+
+    /// Nearest integer, rounding halves away from zero like `f64::round`
+    pub fn round(&self) -> BigInt {
+        let half = Rational::new(BigInt::from(1), BigInt::from(2)).unwrap_or_else(Rational::zero);
+        if self.numer.is_negative() {
+            -&(&(-self) + &half).floor()
+        } else {
+            (self + &half).floor()
+        }
+    }
+
+    pub fn abs(&self) -> Rational {
+        Rational {
+            numer: self.numer.abs(),
+            denom: self.denom.clone(),
+        }
+    }
+
+    /// Decimal expansion with at most `digits` places after the point, rounded
+    /// half away from zero; trailing zeros are dropped
+    pub fn to_decimal(&self, digits: usize) -> String {
+        let scale = BigInt::from(10).pow(digits as u64);
+        let scaled = Rational::new(&self.numer * &scale, BigInt::from(self.denom.clone()))
+            .unwrap_or_else(Rational::zero)
+            .round();
+        let text = scaled.magnitude().to_string();
+        let sign = if scaled.is_negative() { "-" } else { "" };
+        if digits == 0 {
+            return format!("{}{}", sign, text);
+        }
+        let padded = format!("{:0>width$}", text, width = digits + 1);
+        let (int_part, frac_part) = padded.split_at(padded.len() - digits);
+        let frac_part = frac_part.trim_end_matches('0');
+        if frac_part.is_empty() {
+            format!("{}{}", sign, int_part)
+        } else {
+            format!("{}{}.{}", sign, int_part, frac_part)
+        }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        let lhs = &self.numer * &BigInt::from(other.denom.clone());
+        let rhs = &other.numer * &BigInt::from(self.denom.clone());
+        lhs.cmp(&rhs)
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::ops::Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational {
+            numer: -&self.numer,
+            denom: self.denom.clone(),
+        }
+    }
+}
+
+impl std::ops::Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, other: &Rational) -> Rational {
+        let (a, b) = (
+            BigInt::from(self.denom.clone()),
+            BigInt::from(other.denom.clone()),
+        );
+        let numer = &(&self.numer * &b) + &(&other.numer * &a);
+        Rational::new(numer, &a * &b).unwrap_or_else(Rational::zero)
+    }
+}
+
+impl std::ops::Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, other: &Rational) -> Rational {
+        self + &-other
+    }
+}
+
+impl std::ops::Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, other: &Rational) -> Rational {
+        let denom = BigInt::from(&self.denom * &other.denom);
+        Rational::new(&self.numer * &other.numer, denom).unwrap_or_else(Rational::zero)
+    }
+}
+
+/// Prints as a fraction in lowest terms, e.g. `-7/3`, or as a plain integer
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.numer)
+        } else {
+            write!(f, "{}/{}", self.numer, self.denom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn q(text: &str) -> Rational {
+        Rational::parse_decimal(text).unwrap()
+    }
+
+    #[test]
+    fn test_exact_decimal_arithmetic() {
+        assert_eq!(&q("0.1") + &q("0.2"), q("0.3"));
+        assert_eq!((&q("0.1") + &q("0.2")).to_string(), "3/10");
+        let third = Rational::new(BigInt::from(1), BigInt::from(3)).unwrap();
+        assert_eq!((&third * &q("3")).to_string(), "1");
+        assert_eq!(q("6.02e23").to_string(), "602000000000000000000000");
+        assert_eq!(q("-1.5e-2").to_string(), "-3/200");
+    }
+
+    #[test]
+    fn test_normalization() {
+        let r = Rational::new(BigInt::from(6), BigInt::from(-4)).unwrap();
+        assert_eq!(r.to_string(), "-3/2");
+        assert!(Rational::new(BigInt::from(1), BigInt::zero()).is_none());
+        assert_eq!(Rational::from_f64(0.1), Some(q("0.1")));
+    }
+
+    #[test]
+    fn test_rounding_and_powers() {
+        let r = q("-2.5");
+        assert_eq!(r.floor().to_string(), "-3");
+        assert_eq!(r.ceil().to_string(), "-2");
+        assert_eq!(r.round().to_string(), "-3");
+        assert_eq!(q("2").powi(-3).unwrap().to_string(), "1/8");
+        assert_eq!(q("2.25").sqrt().unwrap().to_string(), "3/2");
+        assert!(q("2").sqrt().is_none());
+        assert_eq!(q("-3.5").div_euclid(&q("1.5")).unwrap().to_string(), "-3");
+        assert_eq!(q("3.5").div_euclid(&q("-1.5")).unwrap().to_string(), "-2");
+        assert!(q("1").div_euclid(&Rational::zero()).is_none());
+    }
+
+    #[test]
+    fn test_decimal_output() {
+        let third = Rational::new(BigInt::from(1), BigInt::from(3)).unwrap();
+        assert_eq!(third.to_decimal(5), "0.33333");
+        assert_eq!((-&q("2")).recip().unwrap().to_decimal(5), "-0.5");
+        assert_eq!(
+            q("2").recip().unwrap().powi(3).unwrap().to_decimal(2),
+            "0.13"
+        );
+        assert_eq!(q("1234").to_decimal(3), "1234");
+        assert_eq!(third.to_f64(), 1.0 / 3.0);
+    }
+}
@@ -0,0 +1,901 @@
+//! Fixed-width integers for programmer mode
+//!
+//! A `Word` holds an integer of one of the types `i8` through `u128` as its
+//! two's complement bit pattern, along with what to do when a result does not
+//! fit the type: wrap around, as release builds of Rust do; saturate at the
+//! nearest bound; or fail with an `Overflow` error, as debug builds panic.
+//! Arithmetic is computed exactly and only then fitted to the type, so the
+//! policy sees the true result.
+//!
+//! Literals take the type and policy from the `Format` the environment holds.
+//! Decimal literals must be in range; `0x`, `0o` and `0b` literals are bit
+//! patterns, so `0xFF` is `-1` as an `i8`. Values converted from other modes
+//! have no format until they meet one that does.
+
+use super::{BigInt, BigUint, Number, Rational, inexact, unsupported_op};
+use crate::ast::Literal;
+use crate::functions::Builtin;
+use crate::{CalcError, Op, Span};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Integer types programmer mode can compute in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntType {
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+}
+
+impl IntType {
+    pub const ALL: [IntType; 10] = [
+        IntType::I8,
+        IntType::I16,
+        IntType::I32,
+        IntType::I64,
+        IntType::I128,
+        IntType::U8,
+        IntType::U16,
+        IntType::U32,
+        IntType::U64,
+        IntType::U128,
+    ];
+
+    /// Rust name of the type, e.g. `u8`
+    pub fn name(self) -> &'static str {
+        match self {
+            IntType::I8 => "i8",
+            IntType::I16 => "i16",
+            IntType::I32 => "i32",
+            IntType::I64 => "i64",
+            IntType::I128 => "i128",
+            IntType::U8 => "u8",
+            IntType::U16 => "u16",
+            IntType::U32 => "u32",
+            IntType::U64 => "u64",
+            IntType::U128 => "u128",
+        }
+    }
+
+    /// Type named `name`, as `name()` spells it
+    pub fn from_name(name: &str) -> Option<IntType> {
+        IntType::ALL.into_iter().find(|ty| ty.name() == name)
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            IntType::I8 | IntType::U8 => 8,
+            IntType::I16 | IntType::U16 => 16,
+            IntType::I32 | IntType::U32 => 32,
+            IntType::I64 | IntType::U64 => 64,
+            IntType::I128 | IntType::U128 => 128,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(
+            self,
+            IntType::I8 | IntType::I16 | IntType::I32 | IntType::I64 | IntType::I128
+        )
+    }
+
+    /// The bits a value of this type uses
+    fn mask(self) -> u128 {
+        u128::MAX >> (128 - self.bits())
+    }
+
+    /// Bit pattern of the largest value
+    fn max_bits(self) -> u128 {
+        if self.is_signed() {
+            self.mask() >> 1
+        } else {
+            self.mask()
+        }
+    }
+
+    /// Bit pattern of the smallest value
+    fn min_bits(self) -> u128 {
+        if self.is_signed() {
+            (self.mask() >> 1) + 1
+        } else {
+            0
+        }
+    }
+
+    /// Largest value, or `i128::MAX` for `u128`
+    fn max_signed(self) -> i128 {
+        i128::try_from(self.max_bits()).unwrap_or(i128::MAX)
+    }
+
+    fn min_signed(self) -> i128 {
+        if self.is_signed() {
+            -self.max_signed() - 1
+        } else {
+            0
+        }
+    }
+}
+
+impl fmt::Display for IntType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What happens to a result outside the range of its type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Keep the low bits, so `u8` `255 + 1` is `0`
+    Wrapping,
+    /// Clamp to the nearest bound, so `u8` `255 + 1` is `255`
+    Saturating,
+    /// Fail with an `Overflow` error
+    Error,
+}
+
+impl Overflow {
+    pub fn name(self) -> &'static str {
+        match self {
+            Overflow::Wrapping => "wrapping",
+            Overflow::Saturating => "saturating",
+            Overflow::Error => "error",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        [Overflow::Wrapping, Overflow::Saturating, Overflow::Error]
+            .into_iter()
+            .find(|o| o.name() == name)
+    }
+}
+
+/// Integer type and overflow policy of a `Word`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub ty: IntType,
+    pub overflow: Overflow,
+}
+
+impl Default for Format {
+    /// `i64`, failing on overflow like a debug build
+    fn default() -> Format {
+        Format {
+            ty: IntType::I64,
+            overflow: Overflow::Error,
+        }
+    }
+}
+
+/// How values without a format behave: wide, and strict about overflow
+const UNTYPED: Format = Format {
+    ty: IntType::I128,
+    overflow: Overflow::Error,
+};
+
+/// A fixed-width integer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    /// Two's complement bit pattern, zero above the type's width
+    bits: u128,
+    /// `None` for a value converted from another mode
+    format: Option<Format>,
+}
+
+/// Mathematically exact result of an operation, before it is fitted to a type
+enum Exact {
+    Signed(i128),
+    Unsigned(u128),
+    /// Beyond what 128 bits hold, in that direction
+    TooHigh,
+    TooLow,
+}
+
+impl Word {
+    /// `value` in `format`, whose type it must fit
+    pub fn new(value: i128, format: Format) -> Result<Word, CalcError> {
+        Word::fit(
+            Exact::Signed(value),
+            format,
+            || value as u128,
+            || value.to_string(),
+        )
+    }
+
+    /// Type and policy the value computes with
+    pub fn format(&self) -> Format {
+        self.format.unwrap_or(UNTYPED)
+    }
+
+    /// Type of the value, if it has one yet
+    pub fn ty(&self) -> Option<IntType> {
+        self.format.map(|f| f.ty)
+    }
+
+    /// The same value in `format`, under that format's overflow policy
+    pub fn cast(&self, format: Format) -> Result<Word, CalcError> {
+        Word::fit(
+            self.exact(),
+            format,
+            || self.sign_extended(),
+            || self.to_string(),
+        )
+    }
+
+    /// Two's complement bit pattern, `ty().bits()` wide
+    pub fn bits(&self) -> u128 {
+        self.bits
+    }
+
+    /// Value as a signed number, for signed types
+    fn signed(&self) -> i128 {
+        let shift = 128 - self.format().ty.bits();
+        ((self.bits << shift) as i128) >> shift
+    }
+
+    /// Bit pattern extended to 128 bits by the sign of a signed type
+    fn sign_extended(&self) -> u128 {
+        if self.format().ty.is_signed() {
+            self.signed() as u128
+        } else {
+            self.bits
+        }
+    }
+
+    fn exact(&self) -> Exact {
+        if self.format().ty.is_signed() {
+            Exact::Signed(self.signed())
+        } else {
+            Exact::Unsigned(self.bits)
+        }
+    }
+
+    fn is_negative(&self) -> bool {
+        self.format().ty.is_signed() && self.signed() < 0
+    }
+
+    fn is_zero(&self) -> bool {
+        self.bits == 0
+    }
+
+    fn value_cmp(&self, other: &Word) -> Ordering {
+        match (self.exact(), other.exact()) {
+            (Exact::Signed(a), Exact::Signed(b)) => a.cmp(&b),
+            (Exact::Unsigned(a), Exact::Unsigned(b)) => a.cmp(&b),
+            _ => self.to_f64().total_cmp(&other.to_f64()),
+        }
+    }
+
+    /// `exact` as a value of `format`, applying its overflow policy when it is
+    /// out of range; `wrapped` gives the low bits for wrapping, and `what`
+    /// names the operation for the error
+    fn fit(
+        exact: Exact,
+        format: Format,
+        wrapped: impl FnOnce() -> u128,
+        what: impl FnOnce() -> String,
+    ) -> Result<Word, CalcError> {
+        let ty = format.ty;
+        let word = |bits: u128| {
+            Ok(Word {
+                bits: bits & ty.mask(),
+                format: Some(format),
+            })
+        };
+        let above = match exact {
+            Exact::Signed(v) if v > ty.max_signed() => true,
+            Exact::Signed(v) if v < ty.min_signed() => false,
+            Exact::Unsigned(v) if v > ty.max_bits() => true,
+            Exact::Signed(v) => return word(v as u128),
+            Exact::Unsigned(v) => return word(v),
+            Exact::TooHigh => true,
+            Exact::TooLow => false,
+        };
+        match format.overflow {
+            Overflow::Wrapping => word(wrapped()),
+            Overflow::Saturating if above => word(ty.max_bits()),
+            Overflow::Saturating => word(ty.min_bits()),
+            Overflow::Error => Err(CalcError::Overflow(what(), ty.name(), Span::default())),
+        }
+    }
+
+    /// Text of `a op b`, for error messages
+    fn shown(a: &Word, op: Op, b: &Word) -> String {
+        format!("{} {} {}", a, op, b)
+    }
+}
+
+/// `base ^ exp` modulo 2^128
+fn wrapping_pow(mut base: u128, mut exp: u128) -> u128 {
+    let mut acc: u128 = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            acc = acc.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    acc
+}
+
+/// Exact `a op b` for the arithmetic operators on signed values
+fn signed_exact(op: Op, a: i128, b: i128) -> Exact {
+    let negative = (a < 0) != (b < 0);
+    let result = match op {
+        Op::Add => a
+            .checked_add(b)
+            .ok_or(if b > 0 { Exact::TooHigh } else { Exact::TooLow }),
+        Op::Sub => a
+            .checked_sub(b)
+            .ok_or(if b < 0 { Exact::TooHigh } else { Exact::TooLow }),
+        Op::Mul => a.checked_mul(b).ok_or(if negative {
+            Exact::TooLow
+        } else {
+            Exact::TooHigh
+        }),
+        // Only `MIN / -1` overflows
+        Op::Div => a.checked_div(b).ok_or(Exact::TooHigh),
+        Op::IntDiv => a.checked_div_euclid(b).ok_or(Exact::TooHigh),
+        Op::Rem => Ok(a.checked_rem_euclid(b).unwrap_or(0)),
+        _ => unreachable!("{} is not arithmetic", op),
+    };
+    result.map_or_else(|e| e, Exact::Signed)
+}
+
+/// Exact `a op b` for the arithmetic operators on unsigned values
+fn unsigned_exact(op: Op, a: u128, b: u128) -> Exact {
+    let result = match op {
+        Op::Add => a.checked_add(b).ok_or(Exact::TooHigh),
+        Op::Sub => a.checked_sub(b).ok_or(Exact::TooLow),
+        Op::Mul => a.checked_mul(b).ok_or(Exact::TooHigh),
+        Op::Div | Op::IntDiv => Ok(a / b),
+        Op::Rem => Ok(a % b),
+        _ => unreachable!("{} is not arithmetic", op),
+    };
+    result.map_or_else(|e| e, Exact::Unsigned)
+}
+
+/// Exact `a ^ exp` for a non-negative `exp`
+fn pow_exact(a: &Word, exp: u128) -> Exact {
+    let odd = exp & 1 == 1;
+    let small = u32::try_from(exp).ok();
+    match a.exact() {
+        Exact::Signed(v) => match small.and_then(|e| v.checked_pow(e)) {
+            Some(n) => Exact::Signed(n),
+            None if v == 0 || v == 1 => Exact::Signed(v),
+            None if v == -1 => Exact::Signed(if odd { -1 } else { 1 }),
+            None if v < 0 && odd => Exact::TooLow,
+            None => Exact::TooHigh,
+        },
+        Exact::Unsigned(v) => match small.and_then(|e| v.checked_pow(e)) {
+            Some(n) => Exact::Unsigned(n),
+            None if v <= 1 => Exact::Unsigned(v),
+            None => Exact::TooHigh,
+        },
+        other => other,
+    }
+}
+
+impl fmt::Display for Word {
+    /// The value in decimal; see `to_radix_string` for the others
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.exact() {
+            Exact::Signed(v) => write!(f, "{}", v),
+            _ => write!(f, "{}", self.bits),
+        }
+    }
+}
+
+impl Word {
+    /// The bit pattern in radix 16, 8 or 2 with its `0x`, `0o` or `0b` prefix
+    ///
+    /// Hexadecimal and binary show every bit of the type, in groups of four
+    /// digits joined by `_`, as Rust literals may be written.
+    pub fn to_radix_string(&self, radix: u32) -> String {
+        let bits = self.format().ty.bits() as usize;
+        let (prefix, digits) = match radix {
+            16 => ("0x", format!("{:0width$X}", self.bits, width = bits / 4)),
+            2 => ("0b", format!("{:0width$b}", self.bits, width = bits)),
+            8 => return format!("0o{:o}", self.bits),
+            _ => return self.to_string(),
+        };
+        let groups: Vec<&str> = digits
+            .as_bytes()
+            .rchunks(4)
+            .rev()
+            .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+            .collect();
+        format!("{}{}", prefix, groups.join("_"))
+    }
+}
+
+impl Number for Word {
+    const MODE: &'static str = "programmer";
+
+    type Context = Format;
+
+    fn from_literal(literal: &Literal, format: &Format) -> Result<Word, CalcError> {
+        let text = || literal.text.clone();
+        if let Some(pattern) = literal.radix_value() {
+            // A bit pattern that fits may set the sign bit
+            if pattern <= format.ty.mask() {
+                return Ok(Word {
+                    bits: pattern,
+                    format: Some(*format),
+                });
+            }
+            return Word::fit(Exact::Unsigned(pattern), *format, || pattern, text);
+        }
+        let exact = match literal.text.parse::<u128>() {
+            Ok(n) => Exact::Unsigned(n),
+            // `1e3` and the like
+            Err(_) if literal.value.fract() != 0.0 => {
+                return Err(inexact::<Word>(text()));
+            }
+            Err(_) if literal.value.abs() < 2f64.powi(127) => Exact::Signed(literal.value as i128),
+            Err(_) => Exact::TooHigh,
+        };
+        Word::fit(exact, *format, || literal.value as u128, text)
+    }
+
+    /// Negates the magnitude before fitting it, so that the minimum of a
+    /// signed type, like `-128` for `i8`, can be written. Bit patterns are
+    /// read as `from_literal` reads them and then negated, except for the
+    /// minimum itself: `-0x80` is `-128` too.
+    fn from_negated_literal(literal: &Literal, format: &Format) -> Result<Word, CalcError> {
+        if let Some(pattern) = literal.radix_value() {
+            if format.ty.is_signed() && pattern == format.ty.min_bits() {
+                return Ok(Word {
+                    bits: pattern,
+                    format: Some(*format),
+                });
+            }
+            return Word::from_literal(literal, format)?.negate();
+        }
+        let text = || format!("-{}", literal.text);
+        let exact = match literal.text.parse::<u128>() {
+            Ok(n) if n <= i128::MIN.unsigned_abs() => Exact::Signed(n.wrapping_neg() as i128),
+            Ok(_) => Exact::TooLow,
+            Err(_) if literal.value.fract() != 0.0 => {
+                return Err(inexact::<Word>(text()));
+            }
+            Err(_) if literal.value <= 2f64.powi(127) => Exact::Signed(-literal.value as i128),
+            Err(_) => Exact::TooLow,
+        };
+        Word::fit(exact, *format, || (-literal.value) as i128 as u128, text)
+    }
+
+    /// Counts are wide and strict, whatever the type: `2 ^ 200` in `i8`
+    /// multiplies 200 times rather than `200 as i8` times
+    fn count_context(_format: &Format) -> Format {
+        UNTYPED
+    }
+
+    fn constant(name: &str, _approx: f64) -> Result<Word, CalcError> {
+        Err(inexact::<Word>(name.to_string()))
+    }
+
+    fn from_f64(value: f64) -> Option<Word> {
+        if value.fract() != 0.0 || value.abs() >= 2f64.powi(127) {
+            return None;
+        }
+        Some(Word {
+            bits: value as i128 as u128,
+            format: None,
+        })
+    }
+
+    fn to_f64(&self) -> f64 {
+        match self.exact() {
+            Exact::Signed(v) => v as f64,
+            _ => self.bits as f64,
+        }
+    }
+
+    fn to_rational(&self) -> Option<Rational> {
+        let n = match self.exact() {
+            Exact::Signed(v) => BigInt::from_parts(v < 0, BigUint::from(v.unsigned_abs())),
+            _ => BigInt::from(BigUint::from(self.bits)),
+        };
+        Some(Rational::from_integer(n))
+    }
+
+    /// Integers in the `i128` range come without a type, like `from_f64`
+    /// values; larger ones up to `u128::MAX` come as `u128`
+    fn from_rational(value: &Rational) -> Option<Word> {
+        if !value.is_integer() {
+            return None;
+        }
+        let n = value.numer();
+        let magnitude = n.magnitude().to_u128()?;
+        let (bits, format) = match (n.is_negative(), i128::try_from(magnitude)) {
+            (true, _) if magnitude <= i128::MIN.unsigned_abs() => (magnitude.wrapping_neg(), None),
+            (false, Ok(_)) => (magnitude, None),
+            (false, Err(_)) => (
+                magnitude,
+                Some(Format {
+                    ty: IntType::U128,
+                    overflow: Overflow::Error,
+                }),
+            ),
+            (true, _) => return None,
+        };
+        Some(Word { bits, format })
+    }
+
+    fn negate(&self) -> Result<Word, CalcError> {
+        let exact = match self.exact() {
+            Exact::Signed(v) => v.checked_neg().map_or(Exact::TooHigh, Exact::Signed),
+            _ if self.is_zero() => Exact::Unsigned(0),
+            _ => Exact::TooLow,
+        };
+        Word::fit(
+            exact,
+            self.format(),
+            || self.bits.wrapping_neg(),
+            || format!("-{}", crate::eval::operand_text(self)),
+        )
+    }
+
+    fn bit_not(&self) -> Result<Word, CalcError> {
+        Ok(Word {
+            bits: !self.bits & self.format().ty.mask(),
+            format: self.format,
+        })
+    }
+
+    fn apply(op: Op, a: &Word, b: &Word) -> Result<Word, CalcError> {
+        // A value without a format takes the other's. The count of a power or
+        // shift keeps its own type, as `u32` counts do in Rust.
+        let format = a.format.or(b.format).unwrap_or(UNTYPED);
+        let b = match op {
+            Op::Pow | Op::Shl | Op::Shr => b.clone(),
+            _ => b.cast(format)?,
+        };
+        let (a, b) = (&a.cast(format)?, &b);
+        let ty = format.ty;
+        let same = |bits: u128| {
+            Ok(Word {
+                bits: bits & ty.mask(),
+                format: Some(format),
+            })
+        };
+        let what = || Word::shown(a, op, b);
+        match op {
+            Op::BitAnd => same(a.bits & b.bits),
+            Op::BitOr => same(a.bits | b.bits),
+            Op::BitXor => same(a.bits ^ b.bits),
+            Op::Shl | Op::Shr => {
+                // Bits shifted out are dropped; only the amount can overflow
+                let amount = match b.exact() {
+                    Exact::Signed(n) => u32::try_from(n).ok(),
+                    _ => u32::try_from(b.bits).ok(),
+                };
+                let amount = match amount.filter(|&n| n < ty.bits()) {
+                    Some(n) => n,
+                    None => match format.overflow {
+                        // Like `wrapping_shl`, which masks the amount
+                        Overflow::Wrapping => (b.bits % u128::from(ty.bits())) as u32,
+                        Overflow::Saturating if op == Op::Shr && a.is_negative() => {
+                            return same(u128::MAX);
+                        }
+                        Overflow::Saturating => return same(0),
+                        Overflow::Error => {
+                            return Err(CalcError::Overflow(what(), ty.name(), Span::default()));
+                        }
+                    },
+                };
+                if op == Op::Shl {
+                    same(a.bits << amount)
+                } else {
+                    same(a.sign_extended() >> amount | sign_fill(a, amount))
+                }
+            }
+            Op::Div | Op::IntDiv | Op::Rem if b.is_zero() => {
+                Err(CalcError::DivisionByZero(Span::default()))
+            }
+            Op::Pow => {
+                if b.is_negative() {
+                    return Err(inexact::<Word>(format!("{} ^ ({})", a, b)));
+                }
+                let exp = b.bits;
+                Word::fit(
+                    pow_exact(a, exp),
+                    format,
+                    || wrapping_pow(a.sign_extended(), exp),
+                    what,
+                )
+            }
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::IntDiv | Op::Rem => {
+                let exact = match (a.exact(), b.exact()) {
+                    (Exact::Signed(x), Exact::Signed(y)) => signed_exact(op, x, y),
+                    _ => unsigned_exact(op, a.bits, b.bits),
+                };
+                let wrapped = || {
+                    let (x, y) = (a.sign_extended(), b.sign_extended());
+                    match op {
+                        Op::Add => x.wrapping_add(y),
+                        Op::Sub => x.wrapping_sub(y),
+                        Op::Mul => x.wrapping_mul(y),
+                        // `MIN / -1` wraps back to `MIN`
+                        _ => ty.min_bits(),
+                    }
+                };
+                Word::fit(exact, format, wrapped, what)
+            }
+            Op::BitNot => Err(unsupported_op(op, a, b)),
+        }
+    }
+
+    /// Fails for negative numbers, and like repeated multiplication otherwise
+    fn factorial(&self) -> Result<Word, CalcError> {
+        if self.is_negative() {
+            return Err(inexact::<Word>(format!("({})!", self)));
+        }
+        let format = self.format();
+        // 256! is a multiple of 2^128, and far past every bound
+        if self.bits > 256 {
+            let exact = Exact::TooHigh;
+            return Word::fit(exact, format, || 0, || format!("{}!", self));
+        }
+        let mut acc = Word::new(1, format)?;
+        for k in 2..=self.bits {
+            let k = Word::fit(Exact::Unsigned(k), format, || k, || k.to_string())?;
+            acc = Word::apply(Op::Mul, &acc, &k).map_err(|_| {
+                CalcError::Overflow(format!("{}!", self), format.ty.name(), Span::default())
+            })?;
+        }
+        Ok(acc)
+    }
+
+    fn call(f: &Builtin, args: &[Word]) -> Result<Word, CalcError> {
+        let x = &args[0];
+        match f.name {
+            "abs" if x.is_negative() => x.negate(),
+            "abs" | "floor" | "ceil" | "round" | "re" | "conj" => Ok(x.clone()),
+            "im" => Word::new(0, x.format()),
+            "min" => Ok(args
+                .iter()
+                .min_by(|a, b| a.value_cmp(b))
+                .unwrap_or(x)
+                .clone()),
+            "max" => Ok(args
+                .iter()
+                .max_by(|a, b| a.value_cmp(b))
+                .unwrap_or(x)
+                .clone()),
+            "sqrt" if !x.is_negative() => {
+                let root = x.bits.isqrt();
+                if root * root == x.bits {
+                    Ok(Word {
+                        bits: root,
+                        format: x.format,
+                    })
+                } else {
+                    Err(super::inexact_call(f, args))
+                }
+            }
+            _ => Err(super::inexact_call(f, args)),
+        }
+    }
+}
+
+/// High bits an arithmetic right shift by `amount` fills with ones, for a
+/// negative signed `a`; the bits above the type are masked off afterwards
+fn sign_fill(a: &Word, amount: u32) -> u128 {
+    if a.is_negative() && amount > 0 {
+        !(u128::MAX >> amount)
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(ty: IntType, overflow: Overflow) -> Format {
+        Format { ty, overflow }
+    }
+
+    fn word(value: i128, ty: IntType, overflow: Overflow) -> Word {
+        Word::new(value, format(ty, overflow)).unwrap()
+    }
+
+    fn apply(
+        op: Op,
+        a: i128,
+        b: i128,
+        ty: IntType,
+        overflow: Overflow,
+    ) -> Result<String, CalcError> {
+        let (a, b) = (word(a, ty, overflow), word(b, ty, overflow));
+        Word::apply(op, &a, &b).map(|w| w.to_string())
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        use IntType::*;
+        use Overflow::*;
+        assert_eq!(apply(Op::Add, 255, 1, U8, Wrapping).unwrap(), "0");
+        assert_eq!(apply(Op::Add, 255, 1, U8, Saturating).unwrap(), "255");
+        let err = apply(Op::Add, 255, 1, U8, Error).unwrap_err();
+        assert_eq!(err.to_string(), "255 + 1 overflows u8");
+        assert_eq!(apply(Op::Sub, 0, 1, U32, Wrapping).unwrap(), "4294967295");
+        assert_eq!(apply(Op::Sub, 0, 1, U32, Saturating).unwrap(), "0");
+        assert_eq!(apply(Op::Mul, 100, 2, I8, Wrapping).unwrap(), "-56");
+        assert_eq!(apply(Op::Mul, -100, 2, I8, Saturating).unwrap(), "-128");
+        assert_eq!(apply(Op::Div, -128, -1, I8, Wrapping).unwrap(), "-128");
+        assert_eq!(apply(Op::Div, -7, 2, I8, Error).unwrap(), "-3");
+        assert_eq!(apply(Op::IntDiv, -7, 2, I8, Error).unwrap(), "-4");
+        assert_eq!(apply(Op::Rem, -7, 2, I8, Error).unwrap(), "1");
+        assert_eq!(apply(Op::Pow, 3, 5, U8, Wrapping).unwrap(), "243");
+        assert_eq!(apply(Op::Pow, 3, 6, U8, Wrapping).unwrap(), "217");
+        assert!(matches!(
+            apply(Op::Pow, 2, -1, I32, Error),
+            Err(CalcError::Inexact(..))
+        ));
+        // 128-bit types overflow past what the exact computation can hold
+        let max = i128::MAX;
+        assert_eq!(
+            apply(Op::Add, max, 1, I128, Wrapping).unwrap(),
+            i128::MIN.to_string()
+        );
+        assert_eq!(
+            apply(Op::Mul, max, -2, I128, Saturating).unwrap(),
+            i128::MIN.to_string()
+        );
+        let big = word(0, U128, Wrapping).bit_not().unwrap();
+        let one = word(1, U128, Wrapping);
+        assert_eq!(Word::apply(Op::Add, &big, &one).unwrap().to_string(), "0");
+        assert!(matches!(
+            apply(Op::Div, 1, 0, U8, Wrapping),
+            Err(CalcError::DivisionByZero(_))
+        ));
+        assert_eq!(
+            word(-128, I8, Wrapping).negate().unwrap().to_string(),
+            "-128"
+        );
+        assert!(word(5, U8, Error).negate().is_err());
+    }
+
+    #[test]
+    fn test_bitwise() {
+        use IntType::*;
+        use Overflow::*;
+        assert_eq!(apply(Op::BitAnd, 0b1100, 0b1010, U8, Error).unwrap(), "8");
+        assert_eq!(apply(Op::BitOr, 0b1100, 0b1010, U8, Error).unwrap(), "14");
+        assert_eq!(apply(Op::BitXor, 0b1100, 0b1010, U8, Error).unwrap(), "6");
+        assert_eq!(word(0, I16, Error).bit_not().unwrap().to_string(), "-1");
+        assert_eq!(word(5, U8, Error).bit_not().unwrap().to_string(), "250");
+        // Bits shifted out are dropped; the amount must be below the width
+        assert_eq!(apply(Op::Shl, 0x81, 1, U8, Error).unwrap(), "2");
+        assert_eq!(apply(Op::Shr, -16, 2, I8, Error).unwrap(), "-4");
+        assert_eq!(apply(Op::Shr, 0xF0, 2, U8, Error).unwrap(), "60");
+        assert_eq!(apply(Op::Shl, 1, 9, U8, Wrapping).unwrap(), "2");
+        assert_eq!(apply(Op::Shr, -1, 9, I8, Saturating).unwrap(), "-1");
+        let err = apply(Op::Shl, 1, 8, U8, Error).unwrap_err();
+        assert_eq!(err.to_string(), "1 << 8 overflows u8");
+    }
+
+    #[test]
+    fn test_literals_and_conversion() {
+        let i8_format = format(IntType::I8, Overflow::Error);
+        let lit = |text: &str, value: f64| Literal {
+            value,
+            text: text.to_string(),
+            imaginary: false,
+        };
+        // Radix literals are bit patterns, decimal ones values
+        let minus_one = Word::from_literal(&lit("0xFF", 255.0), &i8_format).unwrap();
+        assert_eq!(minus_one.to_string(), "-1");
+        let err = Word::from_literal(&lit("255", 255.0), &i8_format).unwrap_err();
+        assert_eq!(err.to_string(), "255 overflows i8");
+        assert!(Word::from_literal(&lit("0x1FF", 511.0), &i8_format).is_err());
+        assert!(matches!(
+            Word::from_literal(&lit("1.5", 1.5), &i8_format),
+            Err(CalcError::Inexact(..))
+        ));
+        // Values from other modes adopt the format they meet
+        let three = Word::from_f64(3.0).unwrap();
+        assert_eq!(three.ty(), None);
+        let sum = Word::apply(Op::Add, &three, &minus_one).unwrap();
+        assert_eq!(
+            (sum.to_string(), sum.ty()),
+            ("2".to_string(), Some(IntType::I8))
+        );
+        assert!(Word::from_f64(0.5).is_none());
+    }
+
+    #[test]
+    fn test_signed_minimum_literals() {
+        use crate::{Environment, evaluate, parse};
+        let eval = |input: &str, ty: IntType, overflow: Overflow| {
+            let mut env: Environment<Word> = Environment::new();
+            env.set_context(format(ty, overflow));
+            evaluate(&parse(input).unwrap(), &env).map(|w| w.to_string())
+        };
+        assert_eq!(eval("-128", IntType::I8, Overflow::Error).unwrap(), "-128");
+        assert_eq!(
+            eval("-128", IntType::I8, Overflow::Saturating).unwrap(),
+            "-128"
+        );
+        assert_eq!(
+            eval("-129", IntType::I8, Overflow::Saturating).unwrap(),
+            "-128"
+        );
+        let err = eval("-129", IntType::I8, Overflow::Error).unwrap_err();
+        assert_eq!(err.to_string(), "-129 overflows i8");
+        let min = i128::MIN.to_string();
+        assert_eq!(eval(&min, IntType::I128, Overflow::Error).unwrap(), min);
+        assert!(
+            eval(
+                "-170141183460469231731687303715884105729",
+                IntType::I128,
+                Overflow::Error
+            )
+            .is_err()
+        );
+        assert_eq!(eval("-1e2", IntType::I8, Overflow::Error).unwrap(), "-100");
+        assert_eq!(
+            eval("-0x80", IntType::I8, Overflow::Wrapping).unwrap(),
+            "-128"
+        );
+        assert_eq!(eval("-0x80", IntType::I8, Overflow::Error).unwrap(), "-128");
+        // Other patterns with the sign bit set are negated as values
+        assert_eq!(eval("-0xFF", IntType::I8, Overflow::Error).unwrap(), "1");
+        let err = eval("-(-128)", IntType::I8, Overflow::Error).unwrap_err();
+        assert_eq!(err.to_string(), "-(-128) overflows i8");
+    }
+
+    #[test]
+    fn test_counts_keep_their_value() {
+        use crate::{Environment, evaluate, parse};
+        let eval = |input: &str, ty: IntType, overflow: Overflow| {
+            let mut env: Environment<Word> = Environment::new();
+            env.set_context(format(ty, overflow));
+            evaluate(&parse(input).unwrap(), &env).map(|w| w.to_string())
+        };
+        // 200 would be -56 as an `i8`
+        assert_eq!(
+            eval("2 ^ 200", IntType::I8, Overflow::Wrapping).unwrap(),
+            "0"
+        );
+        assert_eq!(
+            eval("3 ^ 200", IntType::I8, Overflow::Wrapping).unwrap(),
+            "-95"
+        );
+        assert_eq!(
+            eval("1 << 200", IntType::I8, Overflow::Wrapping).unwrap(),
+            "1"
+        );
+        let err = eval("2 ^ 200", IntType::I8, Overflow::Error).unwrap_err();
+        assert_eq!(err.to_string(), "2 ^ 200 overflows i8");
+        let err = eval("1 << 300", IntType::U8, Overflow::Error).unwrap_err();
+        assert_eq!(err.to_string(), "1 << 300 overflows u8");
+        assert!(matches!(
+            eval("2 ^ -1", IntType::I8, Overflow::Wrapping),
+            Err(CalcError::Inexact(..))
+        ));
+    }
+
+    #[test]
+    fn test_radix_strings() {
+        let w = word(-2, IntType::I16, Overflow::Error);
+        assert_eq!(w.to_radix_string(16), "0xFFFE");
+        assert_eq!(w.to_radix_string(8), "0o177776");
+        assert_eq!(w.to_radix_string(2), "0b1111_1111_1111_1110");
+        let w = word(0xDEADBEEF, IntType::U64, Overflow::Error);
+        assert_eq!(w.to_radix_string(16), "0x0000_0000_DEAD_BEEF");
+        assert_eq!(
+            word(5, IntType::U8, Overflow::Error).to_radix_string(2),
+            "0b0000_0101"
+        );
+    }
+}
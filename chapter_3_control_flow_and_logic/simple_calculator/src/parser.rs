@@ -0,0 +1,642 @@
+//! Parser: builds an expression tree from the lexer's tokens
+//!
+//! Recursive descent with precedence climbing for the binary operators.
+
+use crate::ast::Statement;
+use crate::lexer::{Span, Token, TokenKind, tokenize_with};
+use crate::registry::{Assoc, Implementation, Operator, Registry};
+use crate::{CalcError, Expr, ExprKind, Op};
+
+/// Registered operator spelled by `token`, either as a symbol or as a word
+fn token_op<'r>(token: &Token, registry: &'r Registry) -> Option<&'r Operator> {
+    match &token.kind {
+        TokenKind::Op(op) => registry.builtin_operator(*op),
+        TokenKind::Operator(symbol) | TokenKind::Ident(symbol) => registry.operator(symbol),
+        _ => None,
+    }
+}
+
+/// Node applying `op` to `args`: built-in operators keep their own node kinds
+fn apply(op: &Operator, args: Vec<Expr>, span: Span) -> Expr {
+    let mut args = args.into_iter();
+    let expr = match (&op.implementation, args.len()) {
+        (Implementation::Builtin(b), 1) => Expr::unary(*b, args.next().unwrap()),
+        (Implementation::Builtin(b), _) => {
+            Expr::binary(*b, args.next().unwrap(), args.next().unwrap())
+        }
+        (Implementation::Custom(_), _) => {
+            Expr::new(ExprKind::Operator(op.symbol.clone(), args.collect()))
+        }
+    };
+    expr.with_span(span)
+}
+
+/// How deeply an expression may nest, counting parentheses, signs, arguments
+/// and each operator in a chain; deeper input is an error rather than a stack
+/// overflow in the parser or in whatever walks the tree afterwards
+pub const MAX_NESTING: usize = 128;
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    registry: &'a Registry,
+    /// Nesting of the node being parsed
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let tok = self.peek();
+        self.pos += 1;
+        tok
+    }
+
+    /// Error for running out of tokens, pointing just past the last one
+    fn unexpected_end(&self) -> CalcError {
+        let end = self.tokens.last().map_or(0, |t| t.span.end);
+        CalcError::UnexpectedEnd(Span::new(end, end))
+    }
+
+    /// Go one level deeper, failing past `MAX_NESTING`
+    fn nest(&mut self) -> Result<(), CalcError> {
+        self.depth += 1;
+        if self.depth <= MAX_NESTING {
+            return Ok(());
+        }
+        let span = match self.peek() {
+            Some(tok) => tok.span,
+            None => self.unexpected_end().span(),
+        };
+        Err(CalcError::TooDeep(span))
+    }
+
+    /// Parse a chain of binary operators whose precedence is at least `min_prec`
+    fn expr(&mut self, min_prec: u8) -> Result<Expr, CalcError> {
+        let depth = self.depth;
+        self.nest()?;
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.peek().and_then(|tok| token_op(tok, self.registry)) {
+            // `~` and other prefix operators only ever come before their operand
+            if op.arity == 1 || op.precedence < min_prec {
+                break;
+            }
+            self.pos += 1;
+            // Each operator puts the chain so far one level further down
+            self.nest()?;
+            let next_min = match op.assoc {
+                Assoc::Right => op.precedence,
+                Assoc::Left => op.precedence + 1,
+            };
+            let rhs = self.expr(next_min)?;
+            let span = lhs.span.to(rhs.span);
+            lhs = apply(op, vec![lhs, rhs], span);
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    /// Operand position: a primary, a sign, `~` or another prefix operator, or,
+    /// at the start of the input only, the prefix form `op a b`
+    ///
+    /// The operands of a sign or prefix form bind like the right side of `^`,
+    /// so `-2 ^ 2` is `-(2 ^ 2)` and `+ 2 3` is `2 + 3`. A prefix operator's
+    /// operand binds as tightly as its precedence. Elsewhere a sign takes one
+    /// operand, so `2 * - 3 4` is an error rather than `2 * (3 - 4)`.
+    fn unary(&mut self) -> Result<Expr, CalcError> {
+        let Some(tok) = self.peek() else {
+            return Err(self.unexpected_end());
+        };
+        let Some(op) = token_op(tok, self.registry) else {
+            return self.postfix();
+        };
+        let at_start = self.pos == 0;
+        self.pos += 1;
+        if op.arity == 1 {
+            let operand = self.expr(op.precedence)?;
+            let span = tok.span.to(operand.span);
+            return Ok(apply(op, vec![operand], span));
+        }
+        let first = self.expr(Op::Pow.precedence())?;
+        if at_start && self.starts_operand() {
+            let second = self.expr(Op::Pow.precedence())?;
+            let span = tok.span.to(second.span);
+            return Ok(apply(op, vec![first, second], span));
+        }
+        let span = tok.span.to(first.span);
+        match op.implementation {
+            Implementation::Builtin(sign @ (Op::Add | Op::Sub)) => {
+                Ok(Expr::unary(sign, first).with_span(span))
+            }
+            _ => Err(CalcError::WrongArity(span)),
+        }
+    }
+
+    /// Whether the next token can begin an operand (operators are never treated as one)
+    fn starts_operand(&self) -> bool {
+        match self.peek() {
+            Some(tok) => match &tok.kind {
+                TokenKind::Number(_) | TokenKind::LParen => true,
+                TokenKind::Ident(_) => token_op(tok, self.registry).is_none(),
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    /// A primary followed by any number of postfix `!`
+    fn postfix(&mut self) -> Result<Expr, CalcError> {
+        let depth = self.depth;
+        let mut expr = self.primary()?;
+        while let Some(bang) = self.peek().filter(|t| t.kind == TokenKind::Bang) {
+            self.nest()?;
+            self.pos += 1;
+            let span = expr.span.to(bang.span);
+            expr = Expr::factorial(expr).with_span(span);
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, CalcError> {
+        let Some(tok) = self.next() else {
+            return Err(self.unexpected_end());
+        };
+        match &tok.kind {
+            TokenKind::Number(n) => Ok(Expr::new(ExprKind::Number(n.clone())).with_span(tok.span)),
+            TokenKind::Ident(name) if self.peek().map(|t| &t.kind) == Some(&TokenKind::LParen) => {
+                self.pos += 1;
+                let (args, close) = self.arguments()?;
+                Ok(Expr::call(name.as_str(), args).with_span(tok.span.to(close)))
+            }
+            TokenKind::Ident(name) => Ok(Expr::var(name.as_str()).with_span(tok.span)),
+            TokenKind::LParen => {
+                let inner = self.expr(0)?;
+                match self.next() {
+                    Some(close) if close.kind == TokenKind::RParen => {
+                        Ok(inner.with_span(tok.span.to(close.span)))
+                    }
+                    Some(other) => Err(unexpected(other)),
+                    None => Err(self.unexpected_end()),
+                }
+            }
+            _ => Err(CalcError::UnexpectedToken(tok.kind.to_string(), tok.span)),
+        }
+    }
+}
+
+impl Parser<'_> {
+    /// Comma-separated call arguments after the opening parenthesis, and the
+    /// span of the closing one
+    fn arguments(&mut self) -> Result<(Vec<Expr>, Span), CalcError> {
+        let mut args = Vec::new();
+        if let Some(close) = self.peek().filter(|t| t.kind == TokenKind::RParen) {
+            self.pos += 1;
+            return Ok((args, close.span));
+        }
+        loop {
+            args.push(self.expr(0)?);
+            match self.next() {
+                Some(tok) if tok.kind == TokenKind::Comma => continue,
+                Some(tok) if tok.kind == TokenKind::RParen => return Ok((args, tok.span)),
+                Some(tok) => return Err(unexpected(tok)),
+                None => return Err(self.unexpected_end()),
+            }
+        }
+    }
+}
+
+/// Error for a token found where an operator or the end of input was expected
+fn unexpected(tok: &Token) -> CalcError {
+    match &tok.kind {
+        TokenKind::Ident(word) => CalcError::UnknownOperator(word.clone(), tok.span),
+        other => CalcError::UnexpectedToken(other.to_string(), tok.span),
+    }
+}
+
+/// Parse a token stream into an expression tree
+///
+/// Accepts infix (`2 + 3 * 4`), prefix at the start (`add 2 3`, `+ 2 3`) and
+/// parenthesised forms with the usual precedence: a postfix `!` binds tightest, then `^`,
+/// which groups to the right, then `*`, `/`, `//` and `%`, then `+` and `-`,
+/// then the shifts, `&`, `^^` and `|`. A leading `-` negates its operand and
+/// a leading `~` flips its bits.
+pub fn parse_expression(tokens: &[Token]) -> Result<Expr, CalcError> {
+    parse_expression_with(tokens, Registry::builtin())
+}
+
+/// Parse a token stream into an expression tree, with the operators in
+/// `registry` and their precedences
+pub fn parse_expression_with(tokens: &[Token], registry: &Registry) -> Result<Expr, CalcError> {
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        registry,
+        depth: 0,
+    };
+    let expr = parser.expr(0)?;
+    match parser.peek() {
+        Some(tok) => Err(unexpected(tok)),
+        None => Ok(expr),
+    }
+}
+
+/// Tokenize and parse an input line
+pub fn parse(input: &str) -> Result<Expr, CalcError> {
+    parse_with(input, Registry::builtin())
+}
+
+/// Tokenize and parse an input line with the operators in `registry`
+pub fn parse_with(input: &str, registry: &Registry) -> Result<Expr, CalcError> {
+    parse_expression_with(&tokenize_with(input, registry)?, registry)
+}
+
+/// Name and parameters of a definition `name(a, b) =`, and the tokens of its body
+type DefinitionHead<'t> = (&'t str, Vec<(&'t str, Span)>, &'t [Token]);
+
+/// Split off a function definition head, if `tokens` start with one
+fn definition_head<'t>(tokens: &'t [Token], registry: &Registry) -> Option<DefinitionHead<'t>> {
+    let [name_tok, open, rest @ ..] = tokens else {
+        return None;
+    };
+    let TokenKind::Ident(name) = &name_tok.kind else {
+        return None;
+    };
+    if open.kind != TokenKind::LParen || registry.operator(name).is_some() {
+        return None;
+    }
+    let mut params = Vec::new();
+    let mut rest = rest;
+    // After a comma another parameter must follow, so `f(x,) =` is no definition
+    let mut after_comma = false;
+    loop {
+        match rest {
+            [close, assign, body @ ..]
+                if close.kind == TokenKind::RParen
+                    && assign.kind == TokenKind::Assign
+                    && !after_comma =>
+            {
+                return Some((name, params, body));
+            }
+            [param, sep, tail @ ..] => {
+                let TokenKind::Ident(param_name) = &param.kind else {
+                    return None;
+                };
+                params.push((param_name.as_str(), param.span));
+                after_comma = sep.kind == TokenKind::Comma;
+                match sep.kind {
+                    TokenKind::Comma => rest = tail,
+                    TokenKind::RParen => rest = &rest[1..],
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Parse a token stream as a statement: `name = expr`, `name(params) = expr`
+/// or a bare expression
+pub fn parse_statement_tokens(tokens: &[Token]) -> Result<Statement, CalcError> {
+    parse_statement_tokens_with(tokens, Registry::builtin())
+}
+
+/// Parse a token stream as a statement, with the operators in `registry`
+pub fn parse_statement_tokens_with(
+    tokens: &[Token],
+    registry: &Registry,
+) -> Result<Statement, CalcError> {
+    let end_error = || {
+        Parser {
+            tokens,
+            pos: 0,
+            registry,
+            depth: 0,
+        }
+        .unexpected_end()
+    };
+    if let Some((name, params, body)) = definition_head(tokens, registry) {
+        if body.is_empty() {
+            return Err(end_error());
+        }
+        for (i, (param, span)) in params.iter().enumerate() {
+            if params[..i].iter().any(|(earlier, _)| earlier == param) {
+                return Err(CalcError::DuplicateParameter(param.to_string(), *span));
+            }
+        }
+        let params = params.into_iter().map(|(p, _)| p.to_string()).collect();
+        return Ok(Statement::Define(
+            name.to_string(),
+            params,
+            parse_expression_with(body, registry)?,
+        ));
+    }
+    match tokens {
+        [
+            Token {
+                kind: TokenKind::Ident(name),
+                ..
+            },
+            Token {
+                kind: TokenKind::Assign,
+                ..
+            },
+            rest @ ..,
+        ] if registry.operator(name).is_none() => {
+            if rest.is_empty() {
+                return Err(end_error());
+            }
+            Ok(Statement::Assign(
+                name.clone(),
+                parse_expression_with(rest, registry)?,
+            ))
+        }
+        _ => parse_expression_with(tokens, registry).map(Statement::Expr),
+    }
+}
+
+/// Tokenize and parse an input line as a statement
+pub fn parse_statement(input: &str) -> Result<Statement, CalcError> {
+    parse_statement_with(input, Registry::builtin())
+}
+
+/// Tokenize and parse an input line as a statement, with the operators in
+/// `registry`
+pub fn parse_statement_with(input: &str, registry: &Registry) -> Result<Statement, CalcError> {
+    parse_statement_tokens_with(&tokenize_with(input, registry)?, registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::{Environment, evaluate};
+
+    fn eval_str(input: &str) -> Result<f64, CalcError> {
+        evaluate(&parse(input)?, &Environment::<f64>::new())
+    }
+
+    #[test]
+    fn test_add_prefix() {
+        let tokens = tokenize("add 2 3").unwrap();
+        let expr = parse_expression(&tokens).unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(Op::Add, Expr::number(2.0), Expr::number(3.0))
+        );
+        assert_eq!(evaluate(&expr, &Environment::<f64>::new()).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn test_mul_infix() {
+        let tokens = tokenize("* 4 2.5").unwrap();
+        let expr = parse_expression(&tokens).unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(Op::Mul, Expr::number(4.0), Expr::number(2.5))
+        );
+        assert!((evaluate(&expr, &Environment::<f64>::new()).unwrap() - 10.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_div_by_zero() {
+        let tokens = tokenize("/ 10 0").unwrap();
+        let expr = parse_expression(&tokens).unwrap();
+        let env: Environment = Environment::new();
+        let r = evaluate(&expr, &env);
+        println!("{:?}", r);
+        assert!(matches!(
+            evaluate(&expr, &env),
+            Err(CalcError::DivisionByZero(_))
+        ));
+    }
+
+    #[test]
+    fn test_unknown_operator() {
+        assert!(matches!(
+            parse("@ 10 3"),
+            Err(CalcError::UnknownOperator(..))
+        ));
+        assert!(matches!(
+            eval_str("10 modulo 3"),
+            Err(CalcError::UnknownOperator(..))
+        ));
+    }
+
+    #[test]
+    fn test_precedence_and_parentheses() {
+        assert_eq!(eval_str("2 + 3 * 4").unwrap(), 14.0);
+        assert_eq!(eval_str("(2 + 3) * 4").unwrap(), 20.0);
+        assert_eq!(eval_str("10 - 4 - 3").unwrap(), 3.0);
+        assert_eq!(eval_str("16 / 4 / 2").unwrap(), 2.0);
+        let r = eval_str("(2 + 3) * 4 ^ 2 / -7").unwrap();
+        assert!((r - 80.0 / -7.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_euclidean_division_and_factorial() {
+        assert_eq!(eval_str("% 10 3").unwrap(), 1.0);
+        assert_eq!(eval_str("-7 mod 3").unwrap(), 2.0);
+        assert_eq!(eval_str("7 % -3").unwrap(), 1.0);
+        assert_eq!(eval_str("-7 // 2").unwrap(), -4.0);
+        assert_eq!(eval_str("1 + 7 // 2 * 2").unwrap(), 7.0);
+        assert_eq!(eval_str("-3! + 2^3!").unwrap(), 58.0);
+        assert_eq!(eval_str("(1 + 2)!!").unwrap(), 720.0);
+        assert!(matches!(eval_str("(-1)!"), Err(CalcError::Domain(..))));
+        assert!(matches!(
+            eval_str("1 % 0"),
+            Err(CalcError::DivisionByZero(_))
+        ));
+        assert!(matches!(
+            eval_str("!3"),
+            Err(CalcError::UnexpectedToken(..))
+        ));
+    }
+
+    #[test]
+    fn test_pow_right_assoc() {
+        assert_eq!(eval_str("2 ^ 3 ^ 2").unwrap(), 512.0);
+    }
+
+    #[test]
+    fn test_unary_minus() {
+        assert_eq!(eval_str("-2 ^ 2").unwrap(), -4.0);
+        assert_eq!(eval_str("2 * -3").unwrap(), -6.0);
+        assert_eq!(eval_str("2^-1").unwrap(), 0.5);
+        assert_eq!(eval_str("--5").unwrap(), 5.0);
+        // A second operand turns the sign into the prefix form, but only at
+        // the start
+        assert_eq!(eval_str("- 7 2").unwrap(), 5.0);
+        for input in ["2 * - 3 4", "1 + (+ 2 3)", "- 7 - 2 1"] {
+            assert!(
+                matches!(parse(input), Err(CalcError::UnexpectedToken(..))),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_bitwise_precedence() {
+        let shown = |input: &str| parse(input).unwrap().to_string();
+        assert_eq!(shown("a & 0xF | b ^^ c"), "a & 0xF | b ^^ c");
+        assert_eq!(shown("(a | b) & c"), "(a | b) & c");
+        assert_eq!(shown("1 << 4 - 1"), "1 << 4 - 1");
+        assert_eq!(shown("(1 << 4) - 1"), "(1 << 4) - 1");
+        assert_eq!(shown("a xor b"), "a ^^ b");
+        assert_eq!(shown("~a & ~(b + 1)"), "~a & ~(b + 1)");
+        assert_eq!(shown("& 6 3"), "6 & 3");
+        let tree = parse("a & 0xF | b ^^ c").unwrap();
+        let ExprKind::Binary(Op::BitOr, lhs, _) = &tree.kind else {
+            panic!("expected | at the top, got {}", tree);
+        };
+        assert!(matches!(lhs.kind, ExprKind::Binary(Op::BitAnd, ..)));
+        assert!(matches!(
+            parse("2 ~ 3"),
+            Err(CalcError::UnexpectedToken(token, _)) if token == "~"
+        ));
+    }
+
+    #[test]
+    fn test_without_whitespace() {
+        assert_eq!(eval_str("2+3").unwrap(), 5.0);
+        assert_eq!(eval_str("(1)").unwrap(), 1.0);
+        assert_eq!(eval_str("-4").unwrap(), -4.0);
+        assert_eq!(eval_str("3*-2").unwrap(), -6.0);
+        assert_eq!(eval_str("1.5e3/(2+1)").unwrap(), 500.0);
+    }
+
+    #[test]
+    fn test_malformed_expressions() {
+        assert!(matches!(
+            eval_str("(2 + 3"),
+            Err(CalcError::UnexpectedEnd(_))
+        ));
+        assert!(matches!(eval_str("2 +"), Err(CalcError::UnexpectedEnd(_))));
+        assert!(matches!(
+            eval_str("2 3"),
+            Err(CalcError::UnexpectedToken(..))
+        ));
+        assert!(matches!(
+            eval_str("2 )"),
+            Err(CalcError::UnexpectedToken(..))
+        ));
+        assert!(matches!(
+            eval_str("1, 2"),
+            Err(CalcError::UnexpectedToken(..))
+        ));
+        assert!(matches!(eval_str("* 2"), Err(CalcError::WrongArity(_))));
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let n = 10_000;
+        for input in [
+            "(".repeat(100_000) + "1" + &")".repeat(100_000),
+            "-".repeat(n) + "1",
+            "2^".repeat(n) + "2",
+            "1+".repeat(n) + "1",
+            "3".to_string() + &"!".repeat(n),
+        ] {
+            let err = parse(&input).unwrap_err();
+            assert!(matches!(err, CalcError::TooDeep(_)), "{:?}", err);
+            assert_eq!(err.to_string(), "expression nested too deeply");
+        }
+        // Just inside the limit, the tree still evaluates and prints
+        let input = "-".repeat(MAX_NESTING - 1) + "1";
+        let expr = parse(&input).unwrap();
+        assert_eq!(eval_str(&input).unwrap(), -1.0);
+        assert_eq!(parse(&expr.to_string()).unwrap(), expr);
+        assert_eq!(
+            eval_str(&("1+".repeat(MAX_NESTING - 2) + "1")).unwrap(),
+            127.0
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let expr = parse("1 + (2 * 3)").unwrap();
+        assert_eq!(expr.span, Span::new(0, 11));
+        let ExprKind::Binary(_, lhs, rhs) = &expr.kind else {
+            panic!("expected a binary node, got {:?}", expr);
+        };
+        assert_eq!(lhs.span, Span::new(0, 1));
+        assert_eq!(rhs.span, Span::new(4, 11));
+        assert_eq!(parse("* 2").unwrap_err().span(), Span::new(0, 3));
+        assert_eq!(parse("2 3").unwrap_err().span(), Span::new(2, 3));
+    }
+
+    #[test]
+    fn test_variables_and_assignment() {
+        assert_eq!(
+            parse_statement("x = 4.2").unwrap(),
+            Statement::Assign("x".to_string(), Expr::number(4.2))
+        );
+        assert_eq!(
+            parse_statement("x * 3").unwrap(),
+            Statement::Expr(Expr::binary(Op::Mul, Expr::var("x"), Expr::number(3.0)))
+        );
+        assert_eq!(
+            parse("+ x y").unwrap(),
+            Expr::binary(Op::Add, Expr::var("x"), Expr::var("y"))
+        );
+        assert!(matches!(
+            parse_statement("x ="),
+            Err(CalcError::UnexpectedEnd(_))
+        ));
+        assert!(matches!(
+            parse_statement("1 = 2"),
+            Err(CalcError::UnexpectedToken(..))
+        ));
+    }
+
+    #[test]
+    fn test_function_calls() {
+        assert_eq!(
+            parse("max(1, x) + f()").unwrap(),
+            Expr::binary(
+                Op::Add,
+                Expr::call("max", vec![Expr::number(1.0), Expr::var("x")]),
+                Expr::call("f", vec![])
+            )
+        );
+        assert_eq!(parse("sqrt(2) ^ 2").unwrap().span, Span::new(0, 11));
+        assert!(matches!(parse("max(1,"), Err(CalcError::UnexpectedEnd(_))));
+        assert!(matches!(
+            parse("max(1 2)"),
+            Err(CalcError::UnexpectedToken(..))
+        ));
+    }
+
+    #[test]
+    fn test_function_definitions() {
+        assert_eq!(
+            parse_statement("hyp(a, b) = sqrt(a^2 + b^2)")
+                .unwrap()
+                .to_string(),
+            "hyp(a, b) = sqrt(a ^ 2 + b ^ 2)"
+        );
+        assert_eq!(
+            parse_statement("two() = 2").unwrap(),
+            Statement::Define("two".to_string(), vec![], Expr::number(2.0))
+        );
+        // A call on the left of `=` is not a definition
+        assert!(matches!(
+            parse_statement("f(2) = 3"),
+            Err(CalcError::UnexpectedToken(..))
+        ));
+        assert!(matches!(
+            parse_statement("f(x, x) = x"),
+            Err(CalcError::DuplicateParameter(name, span)) if name == "x" && span == Span::new(5, 6)
+        ));
+        assert!(matches!(
+            parse_statement("f(x) ="),
+            Err(CalcError::UnexpectedEnd(_))
+        ));
+        // An empty parameter is not a definition either
+        assert!(parse_statement("f(x,) = x+1").is_err());
+    }
+}
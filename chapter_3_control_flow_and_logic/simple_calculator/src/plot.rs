@@ -0,0 +1,493 @@
+//! Text plots of functions of one variable
+//!
+//! `plot` samples each formula once per character column and `render` draws
+//! the samples on a character grid: a y axis labeled at the top, bottom and
+//! zero, an x axis labeled at both ends, and one glyph per curve with a
+//! legend underneath. Rendering only builds a `String`, so plots can be
+//! compared in tests without a terminal.
+//!
+//! The y range covers the samples, but ignores the few largest and smallest
+//! when they are far out, so one pole does not flatten the rest of the curve.
+//! Neighbouring points are joined, except across a discontinuity, like the
+//! poles of `tan(x)` or the steps of `floor(x)`; there the rows between them
+//! are marked with `:`. Columns where a curve has no value (an error or NaN)
+//! are marked `?` on the x axis.
+
+use crate::CalcError;
+use crate::ast::Expr;
+use crate::env::Environment;
+use crate::eval::evaluate_with;
+
+/// Characters a plot is drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    Ascii,
+    /// Box drawing characters and dots, for terminals that can show them
+    Unicode,
+}
+
+impl Charset {
+    /// Glyphs for successive curves, reused after the last
+    fn glyphs(self) -> &'static [char] {
+        match self {
+            Charset::Ascii => &['*', 'o', '#', '@', '%', '&'],
+            Charset::Unicode => &['•', '∘', '■', '◆', '▲', '×'],
+        }
+    }
+
+    /// Vertical line, horizontal line, their crossing, and the corner of
+    /// the frame
+    fn lines(self) -> (char, char, char, char) {
+        match self {
+            Charset::Ascii => ('|', '-', '+', '+'),
+            Charset::Unicode => ('│', '─', '┼', '└'),
+        }
+    }
+}
+
+/// Size of a plot in characters, and how it is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canvas {
+    /// Whole width, y labels included
+    pub width: usize,
+    /// Rows of the grid, not counting the x labels and legend
+    pub height: usize,
+    pub charset: Charset,
+}
+
+/// Width of the y labels and the space after them
+const GUTTER: usize = 11;
+
+/// Smallest grid `render` draws, whatever the canvas says
+const MIN_COLUMNS: usize = 10;
+const MIN_ROWS: usize = 5;
+
+impl Canvas {
+    /// Columns left for the curves once the labels are drawn
+    pub fn columns(&self) -> usize {
+        self.width.saturating_sub(GUTTER + 1).max(MIN_COLUMNS)
+    }
+
+    fn rows(&self) -> usize {
+        self.height.max(MIN_ROWS)
+    }
+}
+
+impl Default for Canvas {
+    /// A plot that fits an 80 by 24 terminal with a line to spare
+    fn default() -> Canvas {
+        Canvas {
+            width: 80,
+            height: 20,
+            charset: Charset::Ascii,
+        }
+    }
+}
+
+/// A sampled curve: one value per column, NaN where it has none
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    pub label: String,
+    pub values: Vec<f64>,
+    /// Columns whose value is not joined to the one before, because the
+    /// function is discontinuous between them
+    pub breaks: Vec<usize>,
+}
+
+/// Halvings `sample` spends looking for a discontinuity between two columns
+const BREAK_PROBES: usize = 30;
+
+/// `x` for column `col` of `columns` spread evenly from `lo` to `hi`
+fn column_x(lo: f64, hi: f64, col: usize, columns: usize) -> f64 {
+    if columns < 2 {
+        return lo;
+    }
+    // Interpolated rather than stepped, so `hi - lo` cannot overflow
+    let t = col as f64 / (columns - 1) as f64;
+    lo * (1.0 - t) + hi * t
+}
+
+/// Where `v` lies going from `a` at 0 to `b` at 1; halving first keeps
+/// ranges wider than the largest `f64` finite
+fn fraction(v: f64, a: f64, b: f64) -> f64 {
+    (v / 2.0 - a / 2.0) / (b / 2.0 - a / 2.0)
+}
+
+/// Whether `f` jumps somewhere between `a` and `b`
+///
+/// The half with the bigger change is kept each time; for a continuous
+/// function that change shrinks towards zero, while at a jump or a pole it
+/// does not.
+fn jumps(
+    f: &impl Fn(f64) -> f64,
+    (mut a, mut fa): (f64, f64),
+    (mut b, mut fb): (f64, f64),
+) -> bool {
+    let first = (fb - fa).abs();
+    if first == 0.0 {
+        return false;
+    }
+    for _ in 0..BREAK_PROBES {
+        let m = a + (b - a) / 2.0;
+        let fm = f(m);
+        if !fm.is_finite() {
+            return true;
+        }
+        if (fm - fa).abs() >= (fb - fm).abs() {
+            (b, fb) = (m, fm);
+        } else {
+            (a, fa) = (m, fm);
+        }
+    }
+    (fb - fa).abs() > first / 2.0
+}
+
+/// `expr` sampled for `var` at `columns` points from `lo` to `hi`
+///
+/// Points where evaluation fails become NaN, unless it fails everywhere, as
+/// it does for an undefined variable; then the first error is returned.
+pub fn sample(
+    expr: &Expr,
+    var: &str,
+    lo: f64,
+    hi: f64,
+    columns: usize,
+    env: &Environment,
+) -> Result<Curve, CalcError> {
+    let mut first_error = None;
+    let values: Vec<f64> = (0..columns)
+        .map(|col| {
+            let x = column_x(lo, hi, col, columns);
+            evaluate_with(expr, env, &[(var, x)]).unwrap_or_else(|e| {
+                first_error.get_or_insert(e);
+                f64::NAN
+            })
+        })
+        .collect();
+    if let Some(e) = first_error
+        && values.iter().all(|v| v.is_nan())
+    {
+        return Err(e);
+    }
+    let f = |x: f64| evaluate_with(expr, env, &[(var, x)]).unwrap_or(f64::NAN);
+    let breaks = (1..columns)
+        .filter(|&col| {
+            let (a, b) = (
+                column_x(lo, hi, col - 1, columns),
+                column_x(lo, hi, col, columns),
+            );
+            let (fa, fb) = (values[col - 1], values[col]);
+            fa.is_finite() && fb.is_finite() && jumps(&f, (a, fa), (b, fb))
+        })
+        .collect();
+    Ok(Curve {
+        label: expr.to_string(),
+        values,
+        breaks,
+    })
+}
+
+/// Sample every expression in `exprs` over `var` from `lo` to `hi` and
+/// render them together
+pub fn plot(
+    exprs: &[Expr],
+    var: &str,
+    lo: f64,
+    hi: f64,
+    env: &Environment,
+    canvas: &Canvas,
+) -> Result<String, CalcError> {
+    let curves = exprs
+        .iter()
+        .map(|expr| sample(expr, var, lo, hi, canvas.columns(), env))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(render(&curves, lo, hi, canvas))
+}
+
+/// y range that shows `values`, leaving out far outliers
+fn y_range(values: &[f64]) -> (f64, f64) {
+    let mut finite: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    if finite.is_empty() {
+        return (-1.0, 1.0);
+    }
+    finite.sort_by(f64::total_cmp);
+    let quantile = |q: f64| finite[((finite.len() - 1) as f64 * q).round() as usize];
+    let (min, max) = (finite[0], finite[finite.len() - 1]);
+    let (low, high) = (quantile(0.02), quantile(0.98));
+    // Keep everything unless a few values stretch the range many times over
+    let (low, high) = if max - min <= 10.0 * (high - low) {
+        (min, max)
+    } else {
+        (low, high)
+    };
+    if low == high {
+        let pad = if low == 0.0 { 1.0 } else { low.abs() / 2.0 };
+        return (low - pad, high + pad);
+    }
+    (low, high)
+}
+
+/// Short label for an axis value
+fn label(value: f64) -> String {
+    let magnitude = value.abs();
+    if value == 0.0 {
+        "0".to_string()
+    } else if !(1e-3..1e5).contains(&magnitude) {
+        format!("{:.2e}", value)
+    } else {
+        let text = format!("{:.3}", value);
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+/// Draw sampled `curves` over `x` from `lo` to `hi`
+///
+/// Each curve should have `canvas.columns()` values; extra ones are ignored
+/// and missing ones left blank.
+pub fn render(curves: &[Curve], lo: f64, hi: f64, canvas: &Canvas) -> String {
+    let (columns, rows) = (canvas.columns(), canvas.rows());
+    let (vertical, horizontal, cross, corner) = canvas.charset.lines();
+    let glyphs = canvas.charset.glyphs();
+    let all: Vec<f64> = curves
+        .iter()
+        .flat_map(|c| c.values.iter().copied())
+        .collect();
+    let (y_min, y_max) = y_range(&all);
+    // Row of `y`, which may be off the grid
+    let row_of = |y: f64| (fraction(y, y_max, y_min) * (rows - 1) as f64).round();
+
+    let mut grid = vec![vec![' '; columns]; rows];
+    let zero_row = (y_min..=y_max).contains(&0.0).then(|| row_of(0.0) as usize);
+    // The frame already marks zero in the first column
+    let zero_col = (lo.min(hi)..=lo.max(hi))
+        .contains(&0.0)
+        .then(|| (fraction(0.0, lo, hi) * (columns - 1) as f64).round())
+        .filter(|&col| col.is_finite() && col >= 1.0)
+        .map(|col| col as usize);
+    if let Some(r) = zero_row {
+        grid[r].fill(horizontal);
+    }
+    if let Some(c) = zero_col {
+        for (r, row) in grid.iter_mut().enumerate() {
+            row[c] = if Some(r) == zero_row { cross } else { vertical };
+        }
+    }
+
+    let mut gaps = vec![false; columns];
+    for (curve, &glyph) in curves.iter().zip(glyphs.iter().cycle()) {
+        let mut previous: Option<f64> = None;
+        for (col, &y) in curve.values.iter().take(columns).enumerate() {
+            if !y.is_finite() {
+                gaps[col] |= y.is_nan();
+                previous = None;
+                continue;
+            }
+            let row = row_of(y);
+            if let Some(p) = previous {
+                // Join to the previous point so steep parts stay connected,
+                // or mark the gap between them
+                let from = row_of(p);
+                let broken = curve.breaks.contains(&col);
+                let top = row.min(from).max(0.0) as usize;
+                let bottom = row.max(from).min(rows as f64 - 1.0) as usize;
+                for cells in grid.iter_mut().take(bottom + 1).skip(top) {
+                    let cell = &mut cells[col];
+                    if !broken {
+                        *cell = glyph;
+                    } else if *cell == ' ' {
+                        *cell = ':';
+                    }
+                }
+            }
+            if (0.0..rows as f64).contains(&row) {
+                grid[row as usize][col] = glyph;
+            }
+            previous = Some(y);
+        }
+    }
+
+    let mut out = String::new();
+    for (r, row) in grid.iter().enumerate() {
+        let y_label = if r == 0 {
+            label(y_max)
+        } else if r == rows - 1 {
+            label(y_min)
+        } else if Some(r) == zero_row {
+            label(0.0)
+        } else {
+            String::new()
+        };
+        let line: String = row.iter().collect();
+        out += &format!(
+            "{:>width$} {}{}\n",
+            y_label,
+            vertical,
+            line.trim_end(),
+            width = GUTTER - 1
+        );
+    }
+    let axis: String = gaps
+        .iter()
+        .map(|&gap| if gap { '?' } else { horizontal })
+        .collect();
+    out += &format!("{:>width$}{}{}\n", "", corner, axis, width = GUTTER);
+
+    // x labels at both ends, and at zero when it fits between them
+    let mut x_labels = vec![' '; columns + 1];
+    let mut put = |text: &str, start: usize| {
+        let fits = start + text.chars().count() <= x_labels.len()
+            && x_labels[start.saturating_sub(1)..start + text.chars().count()]
+                .iter()
+                .all(|&c| c == ' ');
+        if fits {
+            for (i, c) in text.chars().enumerate() {
+                x_labels[start + i] = c;
+            }
+        }
+    };
+    let (lo_label, hi_label) = (label(lo), label(hi));
+    put(&lo_label, 0);
+    put(&hi_label, (columns + 1).saturating_sub(hi_label.len()));
+    if let Some(c) = zero_col {
+        put("0", c + 1);
+    }
+    let x_labels: String = x_labels.iter().collect();
+    out += &format!("{:>width$}{}\n", "", x_labels.trim_end(), width = GUTTER);
+
+    for (curve, glyph) in curves.iter().zip(glyphs.iter().cycle()) {
+        out += &format!(
+            "{:>width$}{} {}\n",
+            "",
+            glyph,
+            curve.label,
+            width = GUTTER + 1
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn small() -> Canvas {
+        Canvas {
+            width: GUTTER + 1 + 21,
+            height: 7,
+            charset: Charset::Ascii,
+        }
+    }
+
+    fn plotted(formulas: &[&str], lo: f64, hi: f64) -> String {
+        let exprs: Vec<Expr> = formulas.iter().map(|f| parse(f).unwrap()).collect();
+        plot(&exprs, "x", lo, hi, &Environment::new(), &small()).unwrap()
+    }
+
+    #[test]
+    fn test_render_line() {
+        let expected = "
+        10 |          |        **
+           |          |     ****
+           |          | *****
+         0 |---------****--------
+           |      ****|
+           |  *****   |
+       -10 |***       |
+           +---------------------
+           -10        0        10
+            * x
+";
+        let out = plotted(&["x"], -10.0, 10.0);
+        assert_eq!(out, &expected[1..], "\n{}", out);
+        // The same shape over ranges wider than the largest f64
+        let grid = |out: &str| -> Vec<String> {
+            out.lines().take(8).map(|l| l[10..].to_string()).collect()
+        };
+        let wide = plotted(&["x"], -1e308, 1e308);
+        assert_eq!(grid(&wide), grid(&out), "\n{}", wide);
+        let curve = sample(
+            &parse("x").unwrap(),
+            "x",
+            1.0,
+            1e308,
+            5,
+            &Environment::new(),
+        );
+        assert_eq!(curve.unwrap().values, [1.0, 2.5e307, 5e307, 7.5e307, 1e308]);
+    }
+
+    #[test]
+    fn test_render_several_curves() {
+        let expected = "
+         4 |**        |         *
+           | **       |        *o
+           |  **      |       ooo
+           |   ***    |     ooo
+           |     ***  |  oooo
+         0 |-------ooooooo*------
+     -0.75 |oooooooo  |
+           +---------------------
+           -2         0         2
+            * x ^ 2
+            o 2 ^ x - 1
+";
+        let out = plotted(&["x^2", "2^x - 1"], -2.0, 2.0);
+        assert_eq!(out, &expected[1..], "\n{}", out);
+    }
+
+    #[test]
+    fn test_gaps_and_discontinuities() {
+        // sqrt fails left of zero
+        let out = plotted(&["sqrt(x)"], -4.0, 4.0);
+        let axis = out.lines().nth(7).unwrap();
+        assert_eq!(axis.trim(), "+??????????-----------");
+        // tan has a pole at pi / 2, floor a step at each whole number
+        let expected = "
+    14.101 |          *:
+           |          *:
+           |         **:
+         0 |**********-----******
+           |           :****
+           |           :*
+   -12.599 |           **
+           +---------------------
+           0                    3
+            * tan(x)
+";
+        assert_eq!(plotted(&["tan(x)"], 0.0, 3.0), &expected[1..]);
+        let curve = sample(
+            &parse("floor(x)").unwrap(),
+            "x",
+            0.0,
+            3.0,
+            7,
+            &Environment::new(),
+        );
+        assert_eq!(curve.unwrap().breaks, [2, 4, 6]);
+        let curve = sample(
+            &parse("x^3").unwrap(),
+            "x",
+            -9.0,
+            9.0,
+            7,
+            &Environment::new(),
+        );
+        assert!(curve.unwrap().breaks.is_empty());
+        // A formula that fails everywhere is an error
+        let expr = parse("x + y").unwrap();
+        let err = plot(&[expr], "x", 0.0, 1.0, &Environment::new(), &small()).unwrap_err();
+        assert!(matches!(err, CalcError::UndefinedVariable(..)));
+    }
+
+    #[test]
+    fn test_y_range_ignores_outliers() {
+        let mut values: Vec<f64> = (0..100).map(|i| i as f64 / 10.0).collect();
+        assert_eq!(y_range(&values), (0.0, 9.9));
+        values[50] = 1e9;
+        let (_, high) = y_range(&values);
+        assert!(high < 10.0);
+        assert_eq!(y_range(&[3.0, 3.0]), (1.5, 4.5));
+        assert_eq!(y_range(&[f64::NAN]), (-1.0, 1.0));
+    }
+}
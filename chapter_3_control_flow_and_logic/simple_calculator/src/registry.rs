@@ -0,0 +1,430 @@
+//! Operators and named functions the parser and evaluator know, and how to
+//! add more
+//!
+//! A `Registry` maps each operator symbol and its word aliases to a
+//! precedence, an associativity, an arity and an implementation, and maps
+//! function names to implementations. `Registry::default()` holds the built-in
+//! operators, which every number mode computes exactly. Custom operators and
+//! functions compute in `f64`, like the built-in functions, so exact modes
+//! report their results as inexact.
+//!
+//! An `Environment` carries a registry for evaluation; parse with the same one
+//! through `parse_with` or `parse_statement_with`.
+
+use crate::Op;
+use crate::functions::Arity;
+use std::fmt;
+use std::sync::{Arc, LazyLock};
+
+/// Implementation of a custom operator or function, given its operands
+pub type CustomFn = Arc<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+/// Which way a chain of operators of equal precedence groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    /// `a - b - c` is `(a - b) - c`
+    Left,
+    /// `a ^ b ^ c` is `a ^ (b ^ c)`
+    Right,
+}
+
+/// What an operator computes
+#[derive(Clone)]
+pub enum Implementation {
+    /// A built-in operation, which every number mode implements
+    Builtin(Op),
+    /// A function of the operands in floating point
+    Custom(CustomFn),
+}
+
+impl fmt::Debug for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Implementation::Builtin(op) => write!(f, "Builtin({:?})", op),
+            Implementation::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// An operator: infix between two operands, or prefix before one
+#[derive(Debug, Clone)]
+pub struct Operator {
+    pub symbol: String,
+    /// Other spellings, usually words like `mod` for `%`
+    pub aliases: Vec<String>,
+    /// Binding power, higher binding tighter; the built-in operators use
+    /// 1 to 7 (see `Op::precedence`)
+    pub precedence: u8,
+    pub assoc: Assoc,
+    /// 2 for an infix operator, 1 for a prefix one
+    pub arity: usize,
+    pub implementation: Implementation,
+}
+
+impl Operator {
+    /// Built-in `op`, spelled by its usual symbol
+    pub fn builtin(op: Op) -> Operator {
+        Operator {
+            symbol: op.symbol().to_string(),
+            aliases: Vec::new(),
+            precedence: op.precedence(),
+            assoc: if op.is_right_assoc() {
+                Assoc::Right
+            } else {
+                Assoc::Left
+            },
+            arity: if op == Op::BitNot { 1 } else { 2 },
+            implementation: Implementation::Builtin(op),
+        }
+    }
+
+    /// Infix operator `symbol` computing `f(a, b)`
+    pub fn infix(
+        symbol: impl Into<String>,
+        precedence: u8,
+        assoc: Assoc,
+        f: impl Fn(f64, f64) -> f64 + Send + Sync + 'static,
+    ) -> Operator {
+        Operator {
+            symbol: symbol.into(),
+            aliases: Vec::new(),
+            precedence,
+            assoc,
+            arity: 2,
+            implementation: Implementation::Custom(Arc::new(move |args| f(args[0], args[1]))),
+        }
+    }
+
+    /// Prefix operator `symbol` computing `f(a)`, binding as tightly as `~`
+    pub fn prefix(
+        symbol: impl Into<String>,
+        f: impl Fn(f64) -> f64 + Send + Sync + 'static,
+    ) -> Operator {
+        Operator {
+            symbol: symbol.into(),
+            aliases: Vec::new(),
+            precedence: Op::BitNot.precedence(),
+            assoc: Assoc::Right,
+            arity: 1,
+            implementation: Implementation::Custom(Arc::new(move |args| f(args[0]))),
+        }
+    }
+
+    /// The same operator, also spelled `alias`
+    pub fn alias(mut self, alias: impl Into<String>) -> Operator {
+        self.aliases.push(alias.into());
+        self
+    }
+
+    /// The symbol and every alias
+    pub fn spellings(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.symbol.as_str()).chain(self.aliases.iter().map(String::as_str))
+    }
+}
+
+/// A function callable by name, like the built-ins
+#[derive(Clone)]
+pub struct CustomFunction {
+    pub name: String,
+    pub arity: Arity,
+    pub eval: CustomFn,
+}
+
+impl fmt::Debug for CustomFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CustomFunction({}, {})", self.name, self.arity)
+    }
+}
+
+/// Operators and extra functions, looked up by spelling and name
+#[derive(Debug, Clone)]
+pub struct Registry {
+    /// Newest first, so a later registration shadows an earlier alias
+    operators: Vec<Operator>,
+    functions: Vec<CustomFunction>,
+}
+
+static BUILTIN: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+impl Default for Registry {
+    /// The built-in operators with their word aliases, and no extra functions
+    fn default() -> Registry {
+        let builtin = |op: Op, aliases: &[&str]| {
+            aliases
+                .iter()
+                .fold(Operator::builtin(op), |op, alias| op.alias(*alias))
+        };
+        Registry {
+            operators: vec![
+                builtin(Op::Add, &["add"]),
+                builtin(Op::Sub, &["sub"]),
+                builtin(Op::Mul, &["mul"]),
+                builtin(Op::Div, &["div"]),
+                builtin(Op::IntDiv, &[]),
+                builtin(Op::Rem, &["mod"]),
+                builtin(Op::Pow, &["pow"]),
+                builtin(Op::BitAnd, &[]),
+                builtin(Op::BitOr, &[]),
+                builtin(Op::BitXor, &["xor"]),
+                builtin(Op::Shl, &[]),
+                builtin(Op::Shr, &[]),
+                builtin(Op::BitNot, &[]),
+            ],
+            functions: Vec::new(),
+        }
+    }
+}
+
+/// Whether `spelling` lexes as one name rather than as punctuation
+fn is_word(spelling: &str) -> bool {
+    let mut chars = spelling.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_alphabetic() || first == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Whether the lexer can read `spelling` as an operator: a name, or
+/// punctuation other than brackets and commas
+fn is_spelling(spelling: &str) -> bool {
+    is_word(spelling)
+        || (!spelling.is_empty()
+            && spelling.chars().all(|c| {
+                !(c.is_alphanumeric()
+                    || c.is_whitespace()
+                    || matches!(c, '_' | '.' | '(' | ')' | ','))
+            }))
+}
+
+impl Registry {
+    /// The default registry, shared by `parse` and the other functions that
+    /// take none
+    pub fn builtin() -> &'static Registry {
+        &BUILTIN
+    }
+
+    /// Add `op`, replacing any operator with the same symbol; where aliases
+    /// clash, the newest registration wins
+    ///
+    /// # Panics
+    ///
+    /// If a spelling is neither a name nor punctuation, or the arity is not 1
+    /// or 2.
+    pub fn register_operator(&mut self, op: Operator) {
+        for spelling in op.spellings() {
+            assert!(is_spelling(spelling), "cannot lex operator {:?}", spelling);
+        }
+        assert!(
+            matches!(op.arity, 1 | 2),
+            "operator {} takes 1 or 2 operands, not {}",
+            op.symbol,
+            op.arity
+        );
+        self.operators.retain(|old| old.symbol != op.symbol);
+        self.operators.insert(0, op);
+    }
+
+    /// Add function `name`, replacing any registered under that name; it
+    /// hides a built-in of the same name but not a user-defined function
+    pub fn register_function(
+        &mut self,
+        name: impl Into<String>,
+        arity: Arity,
+        f: impl Fn(&[f64]) -> f64 + Send + Sync + 'static,
+    ) {
+        let name = name.into();
+        self.functions.retain(|old| old.name != name);
+        self.functions.insert(
+            0,
+            CustomFunction {
+                name,
+                arity,
+                eval: Arc::new(f),
+            },
+        );
+    }
+
+    /// Operator spelled `spelling`, by symbol or alias
+    pub fn operator(&self, spelling: &str) -> Option<&Operator> {
+        self.operators
+            .iter()
+            .find(|op| op.spellings().any(|s| s == spelling))
+    }
+
+    /// The operator built-in `op` is registered as
+    pub fn builtin_operator(&self, op: Op) -> Option<&Operator> {
+        self.operators
+            .iter()
+            .find(|o| matches!(o.implementation, Implementation::Builtin(b) if b == op))
+    }
+
+    pub fn operators(&self) -> impl Iterator<Item = &Operator> {
+        self.operators.iter()
+    }
+
+    pub fn function(&self, name: &str) -> Option<&CustomFunction> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &CustomFunction> {
+        self.functions.iter()
+    }
+
+    /// Longest punctuation spelling of an operator at the start of `text`,
+    /// and the operator
+    pub(crate) fn match_symbol(&self, text: &str) -> Option<(&str, &Operator)> {
+        self.operators
+            .iter()
+            .flat_map(|op| op.spellings().map(move |s| (s, op)))
+            .filter(|(s, _)| !is_word(s) && text.starts_with(s))
+            .max_by_key(|(s, _)| s.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CalcError, Environment, Rational, Rpn, evaluate, execute, parse, parse_statement_with,
+        parse_with,
+    };
+
+    #[test]
+    fn test_default_registry() {
+        let registry = Registry::default();
+        let rem = registry.operator("mod").unwrap();
+        assert_eq!(rem.symbol, "%");
+        assert!(matches!(
+            rem.implementation,
+            Implementation::Builtin(Op::Rem)
+        ));
+        assert_eq!(registry.operator("^").unwrap().assoc, Assoc::Right);
+        assert_eq!(registry.operator("~").unwrap().arity, 1);
+        let (spelling, op) = registry.match_symbol("^^ 3").unwrap();
+        assert_eq!((spelling, op.symbol.as_str()), ("^^", "^^"));
+        assert!(registry.match_symbol("< 2").is_none());
+        assert!(registry.match_symbol("add").is_none());
+    }
+
+    #[test]
+    fn test_registering_replaces() {
+        let mut registry = Registry::default();
+        registry.register_operator(Operator::infix("%", 6, Assoc::Left, |a, b| a * b / 100.0));
+        let percent = registry.operator("%").unwrap();
+        assert!(matches!(percent.implementation, Implementation::Custom(_)));
+        // `mod` went with the operator it named
+        assert!(registry.operator("mod").is_none());
+        registry.register_operator(Operator::infix("**", 7, Assoc::Right, f64::powf).alias("pow"));
+        assert_eq!(registry.operator("pow").unwrap().symbol, "**");
+        assert_eq!(registry.match_symbol("**2").unwrap().0, "**");
+    }
+
+    /// Float environment with `**` for powers, `<>` for the average of two
+    /// values, prefix `√`, and `clamp` and `lerp`
+    fn custom_env() -> Environment {
+        let mut env = Environment::new();
+        let registry = env.registry_mut();
+        registry.register_operator(Operator::infix("**", 7, Assoc::Right, f64::powf));
+        registry.register_operator(
+            Operator::infix("<>", 5, Assoc::Left, |a, b| (a + b) / 2.0).alias("avg"),
+        );
+        registry.register_operator(Operator::prefix("√", f64::sqrt));
+        registry.register_function("clamp", Arity::Exact(3), |args| {
+            args[0].clamp(args[1], args[2])
+        });
+        registry.register_function("lerp", Arity::Exact(3), |args| {
+            args[0] + (args[1] - args[0]) * args[2]
+        });
+        env
+    }
+
+    fn eval_in(input: &str, env: &mut Environment) -> Result<f64, CalcError> {
+        let stmt = parse_statement_with(input, env.registry())?;
+        Ok(execute(&stmt, env)?.unwrap())
+    }
+
+    #[test]
+    fn test_custom_operators() {
+        let mut env = custom_env();
+        let eval = |input: &str| eval_in(input, &mut env.clone()).unwrap();
+        assert_eq!(eval("2 ** 3 ** 2"), 512.0);
+        assert_eq!(eval("1 + 2 * 3 <> 5"), 6.0);
+        assert_eq!(eval("1 avg 2 avg 5"), 3.25);
+        assert_eq!(eval("<> 4 8"), 6.0);
+        assert_eq!(eval("√16 * 2"), 8.0);
+        assert_eq!(eval("-√(2 + 7)"), -3.0);
+        // Printed forms group custom operators and parse back the same
+        for input in [
+            "2 ** 3 ** 2",
+            "1 + 2 * 3 <> 5",
+            "√(x + 1)!",
+            "-(a avg b) ^ 2",
+        ] {
+            let expr = parse_with(input, env.registry()).unwrap();
+            let shown = expr.to_string();
+            assert_eq!(
+                parse_with(&shown, env.registry()).unwrap(),
+                expr,
+                "{}",
+                shown
+            );
+        }
+        assert_eq!(
+            parse_with("1 + 2 * 3 <> 5", env.registry())
+                .unwrap()
+                .to_string(),
+            "(1 + 2 * 3) <> 5"
+        );
+        // Only the registry that knows a symbol can lex it
+        assert!(matches!(parse("2 ** 3"), Err(CalcError::WrongArity(_))));
+        assert!(matches!(
+            evaluate(&parse_with("1 <> 2", env.registry()).unwrap(), &Environment::<f64>::new()),
+            Err(CalcError::UnknownOperator(symbol, _)) if symbol == "<>"
+        ));
+        // Names of operators cannot be assigned to
+        assert!(eval_in("avg = 3", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_custom_functions() {
+        let mut env = custom_env();
+        assert_eq!(eval_in("clamp(12, 0, 10)", &mut env).unwrap(), 10.0);
+        assert_eq!(eval_in("lerp(2, 4, 0.25) + 1", &mut env).unwrap(), 3.5);
+        assert!(matches!(
+            eval_in("clamp(1, 2)", &mut env),
+            Err(CalcError::FunctionArity(name, Arity::Exact(3), 2, _)) if name == "clamp"
+        ));
+        assert!(matches!(
+            eval_in("lrep(1, 2, 0)", &mut env),
+            Err(CalcError::UnknownFunction(_, Some(s), _)) if s == "lerp"
+        ));
+        assert!(matches!(
+            eval_in("√-1", &mut env),
+            Err(CalcError::Domain(what, _)) if what == "√(-1)"
+        ));
+        // A user function of the same name comes first
+        let define = parse_statement_with("clamp(x, lo, hi) = x", env.registry()).unwrap();
+        execute(&define, &mut env).unwrap();
+        assert_eq!(eval_in("clamp(12, 0, 10)", &mut env).unwrap(), 12.0);
+        // Exact modes cannot trust a float result, but keep the registry
+        let mut exact = custom_env().convert::<Rational>();
+        let expr = parse_with("lerp(0, 1, 1/3)", exact.registry()).unwrap();
+        assert!(matches!(
+            evaluate(&expr, &exact),
+            Err(CalcError::Inexact(what, ..)) if what == "lerp(0, 1, 1/3)"
+        ));
+        let mut rpn = Rpn::new();
+        rpn.run("2 3 ** 10 avg", &mut exact)
+            .expect_err("inexact in rational mode");
+        let mut env = custom_env();
+        let mut rpn = Rpn::new();
+        rpn.run("2 3 ** 10 avg √ 5 0 3 clamp", &mut env).unwrap();
+        assert_eq!(rpn.values(), [3.0, 3.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot lex operator")]
+    fn test_unlexable_symbol() {
+        Registry::default().register_operator(Operator::prefix("a b", f64::abs));
+    }
+}
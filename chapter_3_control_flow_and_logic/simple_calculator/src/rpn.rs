@@ -0,0 +1,367 @@
+//! Reverse Polish notation: numbers push onto a stack that persists between
+//! lines, and operators and functions pop their operands and push the result
+//!
+//! `3 4 + 2 *` is `(3 + 4) * 2`. Besides the operators (`+`, `add`, `mod`, ...,
+//! and any in the environment's registry), `!` and `~`, a line can use functions, which pop as many values as they take
+//! (`min` and `max` take two), variables and constants, which push their value,
+//! and these commands:
+//!
+//! - `dup` pushes a copy of the top value
+//! - `swap` exchanges the top two values
+//! - `drop` discards the top value
+//! - `clear` empties the stack
+//! - `roll` moves the top value to the bottom
+//! - `neg` negates the top value
+//! - `stack` asks for the whole stack to be shown
+//!
+//! A line either runs completely or, on an error, leaves the stack as it was.
+
+use crate::env::{ANS, Environment};
+use crate::eval::{EvalPolicy, call, custom, literal, operand_text};
+use crate::functions::{self, Arity};
+use crate::lexer::{Span, Token, TokenKind, tokenize_with};
+use crate::number::{self, Number};
+use crate::registry::{Implementation, Operator, Registry};
+use crate::{CalcError, Op};
+
+/// Tokens of one line of RPN input; brackets, commas and `=` have no meaning
+/// in RPN and are rejected
+pub fn parse_rpn(line: &str) -> Result<Vec<Token>, CalcError> {
+    parse_rpn_with(line, Registry::builtin())
+}
+
+/// Tokens of one line of RPN input, with the operators in `registry`
+pub fn parse_rpn_with(line: &str, registry: &Registry) -> Result<Vec<Token>, CalcError> {
+    let tokens = tokenize_with(line, registry)?;
+    let misplaced = tokens.iter().find(|t| {
+        matches!(
+            t.kind,
+            TokenKind::LParen | TokenKind::RParen | TokenKind::Comma | TokenKind::Assign
+        )
+    });
+    match misplaced {
+        Some(tok) => Err(CalcError::UnexpectedToken(tok.kind.to_string(), tok.span)),
+        None => Ok(tokens),
+    }
+}
+
+/// What a line asked to be shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RpnView {
+    /// The top of the stack, as for any line
+    Top,
+    /// The whole stack, after a `stack` command
+    Stack,
+}
+
+/// An RPN stack of values of number type `N`
+#[derive(Debug, Clone)]
+pub struct Rpn<N = f64> {
+    stack: Vec<N>,
+}
+
+impl<N: Number> Default for Rpn<N> {
+    fn default() -> Rpn<N> {
+        Rpn { stack: Vec::new() }
+    }
+}
+
+impl<N: Number> Rpn<N> {
+    pub fn new() -> Rpn<N> {
+        Rpn::default()
+    }
+
+    /// Values from the bottom of the stack to the top
+    pub fn values(&self) -> &[N] {
+        &self.stack
+    }
+
+    pub fn top(&self) -> Option<&N> {
+        self.stack.last()
+    }
+
+    pub fn push(&mut self, value: N) {
+        self.stack.push(value);
+    }
+
+    /// The same stack in number type `M`, converting like
+    /// `Environment::convert`; values `M` cannot hold are dropped
+    pub fn convert<M: Number>(&self) -> Rpn<M> {
+        Rpn {
+            stack: self.stack.iter().filter_map(number::convert).collect(),
+        }
+    }
+
+    /// Run one line of RPN input, binding the new top of the stack to `ans`
+    pub fn run(&mut self, line: &str, env: &mut Environment<N>) -> Result<RpnView, CalcError> {
+        self.run_tokens(&parse_rpn_with(line, env.registry())?, env)
+    }
+
+    /// Run the tokens of one line, as returned by `parse_rpn_with` for the
+    /// environment's registry
+    pub fn run_tokens(
+        &mut self,
+        tokens: &[Token],
+        env: &mut Environment<N>,
+    ) -> Result<RpnView, CalcError> {
+        let saved = self.stack.clone();
+        let mut view = RpnView::Top;
+        for tok in tokens {
+            match self.step(tok, env) {
+                Ok(Some(v)) => view = v,
+                Ok(None) => {}
+                Err(e) => {
+                    self.stack = saved;
+                    return Err(e);
+                }
+            }
+        }
+        if let Some(top) = self.top() {
+            env.set(ANS, top.clone());
+        }
+        Ok(view)
+    }
+
+    /// Apply one token
+    fn step(&mut self, tok: &Token, env: &Environment<N>) -> Result<Option<RpnView>, CalcError> {
+        let span = tok.span;
+        let at = |e: CalcError| e.at(span);
+        let policy = env.policy();
+        match &tok.kind {
+            TokenKind::Number(lit) => {
+                let value = literal(lit, env.context())
+                    .and_then(|v| policy.check(v, [], || lit.text.clone()))
+                    .map_err(at)?;
+                self.stack.push(value);
+            }
+            TokenKind::Op(Op::BitNot) => {
+                let [x] = self.pop("~", span)?;
+                self.stack.push(x.bit_not().map_err(at)?);
+            }
+            TokenKind::Op(op) => self.binary(*op, policy, span)?,
+            TokenKind::Operator(symbol) => self.operator(symbol, env, span)?,
+            TokenKind::Bang => {
+                let [x] = self.pop("!", span)?;
+                let value = x
+                    .factorial()
+                    .and_then(|v| policy.check(v, [&x], || format!("{}!", operand_text(&x))))
+                    .map_err(at)?;
+                self.stack.push(value);
+            }
+            TokenKind::Ident(word) => match word.as_str() {
+                "dup" => {
+                    let [x] = self.pop("dup", span)?;
+                    self.stack.extend([x.clone(), x]);
+                }
+                "swap" => {
+                    let [a, b] = self.pop("swap", span)?;
+                    self.stack.extend([b, a]);
+                }
+                "drop" => {
+                    self.pop::<1>("drop", span)?;
+                }
+                "clear" => self.stack.clear(),
+                "roll" => {
+                    if let Some(top) = self.stack.pop() {
+                        self.stack.insert(0, top);
+                    }
+                }
+                "neg" => {
+                    let [x] = self.pop("neg", span)?;
+                    let value = x
+                        .negate()
+                        .and_then(|v| policy.check(v, [&x], || format!("neg {}", x)))
+                        .map_err(at)?;
+                    self.stack.push(value);
+                }
+                "stack" => return Ok(Some(RpnView::Stack)),
+                _ => match env.registry().operator(word) {
+                    Some(op) => self.apply(op, env, span)?,
+                    None => self.word(word, env, span)?,
+                },
+            },
+            other => return Err(CalcError::UnexpectedToken(other.to_string(), span)),
+        }
+        Ok(None)
+    }
+
+    /// Apply registered operator `op`, spelled by a word
+    fn apply(&mut self, op: &Operator, env: &Environment<N>, span: Span) -> Result<(), CalcError> {
+        match op.implementation {
+            Implementation::Builtin(Op::BitNot) => {
+                let [x] = self.pop("~", span)?;
+                self.stack.push(x.bit_not().map_err(|e| e.at(span))?);
+                Ok(())
+            }
+            Implementation::Builtin(b) => self.binary(b, env.policy(), span),
+            Implementation::Custom(_) => self.operator(&op.symbol, env, span),
+        }
+    }
+
+    /// Pop as many values as custom operator `symbol` takes and push its result
+    fn operator(
+        &mut self,
+        symbol: &str,
+        env: &Environment<N>,
+        span: Span,
+    ) -> Result<(), CalcError> {
+        let Some(op) = env.registry().operator(symbol) else {
+            return Err(CalcError::UnknownOperator(symbol.to_string(), span));
+        };
+        let Implementation::Custom(f) = &op.implementation else {
+            return self.apply(op, env, span);
+        };
+        self.check_depth(symbol, op.arity, span)?;
+        let args = self.stack.split_off(self.stack.len() - op.arity);
+        let what = || match &args[..] {
+            [a, b] => format!("{} {} {}", operand_text(a), symbol, operand_text(b)),
+            _ => format!("{} {}", symbol, operand_text(&args[0])),
+        };
+        let value = custom(f, &args, env.policy(), what).map_err(|e| e.at(span))?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Pop `b`, then `a`, and push `a op b`
+    fn binary(&mut self, op: Op, policy: EvalPolicy, span: Span) -> Result<(), CalcError> {
+        let [a, b] = self.pop(op.symbol(), span)?;
+        self.stack
+            .push(policy.apply(op, &a, &b).map_err(|e| e.at(span))?);
+        Ok(())
+    }
+
+    /// A function call, which pops its arguments, or a name, which pushes its value
+    fn word(&mut self, name: &str, env: &Environment<N>, span: Span) -> Result<(), CalcError> {
+        let arity = env
+            .registry()
+            .function(name)
+            .map(|f| f.arity)
+            .or_else(|| functions::builtin(name).map(|f| f.arity));
+        let count = match (env.function(name), arity) {
+            (Some(f), _) => f.params.len(),
+            (None, Some(arity)) => match arity {
+                Arity::Exact(n) => n,
+                Arity::AtLeast(n) => n.max(2),
+            },
+            (None, None) => {
+                let value = env.lookup(name, span)?;
+                if env.policy() == EvalPolicy::Strict && !value.is_finite() {
+                    return Err(CalcError::NotANumber(name.to_string(), span));
+                }
+                self.stack.push(value);
+                return Ok(());
+            }
+        };
+        self.check_depth(name, count, span)?;
+        let args = self.stack.split_off(self.stack.len() - count);
+        self.stack.push(call(name, &args, env, span)?);
+        Ok(())
+    }
+
+    fn check_depth(&self, name: &str, count: usize, span: Span) -> Result<(), CalcError> {
+        if self.stack.len() < count {
+            return Err(CalcError::StackUnderflow(
+                name.to_string(),
+                count,
+                self.stack.len(),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Pop the top `K` values, deepest first
+    fn pop<const K: usize>(&mut self, name: &str, span: Span) -> Result<[N; K], CalcError> {
+        self.check_depth(name, K, span)?;
+        let values = self.stack.split_off(self.stack.len() - K);
+        Ok(values
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("split_off returned {} values", K)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::execute;
+    use crate::parse_statement;
+
+    fn stack(rpn: &Rpn) -> Vec<f64> {
+        rpn.values().to_vec()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut env = Environment::new();
+        let mut rpn = Rpn::new();
+        rpn.run("3 4 + 2 *", &mut env).unwrap();
+        assert_eq!(stack(&rpn), vec![14.0]);
+        // The stack persists between lines, and words work like symbols
+        rpn.run("5 sub 2 ^", &mut env).unwrap();
+        assert_eq!(stack(&rpn), vec![81.0]);
+        rpn.run("7 2 // 3!", &mut env).unwrap();
+        assert_eq!(stack(&rpn), vec![81.0, 3.0, 6.0]);
+        assert_eq!(env.get(ANS), Some(&6.0));
+    }
+
+    #[test]
+    fn test_stack_commands() {
+        let mut env = Environment::new();
+        let mut rpn = Rpn::new();
+        rpn.run("1 2 3 dup", &mut env).unwrap();
+        assert_eq!(stack(&rpn), vec![1.0, 2.0, 3.0, 3.0]);
+        rpn.run("drop swap", &mut env).unwrap();
+        assert_eq!(stack(&rpn), vec![1.0, 3.0, 2.0]);
+        rpn.run("roll neg", &mut env).unwrap();
+        assert_eq!(stack(&rpn), vec![2.0, 1.0, -3.0]);
+        assert_eq!(rpn.run("stack", &mut env).unwrap(), RpnView::Stack);
+        rpn.run("clear", &mut env).unwrap();
+        assert!(rpn.values().is_empty());
+    }
+
+    #[test]
+    fn test_functions_and_names() {
+        let mut env = Environment::new();
+        execute(
+            &parse_statement("hyp(a, b) = sqrt(a^2 + b^2)").unwrap(),
+            &mut env,
+        )
+        .unwrap();
+        env.set("x", 5.0);
+        let mut rpn = Rpn::new();
+        rpn.run("3 4 hyp x max 16 sqrt 2 1 log", &mut env).unwrap();
+        assert_eq!(stack(&rpn), vec![5.0, 4.0, 0.0]);
+        rpn.run("clear pi", &mut env).unwrap();
+        assert_eq!(stack(&rpn), vec![std::f64::consts::PI]);
+    }
+
+    #[test]
+    fn test_underflow_leaves_stack_alone() {
+        let mut env = Environment::new();
+        let mut rpn = Rpn::new();
+        rpn.run("1 2", &mut env).unwrap();
+        let err = rpn.run("+ *", &mut env).unwrap_err();
+        assert!(matches!(
+            &err,
+            CalcError::StackUnderflow(name, 2, 1, span) if name == "*" && *span == Span::new(2, 3)
+        ));
+        assert_eq!(
+            err.to_string(),
+            "stack underflow: * needs 2 values, the stack has 1"
+        );
+        assert_eq!(stack(&rpn), vec![1.0, 2.0]);
+        assert!(matches!(
+            rpn.run("clear sqrt", &mut env),
+            Err(CalcError::StackUnderflow(_, 1, 0, _))
+        ));
+        assert!(matches!(
+            rpn.run("1 0 /", &mut env),
+            Err(CalcError::DivisionByZero(span)) if span == Span::new(4, 5)
+        ));
+        assert!(matches!(
+            rpn.run("(1)", &mut env),
+            Err(CalcError::UnexpectedToken(..))
+        ));
+        assert_eq!(stack(&rpn), vec![1.0, 2.0]);
+    }
+}
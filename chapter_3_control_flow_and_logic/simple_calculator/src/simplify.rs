@@ -0,0 +1,439 @@
+//! Constant folding and algebraic simplification of expression trees
+//!
+//! `simplify` rewrites a tree bottom-up into an equal one that is usually
+//! shorter. Anything that may overflow or be undefined for some finite
+//! input, like `0 * ln(x)` or `sqrt(x) - sqrt(x)`, is kept so that it still
+//! yields NaN or infinity where the original would. Under
+//! `EvalPolicy::Strict` variables can only hold finite numbers, so `0 * x`
+//! and `x - x` become `0`; under `EvalPolicy::Ieee` a variable may be
+//! infinite or NaN, and those are kept too. Constants are folded only
+//! when the result is finite and has a short exact decimal form, so `1 / 0`
+//! is left for evaluation to report and `ln(2)` or `0.1 + 0.2` stay as written
+//! rather than turning into 16 digits of rounding.
+//!
+//! Calls are folded as the built-in functions of the same name, and named
+//! constants like `pi` are left alone, since a session may redefine either.
+
+use crate::ast::{Expr, ExprKind};
+use crate::{Environment, EvalPolicy, Op, evaluate};
+
+/// Simplified copy of `expr`, equal to it for every input `policy` allows
+pub fn simplify(expr: &Expr, policy: EvalPolicy) -> Expr {
+    let simplify = |expr: &Expr| simplify(expr, policy);
+    let node = match &expr.kind {
+        ExprKind::Number(_) | ExprKind::Var(_) => return expr.clone(),
+        ExprKind::Unary(Op::Add, operand) => return simplify(operand),
+        ExprKind::Unary(op, operand) => Expr::unary(*op, simplify(operand)),
+        ExprKind::Binary(op, lhs, rhs) => Expr::binary(*op, simplify(lhs), simplify(rhs)),
+        ExprKind::Factorial(operand) => Expr::factorial(simplify(operand)),
+        ExprKind::Call(name, args) => Expr::call(name.clone(), args.iter().map(simplify).collect()),
+        ExprKind::Operator(symbol, args) => Expr::new(ExprKind::Operator(
+            symbol.clone(),
+            args.iter().map(simplify).collect(),
+        )),
+    };
+    if let Some(value) = fold(&node) {
+        return number(value);
+    }
+    match node.kind {
+        ExprKind::Unary(Op::Sub, operand) => negate(*operand),
+        ExprKind::Binary(Op::Add | Op::Sub, ..) => collect_sum(&node, policy).unwrap_or(node),
+        ExprKind::Binary(Op::Mul, ..) => collect_product(&node, policy).unwrap_or(node),
+        ExprKind::Binary(Op::Div, lhs, rhs) if constant(&rhs) == Some(1.0) => *lhs,
+        ExprKind::Binary(Op::Pow, base, exp) => match (constant(&base), constant(&exp)) {
+            // `x ^ 0` and `1 ^ x` are 1 only where the other side evaluates at
+            // all: `(1 / x) ^ 0` still fails at 0
+            (_, Some(0.0)) if is_total(&base, policy) => number(1.0),
+            (Some(1.0), _) if is_total(&exp, policy) => number(1.0),
+            (_, Some(1.0)) => *base,
+            _ => Expr::binary(Op::Pow, *base, *exp),
+        },
+        _ => node,
+    }
+}
+
+/// Value of `expr` if every leaf is a number literal and the result is short
+fn fold(expr: &Expr) -> Option<f64> {
+    let leaves_constant = match &expr.kind {
+        ExprKind::Unary(_, operand) | ExprKind::Factorial(operand) => constant(operand).is_some(),
+        ExprKind::Binary(_, lhs, rhs) => constant(lhs).is_some() && constant(rhs).is_some(),
+        ExprKind::Call(_, args) => args.iter().all(|arg| constant(arg).is_some()),
+        // Custom operators need the registry they were parsed with
+        ExprKind::Number(_) | ExprKind::Var(_) | ExprKind::Operator(..) => false,
+    };
+    if !leaves_constant {
+        return None;
+    }
+    evaluate(expr, &Environment::<f64>::new())
+        .ok()
+        .filter(|&value| is_short(value))
+}
+
+/// Whether `value` is finite and prints in at most 15 significant digits,
+/// which rounded results of irrational or repeating values never do
+fn is_short(value: f64) -> bool {
+    let scientific = format!("{:e}", value.abs());
+    let mantissa = scientific.split('e').next().unwrap_or_default();
+    value.is_finite() && mantissa.chars().filter(char::is_ascii_digit).count() <= 15
+}
+
+/// Value of a real literal, or of a negated one
+fn constant(expr: &Expr) -> Option<f64> {
+    match &expr.kind {
+        ExprKind::Number(lit) if !lit.imaginary => Some(lit.value),
+        ExprKind::Unary(Op::Sub, operand) => constant(operand).map(|value| -value),
+        _ => None,
+    }
+}
+
+/// Literal for `value`, written as a negation when negative so that it
+/// prints correctly as the base of a power
+fn number(value: f64) -> Expr {
+    if value < 0.0 {
+        Expr::unary(Op::Sub, Expr::number(-value))
+    } else {
+        // `-0` and `0` are equal; print the plain one
+        Expr::number(value.abs())
+    }
+}
+
+/// `-expr`, cancelling a double negation
+fn negate(expr: Expr) -> Expr {
+    match expr.kind {
+        ExprKind::Unary(Op::Sub, operand) => *operand,
+        kind => Expr::unary(Op::Sub, Expr::new(kind)),
+    }
+}
+
+/// Whether `expr` is finite for every input `policy` allows: built from
+/// literals and, under `Strict`, variables, with signs, `+`, `-`, `*` and
+/// whole powers. Overflow is ignored.
+fn is_total(expr: &Expr, policy: EvalPolicy) -> bool {
+    match &expr.kind {
+        ExprKind::Number(lit) => !lit.imaginary,
+        ExprKind::Var(_) => policy == EvalPolicy::Strict,
+        ExprKind::Unary(_, operand) => is_total(operand, policy),
+        ExprKind::Binary(Op::Add | Op::Sub | Op::Mul, lhs, rhs) => {
+            is_total(lhs, policy) && is_total(rhs, policy)
+        }
+        ExprKind::Binary(Op::Pow, base, exp) => {
+            is_total(base, policy) && constant(exp).is_some_and(is_whole_power)
+        }
+        _ => false,
+    }
+}
+
+fn is_whole_power(exp: f64) -> bool {
+    exp >= 0.0 && exp.fract() == 0.0
+}
+
+/// One term of a sum: `coefficient * part`, or a bare number without a part
+struct Term {
+    coefficient: f64,
+    part: Option<Expr>,
+}
+
+/// Terms of a sum, each with its sign folded into the coefficient
+fn sum_terms(expr: &Expr, sign: f64, terms: &mut Vec<Term>) {
+    match &expr.kind {
+        ExprKind::Binary(op @ (Op::Add | Op::Sub), lhs, rhs) => {
+            sum_terms(lhs, sign, terms);
+            sum_terms(rhs, if *op == Op::Sub { -sign } else { sign }, terms);
+        }
+        ExprKind::Unary(Op::Sub, operand) => sum_terms(operand, -sign, terms),
+        _ => terms.push(match constant(expr) {
+            Some(value) => Term {
+                coefficient: sign * value,
+                part: None,
+            },
+            None => {
+                let (coefficient, part) = split_coefficient(expr);
+                Term {
+                    coefficient: sign * coefficient,
+                    part: Some(part),
+                }
+            }
+        }),
+    }
+}
+
+/// `2 * x * y` as `(2, x * y)`; anything without a leading literal has
+/// coefficient 1
+fn split_coefficient(expr: &Expr) -> (f64, Expr) {
+    leading_coefficient(expr).unwrap_or_else(|| (1.0, expr.clone()))
+}
+
+fn leading_coefficient(expr: &Expr) -> Option<(f64, Expr)> {
+    let ExprKind::Binary(Op::Mul, lhs, rhs) = &expr.kind else {
+        return None;
+    };
+    match constant(lhs) {
+        Some(coefficient) => Some((coefficient, (**rhs).clone())),
+        None => leading_coefficient(lhs)
+            .map(|(coefficient, rest)| (coefficient, Expr::binary(Op::Mul, rest, (**rhs).clone()))),
+    }
+}
+
+/// The sum `expr` with like terms combined and numbers added up, constant
+/// last; `None` if a coefficient does not stay finite
+fn collect_sum(expr: &Expr, policy: EvalPolicy) -> Option<Expr> {
+    let mut terms = Vec::new();
+    sum_terms(expr, 1.0, &mut terms);
+
+    let mut total = 0.0;
+    let mut constants = Vec::new();
+    // Each distinct part with its combined coefficient and original terms
+    let mut groups: Vec<(Expr, f64, Vec<f64>)> = Vec::new();
+    for term in terms {
+        match term.part {
+            None => {
+                total += term.coefficient;
+                constants.push(term.coefficient);
+            }
+            Some(part) => match groups.iter_mut().find(|(p, ..)| *p == part) {
+                Some((_, sum, originals)) => {
+                    *sum += term.coefficient;
+                    originals.push(term.coefficient);
+                }
+                None => groups.push((part, term.coefficient, vec![term.coefficient])),
+            },
+        }
+    }
+
+    let mut collected = Vec::new();
+    for (part, sum, originals) in groups {
+        if !sum.is_finite() {
+            return None;
+        }
+        // `ln(x) - ln(x)` is NaN for x <= 0, and `0.1 * x + 0.2 * x` would
+        // print as 0.30000000000000004 * x, so those terms stay
+        if sum == 0.0 && is_total(&part, policy) {
+            continue;
+        } else if sum != 0.0 && is_short(sum) {
+            collected.push((sum, Some(part)));
+        } else {
+            collected.extend(originals.into_iter().map(|c| (c, Some(part.clone()))));
+        }
+    }
+    if !total.is_finite() {
+        return None;
+    }
+    if !is_short(total) {
+        collected.extend(constants.into_iter().map(|c| (c, None)));
+    } else if total != 0.0 || collected.is_empty() {
+        collected.push((total, None));
+    }
+
+    let mut sum: Option<Expr> = None;
+    for (coefficient, part) in collected {
+        let magnitude = term(coefficient.abs(), part);
+        sum = Some(match sum {
+            None if coefficient < 0.0 => negate(magnitude),
+            None => magnitude,
+            Some(acc) if coefficient < 0.0 => Expr::binary(Op::Sub, acc, magnitude),
+            Some(acc) => Expr::binary(Op::Add, acc, magnitude),
+        });
+    }
+    sum
+}
+
+/// `coefficient * part`, dropping a coefficient of 1
+fn term(coefficient: f64, part: Option<Expr>) -> Expr {
+    match part {
+        None => number(coefficient),
+        Some(part) if coefficient == 1.0 => part,
+        Some(part) => scaled(coefficient, part),
+    }
+}
+
+/// `coefficient * part` with the coefficient as the first factor of a
+/// product, so `2 * x * y` rather than `2 * (x * y)`
+fn scaled(coefficient: f64, part: Expr) -> Expr {
+    match part.kind {
+        ExprKind::Binary(Op::Mul, lhs, rhs) => {
+            Expr::binary(Op::Mul, scaled(coefficient, *lhs), *rhs)
+        }
+        kind => Expr::binary(Op::Mul, number(coefficient), Expr::new(kind)),
+    }
+}
+
+/// Factors of a product as `(base, exponent)`, with every number and sign
+/// multiplied into `coefficient`
+fn product_factors(expr: &Expr, coefficient: &mut f64, factors: &mut Vec<(Expr, Option<f64>)>) {
+    if let Some(value) = constant(expr) {
+        *coefficient *= value;
+        return;
+    }
+    match &expr.kind {
+        ExprKind::Binary(Op::Mul, lhs, rhs) => {
+            product_factors(lhs, coefficient, factors);
+            product_factors(rhs, coefficient, factors);
+        }
+        ExprKind::Unary(Op::Sub, operand) => {
+            *coefficient = -*coefficient;
+            product_factors(operand, coefficient, factors);
+        }
+        ExprKind::Binary(Op::Pow, base, exp) => match constant(exp) {
+            Some(exp) if is_whole_power(exp) => factors.push(((**base).clone(), Some(exp))),
+            _ => factors.push((expr.clone(), None)),
+        },
+        _ => factors.push((expr.clone(), Some(1.0))),
+    }
+}
+
+/// The product `expr` with its numbers multiplied into a leading
+/// coefficient and repeated factors merged into whole powers; `None` if the
+/// coefficient is not short
+fn collect_product(expr: &Expr, policy: EvalPolicy) -> Option<Expr> {
+    let mut coefficient = 1.0;
+    let mut factors = Vec::new();
+    product_factors(expr, &mut coefficient, &mut factors);
+    if !is_short(coefficient) {
+        return None;
+    }
+    if coefficient == 0.0 && factors.iter().all(|(base, _)| is_total(base, policy)) {
+        return Some(number(0.0));
+    }
+
+    // Only whole powers merge: `x^0.5 * x^0.5` is NaN, not x, for x < 0
+    let mut merged: Vec<(Expr, Option<f64>)> = Vec::new();
+    for (base, exp) in factors {
+        let existing = exp.and_then(|_| merged.iter_mut().find(|(b, e)| e.is_some() && *b == base));
+        match existing {
+            Some((_, total)) => *total = Some(total.unwrap_or(0.0) + exp.unwrap_or(0.0)),
+            None => merged.push((base, exp)),
+        }
+    }
+
+    let product = merged
+        .into_iter()
+        .map(|(base, exp)| match exp {
+            Some(1.0) | None => base,
+            Some(exp) => Expr::binary(Op::Pow, base, number(exp)),
+        })
+        .reduce(|acc, factor| Expr::binary(Op::Mul, acc, factor));
+    Some(match product {
+        None => number(coefficient),
+        Some(product) if coefficient == -1.0 => negate(product),
+        Some(product) => term(coefficient, Some(product)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn simplified(input: &str) -> String {
+        simplify(&parse(input).unwrap(), EvalPolicy::Strict).to_string()
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_eq!(simplified("2 + 3 * 4"), "14");
+        assert_eq!(simplified("x * (2 ^ 3 - sqrt(16))"), "4 * x");
+        assert_eq!(simplified("-(3 - 5)"), "2");
+        assert_eq!(simplified("(3 - 5) ^ 2"), "4");
+        assert_eq!(simplified("4! + x"), "x + 24");
+        // Errors and overflow are left for evaluation
+        assert_eq!(simplified("1 / 0"), "1 / 0");
+        assert_eq!(simplified("10 ^ 400"), "10 ^ 400");
+        // Rounded results keep their exact form
+        assert_eq!(simplified("x * ln(2) * 2"), "2 * x * ln(2)");
+        assert_eq!(simplified("0.1 + 0.2"), "0.1 + 0.2");
+        assert_eq!(simplified("0.1 + 0.2 + 1 / 4"), "0.55");
+        assert_eq!(simplified("0.1*x + 0.2*x"), "0.1 * x + 0.2 * x");
+        // Constants can be redefined, and imaginary literals need complex mode
+        assert_eq!(simplified("pi * 1"), "pi");
+        assert_eq!(simplified("2i + 0"), "2i");
+    }
+
+    #[test]
+    fn test_identities() {
+        assert_eq!(simplified("x * 1"), "x");
+        assert_eq!(simplified("1 * x + 0"), "x");
+        assert_eq!(simplified("0 + x - 0"), "x");
+        assert_eq!(simplified("x / 1"), "x");
+        assert_eq!(simplified("x ^ 1"), "x");
+        assert_eq!(simplified("x ^ 0"), "1");
+        assert_eq!(simplified("1 ^ x"), "1");
+        assert_eq!(simplified("--x"), "x");
+        assert_eq!(simplified("-(-(x + y))"), "x + y");
+        assert_eq!(simplified("+x"), "x");
+        assert_eq!(simplified("x - x"), "0");
+        assert_eq!(simplified("0 * x"), "0");
+        assert_eq!(simplified("0 * (x + y * 2)"), "0");
+    }
+
+    #[test]
+    fn test_keeps_undefined_results() {
+        // Each of these is NaN or infinite for some finite x
+        assert_eq!(simplified("0 * ln(x)"), "0 * ln(x)");
+        assert_eq!(simplified("sqrt(x) - sqrt(x)"), "sqrt(x) - sqrt(x)");
+        assert_eq!(simplified("x / x"), "x / x");
+        assert_eq!(simplified("x ^ 0.5 * x ^ 0.5"), "x ^ 0.5 * x ^ 0.5");
+        assert_eq!(simplified("0 * (1 / x)"), "0 * (1 / x)");
+        assert_eq!(simplified("(1 / x) ^ 0"), "(1 / x) ^ 0");
+        assert_eq!(simplified("1 ^ ln(y)"), "1 ^ ln(y)");
+    }
+
+    #[test]
+    fn test_ieee_keeps_cancellations() {
+        // x may be infinite or NaN, making both NaN
+        let ieee = |input: &str| simplify(&parse(input).unwrap(), EvalPolicy::Ieee).to_string();
+        assert_eq!(ieee("0 * x"), "0 * x");
+        assert_eq!(ieee("x - x"), "x - x");
+        assert_eq!(ieee("1 + y - y + 2"), "y - y + 3");
+        assert_eq!(ieee("2*x + 3*x"), "5 * x");
+        assert_eq!(ieee("0 * 5 + x * 1"), "x");
+    }
+
+    #[test]
+    fn test_like_terms() {
+        assert_eq!(simplified("2*x + 3*x"), "5 * x");
+        assert_eq!(simplified("x + 1 + x - 3"), "2 * x - 2");
+        assert_eq!(simplified("a*b - 2*(a*b) + c"), "-(a * b) + c");
+        assert_eq!(simplified("1 + x + y - x"), "y + 1");
+        assert_eq!(simplified("sin(x) + 2*sin(x)"), "3 * sin(x)");
+        assert_eq!(simplified("x * 3 * x * 2"), "6 * x ^ 2");
+        assert_eq!(simplified("x^2 * y * x^3"), "x ^ 5 * y");
+        assert_eq!(simplified("-x * -y"), "x * y");
+        assert_eq!(simplified("2 * -x"), "-2 * x");
+    }
+
+    /// Simplifying never changes a value that was finite, and doing it twice
+    /// changes nothing more
+    #[test]
+    fn test_preserves_values() {
+        let inputs = [
+            "3*x^2 - x*x + 2*(x - 1) - 2*x",
+            "-(a - b) * (b - a) + 0 * a + a ^ 1 / 1",
+            "x*y*2 - y*x + 4 - 2^2",
+            "(x + 1) * (x + 1) * 3 - (x + 1) ^ 2",
+            "sqrt(x * x) + 0 - -x",
+            "5! / x - 120 / x + x ^ 0 - 1",
+        ];
+        let mut env = Environment::new();
+        for input in inputs {
+            let expr = parse(input).unwrap();
+            let once = simplify(&expr, EvalPolicy::Strict);
+            assert_eq!(simplify(&once, EvalPolicy::Strict), once, "{}", input);
+            for (a, b, x, y) in [(1.5, -2.0, 3.0, 0.5), (-4.0, 0.25, -1.75, 8.0)] {
+                env.set("a", a);
+                env.set("b", b);
+                env.set("x", x);
+                env.set("y", y);
+                let want = evaluate(&expr, &env).unwrap();
+                let got = evaluate(&once, &env).unwrap();
+                assert!(
+                    (want - got).abs() <= 1e-12 * want.abs().max(1.0),
+                    "{} = {}, got {} from {}",
+                    input,
+                    want,
+                    got,
+                    once
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Numeric root finding for equations in one variable
+//!
+//! `solve` looks for a root of `lhs = rhs` between two bounds. When the two
+//! sides compare differently at the bounds it bisects down to adjacent
+//! floats, which always converges; otherwise it falls back to Newton's
+//! method from the midpoint, using the derivative from `derive` or, for
+//! functions that have none, a central difference. `solve_all` scans the
+//! interval for sign changes and bisects each one.
+
+use crate::ast::Expr;
+use crate::derive::derive_in;
+use crate::env::Environment;
+use crate::eval::evaluate_with;
+use crate::lexer::{TokenKind, tokenize_with};
+use crate::parser::parse_expression_with;
+use crate::registry::Registry;
+use crate::{CalcError, Op};
+
+/// Newton steps taken before `solve` gives up
+pub const NEWTON_ITERATIONS: usize = 100;
+
+/// Bisection steps allowed; enough to narrow any pair of finite bounds to
+/// adjacent floats
+const BISECTIONS: usize = 2200;
+
+/// Number of pieces `solve_all` cuts its interval into when looking for
+/// sign changes
+pub const SCAN_STEPS: usize = 1000;
+
+/// Parse `lhs = rhs` as the expression `lhs - rhs`, whose roots solve it; an
+/// input without `=` is taken as `expr = 0`
+pub fn parse_equation(input: &str) -> Result<Expr, CalcError> {
+    parse_equation_with(input, Registry::builtin())
+}
+
+/// Parse an equation as `parse_equation` does, with the operators in `registry`
+pub fn parse_equation_with(input: &str, registry: &Registry) -> Result<Expr, CalcError> {
+    let tokens = tokenize_with(input, registry)?;
+    let mut sides = tokens.split(|tok| tok.kind == TokenKind::Assign);
+    let lhs = parse_expression_with(sides.next().unwrap_or_default(), registry)?;
+    let Some(rhs) = sides.next() else {
+        return Ok(lhs);
+    };
+    if let Some(extra) = tokens
+        .iter()
+        .filter(|tok| tok.kind == TokenKind::Assign)
+        .nth(1)
+    {
+        return Err(CalcError::UnexpectedToken(
+            extra.kind.to_string(),
+            extra.span,
+        ));
+    }
+    if rhs.is_empty() {
+        let end = tokens.last().map(|tok| tok.span.end).unwrap_or_default();
+        return Err(CalcError::UnexpectedEnd(crate::Span::new(end, end)));
+    }
+    let rhs = parse_expression_with(rhs, registry)?;
+    let span = lhs.span.to(rhs.span);
+    Ok(Expr::binary(Op::Sub, lhs, rhs).with_span(span))
+}
+
+/// `expr` as a function of `var`, with every other name looked up in `env`
+struct Function<'a> {
+    expr: &'a Expr,
+    var: &'a str,
+    env: &'a Environment,
+}
+
+impl Function<'_> {
+    fn at(&self, x: f64) -> Result<f64, CalcError> {
+        evaluate_with(self.expr, self.env, &[(self.var, x)])
+    }
+
+    fn no_convergence(&self, iterations: usize) -> CalcError {
+        CalcError::NoConvergence("solve".to_string(), iterations, self.expr.span)
+    }
+
+    /// Root between `a` and `b`, where `f(a)` and `f(b)` have opposite signs
+    fn bisect(
+        &self,
+        (mut a, mut fa): (f64, f64),
+        (mut b, mut fb): (f64, f64),
+    ) -> Result<f64, CalcError> {
+        for iteration in 1..=BISECTIONS {
+            let mid = a + (b - a) / 2.0;
+            if mid == a || mid == b {
+                return Ok(if fa.abs() <= fb.abs() { a } else { b });
+            }
+            let fm = self.at(mid)?;
+            if fm == 0.0 {
+                return Ok(mid);
+            }
+            if fm.is_nan() {
+                return Err(self.no_convergence(iteration));
+            }
+            if fm.is_sign_negative() == fa.is_sign_negative() {
+                (a, fa) = (mid, fm);
+            } else {
+                (b, fb) = (mid, fm);
+            }
+        }
+        Err(self.no_convergence(BISECTIONS))
+    }
+
+    /// Root near `x` by Newton's method, which must stay within `lo..=hi`
+    fn newton(&self, mut x: f64, lo: f64, hi: f64) -> Result<f64, CalcError> {
+        let derivative = derive_in(self.expr, self.var, self.env.angle()).ok();
+        for iteration in 1..=NEWTON_ITERATIONS {
+            let fx = self.at(x)?;
+            if fx == 0.0 {
+                return Ok(x);
+            }
+            let slope = match &derivative {
+                Some(d) => evaluate_with(d, self.env, &[(self.var, x)])?,
+                None => {
+                    let h = f64::EPSILON.cbrt() * x.abs().max(1.0);
+                    (self.at(x + h)? - self.at(x - h)?) / (2.0 * h)
+                }
+            };
+            let step = fx / slope;
+            if !step.is_finite() {
+                return Err(self.no_convergence(iteration));
+            }
+            x -= step;
+            if !(lo..=hi).contains(&x) {
+                return Err(self.no_convergence(iteration));
+            }
+            if step.abs() <= 1e-12 * x.abs().max(1.0) {
+                return Ok(x);
+            }
+        }
+        Err(self.no_convergence(NEWTON_ITERATIONS))
+    }
+}
+
+/// A root of `expr = 0` for `var` between `lo` and `hi`
+///
+/// Other names in `expr` are looked up in `env`, where `var` is shadowed.
+/// Fails with `NoConvergence` when Newton's method, needed if the bounds do
+/// not bracket a sign change, runs away or leaves the interval.
+pub fn solve(
+    expr: &Expr,
+    var: &str,
+    lo: f64,
+    hi: f64,
+    env: &Environment,
+) -> Result<f64, CalcError> {
+    let (lo, hi) = (lo.min(hi), lo.max(hi));
+    let f = Function { expr, var, env };
+    let (flo, fhi) = (f.at(lo)?, f.at(hi)?);
+    if flo == 0.0 {
+        return Ok(lo);
+    }
+    if fhi == 0.0 {
+        return Ok(hi);
+    }
+    if flo.is_sign_negative() != fhi.is_sign_negative() && !flo.is_nan() && !fhi.is_nan() {
+        return f.bisect((lo, flo), (hi, fhi));
+    }
+    f.newton(lo + (hi - lo) / 2.0, lo, hi)
+}
+
+/// Every root of `expr = 0` for `var` between `lo` and `hi` where the sign
+/// changes, in increasing order
+///
+/// The interval is scanned in `SCAN_STEPS` pieces, so roots closer together
+/// than one piece, and roots where the sign does not change (like `x^2 = 0`),
+/// can be missed. Points where evaluation fails are skipped, and sign
+/// changes across a pole, like `tan(x)` at pi/2, are not reported.
+pub fn solve_all(
+    expr: &Expr,
+    var: &str,
+    lo: f64,
+    hi: f64,
+    env: &Environment,
+) -> Result<Vec<f64>, CalcError> {
+    let (lo, hi) = (lo.min(hi), lo.max(hi));
+    let f = Function { expr, var, env };
+    let mut first_error = None;
+    let mut samples = Vec::with_capacity(SCAN_STEPS + 1);
+    for i in 0..=SCAN_STEPS {
+        // Interpolated rather than stepped, so `hi - lo` cannot overflow and
+        // the last sample is exactly `hi`
+        let t = i as f64 / SCAN_STEPS as f64;
+        let x = lo * (1.0 - t) + hi * t;
+        match f.at(x) {
+            Ok(fx) if fx.is_finite() => samples.push((i, x, fx)),
+            Ok(_) => {}
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if samples.is_empty() {
+        return match first_error {
+            Some(e) => Err(e),
+            None => Ok(Vec::new()),
+        };
+    }
+
+    let mut roots: Vec<f64> = Vec::new();
+    for pair in samples.windows(2) {
+        let ((i, a, fa), (j, b, fb)) = (pair[0], pair[1]);
+        if fa == 0.0 {
+            roots.push(a);
+        } else if j == i + 1 && fb != 0.0 && fa.is_sign_negative() != fb.is_sign_negative() {
+            // Near a pole the function grows instead of vanishing, or fails
+            let root = f.bisect((a, fa), (b, fb));
+            if let Ok(root) = root
+                && f.at(root)
+                    .is_ok_and(|fr| fr.abs() <= fa.abs().min(fb.abs()))
+            {
+                roots.push(root);
+            }
+        }
+    }
+    if let Some(&(_, x, 0.0)) = samples.last() {
+        roots.push(x);
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solved(equation: &str, lo: f64, hi: f64) -> Result<f64, CalcError> {
+        solve(&parse_equation(equation)?, "x", lo, hi, &Environment::new())
+    }
+
+    #[test]
+    fn test_parse_equation() {
+        assert_eq!(
+            parse_equation("x^3 - 2*x = 5").unwrap().to_string(),
+            "x ^ 3 - 2 * x - 5"
+        );
+        assert_eq!(parse_equation("x - 1").unwrap().to_string(), "x - 1");
+        assert!(matches!(
+            parse_equation("x = 1 = 2"),
+            Err(CalcError::UnexpectedToken(_, span)) if span == crate::Span::new(6, 7)
+        ));
+        assert!(matches!(
+            parse_equation("x ="),
+            Err(CalcError::UnexpectedEnd(_))
+        ));
+    }
+
+    #[test]
+    fn test_bisection() {
+        let root = solved("x^3 - 2*x = 5", 0.0, 3.0).unwrap();
+        assert!((root.powi(3) - 2.0 * root - 5.0).abs() < 1e-12);
+        assert!((root - 2.0945514815423265).abs() < 1e-15);
+        assert_eq!(solved("cos(x) = x", 0.0, 1.0).unwrap(), 0.7390851332151607);
+        assert_eq!(solved("x = 2", 2.0, 5.0).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn test_newton_fallback() {
+        // No sign change: x^2 only touches zero
+        let root = solved("x^2 - 2*x + 1", -3.0, 4.0).unwrap();
+        assert!((root - 1.0).abs() < 1e-6);
+        let err = solved("x^2 + 1", -1.0, 3.0).unwrap_err();
+        assert!(matches!(err, CalcError::NoConvergence(_, n, _) if n > 0));
+        assert!(err.to_string().starts_with("solve did not converge after"));
+        // Errors from the function itself come through
+        assert!(matches!(
+            solved("x + y", 0.0, 1.0),
+            Err(CalcError::UndefinedVariable(..))
+        ));
+    }
+
+    #[test]
+    fn test_solve_all() {
+        let env = Environment::new();
+        let expr = parse_equation("sin(x) = 0").unwrap();
+        let roots = solve_all(&expr, "x", -1.0, 10.0, &env).unwrap();
+        assert_eq!(roots.len(), 4);
+        for (root, k) in roots.iter().zip(0..) {
+            assert!((root - k as f64 * std::f64::consts::PI).abs() < 1e-12);
+        }
+        // Poles of tan are sign changes but not roots
+        let expr = parse_equation("tan(x)").unwrap();
+        assert_eq!(solve_all(&expr, "x", 1.0, 4.0, &env).unwrap().len(), 1);
+        // Points where 1/x fails are skipped
+        let expr = parse_equation("1 / x = 2").unwrap();
+        assert_eq!(solve_all(&expr, "x", -1.0, 1.0, &env).unwrap(), [0.5]);
+        // Ranges wider than the largest f64
+        let expr = parse_equation("x = 1e307").unwrap();
+        assert_eq!(solve_all(&expr, "x", -1e308, 1e308, &env).unwrap(), [1e307]);
+        let expr = parse_equation("x").unwrap();
+        assert_eq!(solve_all(&expr, "x", -1e308, 1e308, &env).unwrap(), [0.0]);
+    }
+}
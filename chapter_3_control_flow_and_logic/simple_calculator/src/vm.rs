@@ -0,0 +1,433 @@
+//! Bytecode compiler and stack machine for evaluating one formula many times
+//!
+//! `compile` parses a formula once and flattens it into postfix instructions.
+//! Variables become numbered slots, so each run only fills in their values
+//! instead of walking the tree and looking names up. Results match
+//! `evaluate` in f64 mode under the same `EvalPolicy`, errors included.
+
+use crate::ast::{Expr, ExprKind};
+use crate::env::Environment;
+use crate::error::closest_match;
+use crate::eval::{EvalPolicy, literal, operand_text};
+use crate::functions::{BUILTINS, CONSTANTS, IMAGINARY_UNIT, constant};
+use crate::number::{Number, call_text};
+use crate::{CalcError, Op, Span, parse};
+
+/// One step of a compiled program
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Instr {
+    /// Push a literal
+    Const(f64),
+    /// Push a literal too large for an `f64`, `Program::huge[n]` in the source,
+    /// as infinity; strict evaluation rejects it
+    Huge(usize),
+    /// Push the value of variable slot `n`
+    Load(usize),
+    Neg,
+    /// Pop `b`, then `a`, and push `a op b`
+    Binary(Op),
+    Factorial,
+    /// Pop `argc` arguments and push built-in `BUILTINS[index]` applied to them
+    Call(usize, usize),
+}
+
+/// A formula compiled to bytecode
+#[derive(Debug, Clone)]
+pub struct Program {
+    code: Vec<Instr>,
+    /// Span each instruction's errors point at
+    spans: Vec<Span>,
+    /// Variable names by slot, and where each is first used
+    vars: Vec<(String, Span)>,
+    /// Literals that overflow to infinity, as written
+    huge: Vec<String>,
+    /// Deepest the stack gets while running
+    max_stack: usize,
+    policy: EvalPolicy,
+}
+
+/// Parse and compile `input`
+pub fn compile(input: &str) -> Result<Program, CalcError> {
+    compile_expr(&parse(input)?)
+}
+
+/// Compile an already parsed expression
+///
+/// Only built-in functions can be called, since a program does not carry an
+/// environment; anything else is reported here rather than on every run.
+pub fn compile_expr(expr: &Expr) -> Result<Program, CalcError> {
+    let mut program = Program {
+        code: Vec::new(),
+        spans: Vec::new(),
+        vars: Vec::new(),
+        huge: Vec::new(),
+        max_stack: 0,
+        policy: EvalPolicy::default(),
+    };
+    program.emit_expr(expr, 0)?;
+    Ok(program)
+}
+
+impl Program {
+    /// Variable names in slot order, the order `run` takes their values in
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.vars.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Number of instructions
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// Whether NaN and infinite results are errors, strict by default as in
+    /// a new `Environment`
+    pub fn policy(&self) -> EvalPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: EvalPolicy) {
+        self.policy = policy;
+    }
+
+    /// Run with the variables given by name
+    ///
+    /// Names not in `bindings` fall back to the built-in constants, like a
+    /// fresh `Environment` would.
+    pub fn eval(&self, bindings: &[(&str, f64)]) -> Result<f64, CalcError> {
+        let values = self
+            .vars
+            .iter()
+            .map(|(name, span)| resolve(name, bindings, *span))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.run(&values)
+    }
+
+    /// Run with `values` holding each variable's value in `variables()` order
+    ///
+    /// This is the fast path for evaluating the same program many times.
+    ///
+    /// # Panics
+    ///
+    /// If `values` has fewer entries than the program has variables.
+    pub fn run(&self, values: &[f64]) -> Result<f64, CalcError> {
+        assert!(
+            values.len() >= self.vars.len(),
+            "program needs {} variable values, got {}",
+            self.vars.len(),
+            values.len()
+        );
+        let strict = self.policy == EvalPolicy::Strict;
+        let mut stack: Vec<f64> = Vec::with_capacity(self.max_stack);
+        for (pc, instr) in self.code.iter().enumerate() {
+            match *instr {
+                Instr::Const(n) => stack.push(n),
+                Instr::Huge(index) if strict => {
+                    let text = self.huge[index].clone();
+                    return Err(CalcError::Overflow(text, f64::MODE, self.spans[pc]));
+                }
+                Instr::Huge(_) => stack.push(f64::INFINITY),
+                Instr::Load(slot) if strict && !values[slot].is_finite() => {
+                    let name = self.vars[slot].0.clone();
+                    return Err(CalcError::NotANumber(name, self.spans[pc]));
+                }
+                Instr::Load(slot) => stack.push(values[slot]),
+                Instr::Neg => {
+                    let top = stack.last_mut().expect("compiled stack depth");
+                    *top = -*top;
+                }
+                Instr::Binary(op) => {
+                    let b = stack.pop().expect("compiled stack depth");
+                    let a = stack.last_mut().expect("compiled stack depth");
+                    *a = self
+                        .policy
+                        .apply(op, a, &b)
+                        .map_err(|e| e.at(self.spans[pc]))?;
+                }
+                Instr::Factorial => {
+                    let top = stack.last_mut().expect("compiled stack depth");
+                    let what = || format!("{}!", operand_text(top));
+                    *top = top
+                        .factorial()
+                        .and_then(|value| self.policy.check(value, [&*top], what))
+                        .map_err(|e| e.at(self.spans[pc]))?;
+                }
+                Instr::Call(index, argc) => {
+                    let at = stack.len() - argc;
+                    let (f, args) = (&BUILTINS[index], &stack[at..]);
+                    let value = f64::call(f, args)
+                        .and_then(|value| {
+                            self.policy.check(value, args, || call_text(f.name, args))
+                        })
+                        .map_err(|e| e.at(self.spans[pc]))?;
+                    stack.truncate(at);
+                    stack.push(value);
+                }
+            }
+        }
+        Ok(stack.pop().expect("a program leaves one value"))
+    }
+
+    fn push(&mut self, instr: Instr, span: Span) {
+        self.code.push(instr);
+        self.spans.push(span);
+    }
+
+    /// Slot for variable `name`, adding one on its first use
+    fn slot(&mut self, name: &str, span: Span) -> usize {
+        match self.vars.iter().position(|(var, _)| var == name) {
+            Some(slot) => slot,
+            None => {
+                self.vars.push((name.to_string(), span));
+                self.vars.len() - 1
+            }
+        }
+    }
+
+    /// Emit code leaving the value of `expr` on a stack that already holds
+    /// `depth` values
+    fn emit_expr(&mut self, expr: &Expr, depth: usize) -> Result<(), CalcError> {
+        self.max_stack = self.max_stack.max(depth + 1);
+        match &expr.kind {
+            ExprKind::Number(lit) => {
+                let value = literal::<f64>(lit, &()).map_err(|e| e.at(expr.span))?;
+                if value.is_finite() {
+                    self.push(Instr::Const(value), expr.span);
+                } else {
+                    self.huge.push(lit.text.clone());
+                    self.push(Instr::Huge(self.huge.len() - 1), expr.span);
+                }
+            }
+            ExprKind::Var(name) => {
+                let slot = self.slot(name, expr.span);
+                self.push(Instr::Load(slot), expr.span);
+            }
+            ExprKind::Unary(op, operand) => {
+                self.emit_expr(operand, depth)?;
+                match op {
+                    Op::Add => {}
+                    Op::Sub => self.push(Instr::Neg, expr.span),
+                    // Floats have no bits to flip
+                    Op::BitNot => {
+                        return Err(CalcError::Unsupported(
+                            expr.to_string(),
+                            f64::MODE,
+                            expr.span,
+                        ));
+                    }
+                    _ => return Err(CalcError::WrongArity(expr.span)),
+                }
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                self.emit_expr(lhs, depth)?;
+                self.emit_expr(rhs, depth + 1)?;
+                self.push(Instr::Binary(*op), rhs.span);
+            }
+            ExprKind::Factorial(operand) => {
+                self.emit_expr(operand, depth)?;
+                self.push(Instr::Factorial, expr.span);
+            }
+            ExprKind::Call(name, args) => {
+                let Some(index) = BUILTINS.iter().position(|b| b.name == name) else {
+                    return Err(Environment::<f64>::new().unknown_function(name, expr.span));
+                };
+                if !BUILTINS[index].arity.accepts(args.len()) {
+                    return Err(CalcError::FunctionArity(
+                        name.to_string(),
+                        BUILTINS[index].arity,
+                        args.len(),
+                        expr.span,
+                    ));
+                }
+                for (i, arg) in args.iter().enumerate() {
+                    self.emit_expr(arg, depth + i)?;
+                }
+                self.push(Instr::Call(index, args.len()), expr.span);
+            }
+            ExprKind::Operator(symbol, _) => {
+                return Err(CalcError::UnknownOperator(symbol.clone(), expr.span));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Value of variable `name` from `bindings`, falling back to the constants
+fn resolve(name: &str, bindings: &[(&str, f64)], span: Span) -> Result<f64, CalcError> {
+    if let Some((_, value)) = bindings.iter().find(|(bound, _)| *bound == name) {
+        return Ok(*value);
+    }
+    if let Some(value) = constant(name) {
+        return Ok(value);
+    }
+    if name == IMAGINARY_UNIT {
+        return f64::imaginary(1.0).map_err(|e| e.at(span));
+    }
+    let names = bindings.iter().map(|(bound, _)| *bound);
+    let suggestion = closest_match(name, names.chain(CONSTANTS.iter().map(|(n, _)| *n)));
+    Err(CalcError::UndefinedVariable(
+        name.to_string(),
+        suggestion.map(str::to_string),
+        span,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_compile_and_eval() {
+        let prog = compile("a*x^2+b").unwrap();
+        assert_eq!(prog.variables().collect::<Vec<_>>(), ["a", "x", "b"]);
+        assert_eq!(
+            prog.eval(&[("x", 3.0), ("a", 2.0), ("b", 1.0)]).unwrap(),
+            19.0
+        );
+        assert_eq!(prog.run(&[2.0, 4.0, -1.0]).unwrap(), 31.0);
+        let prog = compile("-max(x, 2, pi) + 3!").unwrap();
+        assert_eq!(prog.eval(&[("x", 10.0)]).unwrap(), -4.0);
+        // Bindings shadow constants
+        assert_eq!(compile("e").unwrap().eval(&[("e", 2.0)]).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = compile("a*x^2+b").unwrap().eval(&[("x", 3.0)]).unwrap_err();
+        assert!(
+            matches!(err, CalcError::UndefinedVariable(ref name, _, span)
+            if name == "a" && span == Span::new(0, 1))
+        );
+        let err = compile("1 + x / (x - 2)")
+            .unwrap()
+            .eval(&[("x", 2.0)])
+            .unwrap_err();
+        assert!(matches!(err, CalcError::DivisionByZero(span) if span == Span::new(8, 15)));
+        assert!(matches!(
+            compile("sqr(2)"),
+            Err(CalcError::UnknownFunction(_, Some(ref s), _)) if s == "sqrt"
+        ));
+        assert!(matches!(
+            compile("log(2)"),
+            Err(CalcError::FunctionArity(..))
+        ));
+        assert!(matches!(compile("2i"), Err(CalcError::Imaginary(_))));
+
+        let mut prog = compile("2 * 1e400").unwrap();
+        let err = prog.run(&[]).unwrap_err();
+        assert!(matches!(err, CalcError::Overflow(ref what, "float", span)
+            if what == "1e400" && span == Span::new(4, 9)));
+        prog.set_policy(EvalPolicy::Ieee);
+        assert_eq!(prog.run(&[]).unwrap(), f64::INFINITY);
+        let mut prog = compile("ln(x) / 0").unwrap();
+        assert!(matches!(prog.run(&[0.0]), Err(CalcError::Domain(..))));
+        prog.set_policy(EvalPolicy::Ieee);
+        assert!(prog.run(&[1.0]).unwrap().is_nan());
+        assert_eq!(prog.run(&[0.5]).unwrap(), f64::NEG_INFINITY);
+        let err = compile("x + 1").unwrap().run(&[f64::INFINITY]).unwrap_err();
+        assert!(matches!(err, CalcError::NotANumber(ref name, _) if name == "x"));
+    }
+
+    const FORMULAS: [&str; 6] = [
+        "a*x^2+b",
+        "sqrt(x^2 + 1) - x // 3",
+        "-x! % 7 + max(x, 1 / x, -x)",
+        "sin(x) * cos(2 * x) ^ 2 / (1 + abs(x))",
+        "log(2, abs(x) + 1) + ln(x) - e ^ -x",
+        "(x - 1) * (x + 1) - (x ^ 2 - 1) + round(x * 10) / 10",
+    ];
+
+    /// Environment binding the free variables of `FORMULAS` other than `x`
+    fn formula_env() -> Environment {
+        let mut env = Environment::new();
+        env.set("a", 1.5);
+        env.set("b", -0.25);
+        env
+    }
+
+    fn inputs() -> Vec<f64> {
+        (0..2000).map(|i| (i as f64 - 1000.0) / 37.0).collect()
+    }
+
+    /// Each formula's compiled program run on every input, binding `x`
+    fn run_all(prog: &Program, env: &Environment, xs: &[f64]) -> Vec<Result<f64, CalcError>> {
+        let slots: Vec<&str> = prog.variables().collect();
+        let mut values: Vec<f64> = slots
+            .iter()
+            .map(|n| env.lookup(n, Span::default()).unwrap_or_default())
+            .collect();
+        let x_slot = slots.iter().position(|&n| n == "x").unwrap();
+        xs.iter()
+            .map(|&x| {
+                values[x_slot] = x;
+                prog.run(&values)
+            })
+            .collect()
+    }
+
+    /// Both evaluators agree on every formula and input, NaN included
+    #[test]
+    fn test_matches_tree_evaluator() {
+        let mut env = formula_env();
+        let xs = inputs();
+        // Errors must match too, down to where they point
+        let same = |a: &Result<f64, CalcError>, b: &Result<f64, CalcError>| match (a, b) {
+            (Ok(a), Ok(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (Err(a), Err(b)) => a.to_string() == b.to_string() && a.span() == b.span(),
+            _ => false,
+        };
+        for formula in FORMULAS {
+            let expr = parse(formula).unwrap();
+            let prog = compile(formula).unwrap();
+            let vm = run_all(&prog, &env, &xs);
+            for (&x, v) in xs.iter().zip(&vm) {
+                env.set("x", x);
+                let t = evaluate(&expr, &env);
+                assert!(
+                    same(&t, v),
+                    "{} at x = {}: tree {:?}, vm {:?}",
+                    formula,
+                    x,
+                    t,
+                    v
+                );
+            }
+        }
+    }
+
+    /// The point of compiling: run with `cargo test --release -- --ignored`
+    #[test]
+    #[ignore = "timing benchmark; run in release mode"]
+    fn bench_vm_not_slower_than_tree() {
+        let mut env = formula_env();
+        let xs = inputs();
+        let (mut tree_time, mut vm_time) = (Duration::ZERO, Duration::ZERO);
+        for _ in 0..20 {
+            for formula in FORMULAS {
+                let expr = parse(formula).unwrap();
+                let start = Instant::now();
+                for &x in &xs {
+                    env.set("x", x);
+                    std::hint::black_box(evaluate(&expr, &env)).ok();
+                }
+                tree_time += start.elapsed();
+
+                let prog = compile(formula).unwrap();
+                let start = Instant::now();
+                std::hint::black_box(run_all(&prog, &env, &xs));
+                vm_time += start.elapsed();
+            }
+        }
+        assert!(
+            vm_time <= tree_time,
+            "vm took {:?}, tree evaluator {:?}",
+            vm_time,
+            tree_time
+        );
+    }
+}